@@ -1,52 +1,59 @@
-fn get_dep_symlink_path(name: &String, version: &Version) -> PathBuf {
-    let folder_name = if name.starts_with("@") {
-        name.replace("/", "+")
-    } else {
-        name.to_string()
-    };
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
 
+use tokio::task;
+
+const STORE_FOLDER: &str = ".mnpm";
+const DEPS_FOLDER: &str = "node_modules";
+
+fn get_virtual_store_folder_name(name: &str, version: &str) -> String {
+    format!("{}@{}", name.replace('/', "+"), version)
+}
+
+fn get_dep_symlink_path(name: &str, version: &str) -> PathBuf {
     Path::new("..")
         .join("..")
-        .join(format!("{}@{}", folder_name, version))
+        .join(get_virtual_store_folder_name(name, version))
+        .join(DEPS_FOLDER)
+        .join(name)
+}
+
+fn get_local_store_package_path(name: &str, version: &str) -> PathBuf {
+    Path::new(DEPS_FOLDER)
+        .join(STORE_FOLDER)
+        .join(get_virtual_store_folder_name(name, version))
         .join(DEPS_FOLDER)
         .join(name)
 }
 
 pub async fn symlink_dep(
-    dep_name: &String,
-    dep_version: &Version,
-    dest_name: &String,
-    dest_version: &Version,
+    dep_name: &str,
+    dep_version: &str,
+    dest_name: &str,
+    dest_version: &str,
 ) -> anyhow::Result<()> {
-    let original = get_dep_symlink_path(&dep_name, dep_version);
+    let original = get_dep_symlink_path(dep_name, dep_version);
 
     let link = get_local_store_package_path(dest_name, dest_version);
     let mut parent = link
         .parent()
         .expect("failed to get package folder")
         .to_path_buf();
-    if dest_name.starts_with("@") {
+    if dest_name.starts_with('@') {
         parent = parent
             .parent()
             .expect("failed to get package folder")
             .to_path_buf();
     }
 
-    parent = parent.join(&dep_name);
-
-    let name = dep_name.clone();
+    parent = parent.join(dep_name);
 
     task::spawn_blocking(
         move || match std::os::unix::fs::symlink(&original, &parent) {
             Err(error) if error.kind() == ErrorKind::AlreadyExists => Ok(()),
-            Err(error) => {
-                // TODO: check what pnpm does with @floating-ui/react-dom
-                // I bet they convert the / to something else like _
-
-                println!("{:?}, {:?}, {:?}, {:?}", error, original, parent, name);
-
-                return Err(error.into());
-            }
+            Err(error) => Err(error.into()),
             Ok(_) => Ok(()),
         },
     )
@@ -1,5 +1,5 @@
 use std::path::PathBuf;
-use clean_path::{clean, Clean};
+use clean_path::Clean;
 
 
 pub fn get_fixtures_root() -> PathBuf {
@@ -23,7 +23,6 @@ pub fn get_fixtures_path<T: AsRef<str>>(name: T) -> PathBuf {
 
 #[cfg(test)]
 mod tests {
-    use super::*;
     #[test]
     fn get_fix() {
 
@@ -0,0 +1,362 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use indexmap::IndexMap;
+
+use crate::{
+    config::DependencyScope, package_spec::PackageSpec, platform::Platform, reporter::ReporterKind,
+};
+
+/// A fast node package manager.
+#[derive(Debug, Parser)]
+#[command(name = "mnpm", version, about)]
+pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalOptions,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+/// Options accepted by every subcommand.
+#[derive(Debug, Args)]
+pub struct GlobalOptions {
+    /// Run as if mnpm was started in <DIR> instead of the current directory.
+    #[arg(long, short = 'C', global = true, value_name = "DIR")]
+    pub dir: Option<PathBuf>,
+
+    /// Base URL of the npm registry.
+    #[arg(long, global = true, value_name = "URL")]
+    pub registry: Option<reqwest::Url>,
+
+    /// Directory of the store shared by every project.
+    #[arg(long, global = true, value_name = "DIR")]
+    pub store_dir: Option<PathBuf>,
+
+    /// Maximum number of concurrent requests.
+    #[arg(long, global = true, value_name = "N")]
+    pub network_concurrency: Option<usize>,
+
+    /// How packages are imported from the store.
+    #[arg(long, global = true, value_parser = ["auto", "hardlink", "copy"])]
+    pub package_import_method: Option<String>,
+
+    /// Timeout of each request, in milliseconds.
+    #[arg(long, global = true, value_name = "MS")]
+    pub fetch_timeout: Option<u64>,
+
+    /// How versions are picked among the ones matching a range.
+    #[arg(long, global = true, value_parser = ["highest", "lowest-direct", "time-based"])]
+    pub resolution_mode: Option<String>,
+
+    /// Only install versions published before this date, such as `2023-01-31`.
+    #[arg(long, global = true, value_name = "DATE")]
+    pub before: Option<String>,
+
+    /// Keep going after a package fails and report every error at the end.
+    #[arg(long, global = true)]
+    pub no_bail: bool,
+
+    /// Skip `optionalDependencies`.
+    #[arg(long, global = true)]
+    pub no_optional: bool,
+
+    /// Install optional dependencies for this OS instead of the current one, such as `linux`.
+    #[arg(long, global = true, value_name = "OS")]
+    pub target_os: Option<String>,
+
+    /// Install optional dependencies for this CPU instead of the current one, such as `arm64`.
+    #[arg(long, global = true, value_name = "CPU")]
+    pub target_cpu: Option<String>,
+
+    /// Install optional dependencies for this libc instead of the current one, `glibc` or `musl`.
+    #[arg(long, global = true, value_name = "LIBC")]
+    pub target_libc: Option<String>,
+
+    /// How to report progress.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub reporter: ReporterKind,
+}
+
+impl GlobalOptions {
+    /// The settings given as flags, keyed like in `.npmrc`.
+    pub fn settings(&self) -> IndexMap<String, String> {
+        let settings = [
+            ("registry", self.registry.as_ref().map(ToString::to_string)),
+            (
+                "store-dir",
+                self.store_dir.as_ref().map(|dir| dir.display().to_string()),
+            ),
+            (
+                "network-concurrency",
+                self.network_concurrency.as_ref().map(ToString::to_string),
+            ),
+            ("package-import-method", self.package_import_method.clone()),
+            (
+                "fetch-timeout",
+                self.fetch_timeout.as_ref().map(ToString::to_string),
+            ),
+            ("resolution-mode", self.resolution_mode.clone()),
+            ("before", self.before.clone()),
+        ];
+
+        settings
+            .into_iter()
+            .filter_map(|(key, value)| Some((key.to_string(), value?)))
+            .collect()
+    }
+
+    /// The current platform, with the parts given as `--target-*` flags replaced.
+    pub fn platform(&self) -> Platform {
+        let current = Platform::current();
+        let os = self.target_os.clone().unwrap_or(current.os);
+        let libc = match &self.target_libc {
+            Some(libc) => Some(libc.clone()),
+            // the libc of the current platform does not apply to another OS.
+            None if self.target_os.is_some() => None,
+            None => current.libc,
+        };
+
+        Platform {
+            cpu: self.target_cpu.clone().unwrap_or(current.cpu),
+            libc: libc.or_else(|| (os == "linux").then(|| String::from("glibc"))),
+            os,
+        }
+    }
+}
+
+/// Which dependencies of package.json `install` installs.
+#[derive(Debug, Args)]
+pub struct ScopeOptions {
+    /// Only install `dependencies`.
+    #[arg(long, visible_alias = "production", conflicts_with = "dev")]
+    pub prod: bool,
+
+    /// Only install `devDependencies`.
+    #[arg(long)]
+    pub dev: bool,
+}
+
+impl ScopeOptions {
+    pub fn dependency_scope(&self) -> DependencyScope {
+        if self.prod {
+            DependencyScope::Production
+        } else if self.dev {
+            DependencyScope::Development
+        } else {
+            DependencyScope::All
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Install all dependencies listed in package.json.
+    #[command(visible_alias = "i")]
+    Install {
+        #[command(flatten)]
+        scope: ScopeOptions,
+    },
+
+    /// Install packages and save them to the dependencies of package.json.
+    Add {
+        /// Packages to add, such as `react`, `react@^18` or `@types/node@latest`.
+        #[arg(required = true, value_name = "PACKAGE")]
+        packages: Vec<PackageSpec>,
+    },
+
+    /// Remove packages from the dependencies of package.json.
+    #[command(visible_aliases = ["rm", "uninstall"])]
+    Remove {
+        #[arg(required = true, value_name = "PACKAGE")]
+        packages: Vec<String>,
+    },
+
+    /// Update dependencies to the newest version allowed by their range.
+    #[command(visible_alias = "up")]
+    Update {
+        /// Packages to update. Updates every dependency when omitted.
+        #[arg(value_name = "PACKAGE")]
+        packages: Vec<String>,
+
+        /// Ignore the range in package.json and update to the `latest` tag.
+        #[arg(long)]
+        latest: bool,
+    },
+
+    /// Reinstall the dependencies of package.json with as few versions of each package
+    /// as their ranges allow.
+    Dedupe {
+        /// Only check whether deduplicating would change the installed packages,
+        /// and fail if it would.
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Read and write settings.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the value of a setting.
+    Get { key: String },
+
+    /// Write a setting to an `.npmrc` file.
+    Set {
+        key: String,
+        value: String,
+
+        /// Write to the user `.npmrc` or to the project one.
+        #[arg(long, value_enum, default_value_t)]
+        location: ConfigLocation,
+    },
+
+    /// Print every setting and where its value comes from.
+    #[command(visible_alias = "ls")]
+    List,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ConfigLocation {
+    #[default]
+    User,
+    Project,
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn verify_cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn parses_global_flags_after_subcommand() {
+        let cli = Cli::try_parse_from([
+            "mnpm",
+            "add",
+            "react",
+            "--dir",
+            "app",
+            "--registry",
+            "http://localhost:4873",
+        ])
+        .unwrap();
+
+        assert_eq!(cli.global.dir, Some(PathBuf::from("app")));
+        assert_eq!(
+            cli.global.registry.map(String::from),
+            Some(String::from("http://localhost:4873/"))
+        );
+        assert!(matches!(cli.command, Command::Add { packages } if packages[0].name == "react"));
+    }
+
+    #[test]
+    fn rejects_unknown_subcommand() {
+        assert!(Cli::try_parse_from(["mnpm", "react"]).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_package_names() {
+        assert!(Cli::try_parse_from(["mnpm", "add", "React@^18"]).is_err());
+    }
+
+    #[test]
+    fn collects_setting_flags() {
+        let cli = Cli::try_parse_from([
+            "mnpm",
+            "install",
+            "--fetch-timeout",
+            "1000",
+            "--package-import-method",
+            "copy",
+        ])
+        .unwrap();
+
+        let settings: Vec<_> = cli.global.settings().into_iter().collect();
+        assert_eq!(
+            settings,
+            [
+                (String::from("package-import-method"), String::from("copy")),
+                (String::from("fetch-timeout"), String::from("1000")),
+            ]
+        );
+        assert!(
+            Cli::try_parse_from(["mnpm", "install", "--package-import-method", "move"]).is_err()
+        );
+    }
+
+    #[test]
+    fn selects_dependency_scope() {
+        let scope = |args: &[&str]| {
+            Cli::try_parse_from(["mnpm", "install"].iter().chain(args)).map(|cli| {
+                match cli.command {
+                    Command::Install { scope } => scope.dependency_scope(),
+                    command => panic!("unexpected command {command:?}"),
+                }
+            })
+        };
+
+        assert_eq!(scope(&[]).unwrap(), DependencyScope::All);
+        assert_eq!(scope(&["--prod"]).unwrap(), DependencyScope::Production);
+        assert_eq!(
+            scope(&["--production"]).unwrap(),
+            DependencyScope::Production
+        );
+        assert_eq!(scope(&["--dev"]).unwrap(), DependencyScope::Development);
+        assert!(scope(&["--prod", "--dev"]).is_err());
+    }
+
+    #[test]
+    fn scopes_only_install() {
+        // `add foo --dev` would otherwise skip the dependencies foo is added to.
+        for command in ["add", "remove", "update"] {
+            for flag in ["--dev", "--prod"] {
+                assert!(Cli::try_parse_from(["mnpm", command, "foo", flag]).is_err());
+            }
+        }
+        assert!(Cli::try_parse_from(["mnpm", "dedupe", "--prod"]).is_err());
+    }
+
+    #[test]
+    fn overrides_target_platform() {
+        let cli = Cli::try_parse_from(["mnpm", "install", "--target-os", "linux"]).unwrap();
+        let platform = cli.global.platform();
+        assert_eq!(platform.os, "linux");
+        assert_eq!(platform.cpu, Platform::current().cpu);
+        assert_eq!(platform.libc.as_deref(), Some("glibc"));
+
+        let cli = Cli::try_parse_from([
+            "mnpm",
+            "install",
+            "--target-os=linux",
+            "--target-cpu=arm64",
+            "--target-libc=musl",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.global.platform(),
+            Platform {
+                os: String::from("linux"),
+                cpu: String::from("arm64"),
+                libc: Some(String::from("musl")),
+            }
+        );
+
+        let cli = Cli::try_parse_from(["mnpm", "install", "--target-os", "darwin"]).unwrap();
+        assert_eq!(cli.global.platform().libc, None);
+    }
+
+    #[test]
+    fn add_requires_packages() {
+        assert!(Cli::try_parse_from(["mnpm", "add"]).is_err());
+    }
+}
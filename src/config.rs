@@ -1,4 +1,709 @@
+use std::{
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, Utc};
+use indexmap::IndexMap;
+use reqwest::{Certificate, Identity, NoProxy, Proxy, Url};
+use rustc_hash::FxHashMap as HashMap;
+use tokio::sync::Semaphore;
+
+use crate::{
+    npmrc::{get_user_npmrc_path, Credentials, Npmrc, NPMRC_FILE},
+    platform::Platform,
+    reporter::{Reporter, SilentReporter},
+};
+
+pub const NPM_REGISTRY_URL: &str = "https://registry.npmjs.org/";
+
+/// The settings mnpm understands, listed by `mnpm config list`.
+pub const SETTINGS: [&str; 14] = [
+    "registry",
+    "store-dir",
+    "network-concurrency",
+    "package-import-method",
+    "fetch-timeout",
+    "proxy",
+    "https-proxy",
+    "noproxy",
+    "cafile",
+    "strict-ssl",
+    "cert",
+    "key",
+    "resolution-mode",
+    "before",
+];
+
+/// Settings whose values are never printed.
+const PROTECTED_SUFFIXES: [&str; 4] = ["_authToken", "_auth", "_password", "key"];
+
+/// How packages are imported from the store into `node_modules`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageImportMethod {
+    /// Hardlink, or copy when the store is on another filesystem.
+    Auto,
+    Hardlink,
+    Copy,
+}
+
+impl FromStr for PackageImportMethod {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(Self::Auto),
+            "hardlink" => Ok(Self::Hardlink),
+            "copy" => Ok(Self::Copy),
+            _ => Err(String::from("expected auto, hardlink or copy")),
+        }
+    }
+}
+
+/// How versions are picked among the ones matching a range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResolutionMode {
+    /// The highest version of every range.
+    #[default]
+    Highest,
+    /// The lowest version of the ranges of package.json, the highest of the others.
+    LowestDirect,
+    /// The lowest version of the ranges of package.json, and for the others the highest
+    /// version published before the newest of those.
+    TimeBased,
+}
+
+impl FromStr for ResolutionMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "highest" => Ok(Self::Highest),
+            "lowest-direct" => Ok(Self::LowestDirect),
+            "time-based" => Ok(Self::TimeBased),
+            _ => Err(String::from(
+                "expected highest, lowest-direct or time-based",
+            )),
+        }
+    }
+}
+
+/// Parse the date of the `before` setting, a RFC 3339 date such as
+/// `2023-01-31T12:00:00Z` or a day such as `2023-01-31`, which is its midnight UTC.
+pub fn parse_date(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date.with_timezone(&Utc));
+    }
+
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|date| date.and_utc())
+        .ok_or_else(|| String::from("expected a date such as 2023-01-31 or 2023-01-31T12:00:00Z"))
+}
+
+/// Which dependencies of package.json are installed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DependencyScope {
+    #[default]
+    All,
+    /// Only `dependencies`.
+    Production,
+    /// Only `devDependencies`.
+    Development,
+}
+
+/// Where the value of a setting came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Cli,
+    Env(String),
+    Project(PathBuf),
+    User(PathBuf),
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cli => write!(f, "command line"),
+            Self::Env(var) => write!(f, "environment variable {var}"),
+            Self::Project(path) => write!(f, "project config {}", path.display()),
+            Self::User(path) => write!(f, "user config {}", path.display()),
+            Self::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// Every source of settings, from the highest to the lowest priority:
+/// command line flags, `MNPM_*` and `npm_config_*` environment variables,
+/// the project `.npmrc`, the user `.npmrc` and the built-in defaults.
+#[derive(Debug, Default, Clone)]
+pub struct ConfigLayers {
+    pub cli: IndexMap<String, String>,
+    pub env: HashMap<String, String>,
+    pub project: Npmrc,
+    pub project_path: Option<PathBuf>,
+    pub user: Npmrc,
+    pub user_path: Option<PathBuf>,
+}
+
+impl ConfigLayers {
+    /// Load the layers of the project in `dir`, with the `cli` flags on top.
+    pub fn load(cli: IndexMap<String, String>, dir: &Path) -> io::Result<Self> {
+        let project_path = dir.join(NPMRC_FILE);
+        let user_path = get_user_npmrc_path();
+
+        Ok(Self {
+            cli,
+            env: env::vars().collect(),
+            project: Npmrc::load(&project_path)?,
+            project_path: Some(project_path),
+            user: match &user_path {
+                Some(user_path) => Npmrc::load(user_path)?,
+                None => Npmrc::default(),
+            },
+            user_path,
+        })
+    }
+
+    /// The value of `key` and where it came from, without the defaults.
+    pub fn get(&self, key: &str) -> Option<(String, Source)> {
+        if let Some(value) = self.cli.get(key) {
+            return Some((value.clone(), Source::Cli));
+        }
+
+        for env_var in get_env_vars(key) {
+            if let Some(value) = self.env.get(&env_var).filter(|value| !value.is_empty()) {
+                return Some((value.clone(), Source::Env(env_var)));
+            }
+        }
+
+        let npmrc_path = |path: &Option<PathBuf>| path.clone().unwrap_or_default();
+        if let Some(value) = self.project.get(key) {
+            return Some((
+                value.to_string(),
+                Source::Project(npmrc_path(&self.project_path)),
+            ));
+        }
+        self.user
+            .get(key)
+            .map(|value| (value.to_string(), Source::User(npmrc_path(&self.user_path))))
+    }
+
+    /// The value of `key` and where it came from, falling back to the default.
+    pub fn get_or_default(&self, key: &str) -> Option<(String, Source)> {
+        self.get(key)
+            .or_else(|| self.get_default(key).map(|value| (value, Source::Default)))
+    }
+
+    fn get_default(&self, key: &str) -> Option<String> {
+        let value = match key {
+            "registry" => String::from(NPM_REGISTRY_URL),
+            "store-dir" => self.get_default_store_dir().display().to_string(),
+            "network-concurrency" => String::from("16"),
+            "package-import-method" => String::from("auto"),
+            "fetch-timeout" => String::from("60000"),
+            "strict-ssl" => String::from("true"),
+            "resolution-mode" => String::from("highest"),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    /// `$XDG_DATA_HOME/mnpm/store`, or `~/.local/share/mnpm/store`.
+    fn get_default_store_dir(&self) -> PathBuf {
+        let data_home = match self
+            .env
+            .get("XDG_DATA_HOME")
+            .filter(|value| !value.is_empty())
+        {
+            Some(data_home) => PathBuf::from(data_home),
+            None => match self.env.get("HOME") {
+                Some(home) => PathBuf::from(home).join(".local").join("share"),
+                None => env::temp_dir(),
+            },
+        };
+
+        data_home.join("mnpm").join("store")
+    }
+
+    /// The project `.npmrc` over the user `.npmrc`, for the settings keyed by
+    /// registry like scoped registries and credentials.
+    pub fn npmrc(&self) -> Npmrc {
+        let mut npmrc = self.project.clone();
+        npmrc.extend_missing(self.user.clone());
+        npmrc
+    }
+
+    /// Every setting that has a value: the known settings, then the other
+    /// `.npmrc` entries. Credentials are masked.
+    pub fn list(&self) -> Vec<(String, String, Source)> {
+        let npmrc = self.npmrc();
+        let keys = SETTINGS
+            .iter()
+            .copied()
+            .chain(npmrc.keys().filter(|key| !SETTINGS.contains(key)));
+
+        keys.filter_map(|key| {
+            let (value, source) = self.get_or_default(key)?;
+            Some((key.to_string(), mask(key, value), source))
+        })
+        .collect()
+    }
+
+    fn parse<T: FromStr>(&self, key: &str) -> anyhow::Result<T>
+    where
+        T::Err: fmt::Display,
+    {
+        let (value, source) = self
+            .get_or_default(key)
+            .ok_or_else(|| anyhow::anyhow!("{key} is not set"))?;
+
+        value
+            .parse()
+            .map_err(|error| anyhow::anyhow!("invalid {key} {value} from {source}: {error}"))
+    }
+
+    /// The date of the `before` setting, if set.
+    fn before(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        let Some((value, source)) = self.get_or_default("before") else {
+            return Ok(None);
+        };
+
+        parse_date(&value)
+            .map(Some)
+            .map_err(|error| anyhow::anyhow!("invalid before {value} from {source}: {error}"))
+    }
+}
+
+/// Check that `value` is valid for `key`, for the settings mnpm knows.
+pub fn validate_setting(key: &str, value: &str) -> anyhow::Result<()> {
+    let layers = ConfigLayers {
+        cli: IndexMap::from_iter([(key.to_string(), value.to_string())]),
+        ..ConfigLayers::default()
+    };
+
+    match key {
+        "registry" => layers.parse::<Url>(key).map(drop),
+        "network-concurrency" => layers.parse::<usize>(key).map(drop),
+        "fetch-timeout" => layers.parse::<u64>(key).map(drop),
+        "package-import-method" => layers.parse::<PackageImportMethod>(key).map(drop),
+        "resolution-mode" => layers.parse::<ResolutionMode>(key).map(drop),
+        "before" => layers.before().map(drop),
+        "strict-ssl" => layers.parse::<bool>(key).map(drop),
+        "proxy" | "https-proxy" => layers.parse::<Url>(key).map(drop),
+        _ => Ok(()),
+    }
+}
+
+/// `(protected)` instead of the value of credentials.
+pub fn mask(key: &str, value: String) -> String {
+    if PROTECTED_SUFFIXES
+        .iter()
+        .any(|suffix| key == *suffix || key.ends_with(&format!(":{suffix}")))
+    {
+        String::from("(protected)")
+    } else {
+        value
+    }
+}
+
+/// `MNPM_STORE_DIR` and `npm_config_store_dir` for `store-dir`, in priority order.
+fn get_env_vars(key: &str) -> [String; 2] {
+    let key = key.replace('-', "_");
+
+    [
+        format!("MNPM_{}", key.to_uppercase()),
+        format!("npm_config_{key}"),
+    ]
+}
+
 pub struct Config {
     pub client: reqwest::Client,
+    pub registry: Url,
+    /// Registries of `@scope:registry=` settings, keyed by scope including the `@`.
+    pub scoped_registries: HashMap<String, Url>,
+    pub npmrc: Npmrc,
+    /// The content-addressable store shared by every project of the user.
+    pub store_dir: PathBuf,
+    pub package_import_method: PackageImportMethod,
+    pub fetch_timeout: Duration,
+    /// Limits the number of concurrent requests to `network-concurrency`.
+    pub network: Semaphore,
+    /// Stop at the first error instead of reporting every failing package.
+    pub bail: bool,
+    /// Install `optionalDependencies`.
+    pub optional: bool,
+    /// The platform optional dependencies are installed for.
+    pub platform: Platform,
+    pub dependency_scope: DependencyScope,
+    pub resolution_mode: ResolutionMode,
+    /// Only install versions published before this date.
+    pub before: Option<DateTime<Utc>>,
+    pub reporter: Box<dyn Reporter>,
     // pub npm_registry_ip: SocketAddr,
 }
+
+impl Config {
+    /// Create the config of the project in the current directory.
+    /// `cli` holds the settings of command line flags, which take priority over
+    /// the environment and the project and user `.npmrc`.
+    pub fn load(cli: IndexMap<String, String>) -> anyhow::Result<Self> {
+        Self::from_layers(&ConfigLayers::load(cli, &env::current_dir()?)?)
+    }
+
+    /// Create a config from `npmrc` alone, without the environment, so that the
+    /// variables of the shell running the tests do not change it.
+    pub fn from_npmrc(
+        registry: Option<Url>,
+        store_dir: Option<PathBuf>,
+        npmrc: Npmrc,
+    ) -> anyhow::Result<Self> {
+        let mut cli = IndexMap::new();
+        if let Some(registry) = registry {
+            cli.insert(String::from("registry"), registry.to_string());
+        }
+        if let Some(store_dir) = store_dir {
+            cli.insert(String::from("store-dir"), store_dir.display().to_string());
+        }
+
+        Self::from_layers(&ConfigLayers {
+            cli,
+            project: npmrc,
+            ..ConfigLayers::default()
+        })
+    }
+
+    pub fn from_layers(layers: &ConfigLayers) -> anyhow::Result<Self> {
+        let npmrc = layers.npmrc();
+
+        let mut scoped_registries = HashMap::default();
+        for (scope, scoped_registry) in npmrc.scoped_registries() {
+            let scoped_registry = Url::parse(scoped_registry)
+                .map_err(|error| anyhow::anyhow!("invalid registry {scoped_registry}: {error}"))?;
+            scoped_registries.insert(scope.to_string(), normalize_registry(scoped_registry));
+        }
+
+        let fetch_timeout = Duration::from_millis(layers.parse("fetch-timeout")?);
+        let client = build_client(layers, fetch_timeout)?;
+
+        let network_concurrency: usize = layers.parse("network-concurrency")?;
+
+        Ok(Self {
+            client,
+            registry: normalize_registry(layers.parse("registry")?),
+            scoped_registries,
+            npmrc,
+            store_dir: layers.parse("store-dir")?,
+            package_import_method: layers.parse("package-import-method")?,
+            fetch_timeout,
+            network: Semaphore::new(network_concurrency.max(1)),
+            bail: true,
+            optional: true,
+            platform: Platform::current(),
+            dependency_scope: DependencyScope::All,
+            resolution_mode: layers.parse("resolution-mode")?,
+            before: layers.before()?,
+            reporter: Box::new(SilentReporter),
+        })
+    }
+
+    /// The registry that serves `package_name`, which depends on its scope.
+    pub fn get_registry(&self, package_name: &str) -> &Url {
+        package_name
+            .split_once('/')
+            .and_then(|(scope, _)| self.scoped_registries.get(scope))
+            .unwrap_or(&self.registry)
+    }
+
+    /// The credentials to send with a request to `url`.
+    pub fn get_credentials(&self, url: &Url) -> Option<Credentials> {
+        self.npmrc.get_credentials(url, &self.registry)
+    }
+}
+
+/// The client of every registry request, with the network settings of `layers`.
+fn build_client(layers: &ConfigLayers, fetch_timeout: Duration) -> anyhow::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder().timeout(fetch_timeout);
+
+    // `https-proxy` falls back to `proxy`, like pnpm does.
+    let no_proxy = layers
+        .get("noproxy")
+        .and_then(|(no_proxy, _)| NoProxy::from_string(&no_proxy));
+    let proxy = layers.get("proxy");
+    let https_proxy = layers.get("https-proxy").or_else(|| proxy.clone());
+    if let Some((proxy, source)) = proxy {
+        let proxy =
+            Proxy::http(&proxy).with_context(|| format!("invalid proxy {proxy} from {source}"))?;
+        builder = builder.proxy(proxy.no_proxy(no_proxy.clone()));
+    }
+    if let Some((proxy, source)) = https_proxy {
+        let proxy = Proxy::https(&proxy)
+            .with_context(|| format!("invalid https-proxy {proxy} from {source}"))?;
+        builder = builder.proxy(proxy.no_proxy(no_proxy));
+    }
+
+    if let Some((cafile, source)) = layers.get("cafile") {
+        let certificates = fs::read(&cafile)
+            .map_err(anyhow::Error::from)
+            .and_then(|pem| Ok(Certificate::from_pem_bundle(&pem)?))
+            .with_context(|| format!("invalid cafile {cafile} from {source}"))?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    if !layers.parse::<bool>("strict-ssl")? {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    match (layers.get("cert"), layers.get("key")) {
+        (Some((cert, source)), Some((key, _))) => {
+            let identity = read_pem(&cert)
+                .and_then(|cert| Ok(Identity::from_pkcs8_pem(&cert, &read_pem(&key)?)?))
+                .with_context(|| format!("invalid cert or key from {source}"))?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => anyhow::bail!("cert and key must be set together"),
+    }
+
+    builder.build().context("failed to build the http client")
+}
+
+/// `cert` and `key` are PEM with newlines escaped as `\n` like in npm, or a path to a PEM file.
+fn read_pem(value: &str) -> anyhow::Result<Vec<u8>> {
+    if value.contains("-----BEGIN") {
+        Ok(value.replace("\\n", "\n").into_bytes())
+    } else {
+        fs::read(value).with_context(|| format!("failed to read {value}"))
+    }
+}
+
+/// Registries served under a path prefix need a trailing slash,
+/// otherwise joining a package name replaces the last path segment.
+pub fn normalize_registry(mut registry: Url) -> Url {
+    if !registry.path().ends_with('/') {
+        let path = format!("{}/", registry.path());
+        registry.set_path(&path);
+    }
+
+    registry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layers(project: &str, env: &[(&str, &str)]) -> ConfigLayers {
+        ConfigLayers {
+            env: env
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            project: Npmrc::parse(project),
+            project_path: Some(PathBuf::from("/app/.npmrc")),
+            ..ConfigLayers::default()
+        }
+    }
+
+    fn get_registry(layers: &ConfigLayers) -> Url {
+        layers.parse("registry").unwrap()
+    }
+
+    #[test]
+    fn registry_priority() {
+        let npmrc = "registry=http://localhost:4873/";
+
+        let registry = get_registry(&layers(npmrc, &[]));
+        assert_eq!(registry.as_str(), "http://localhost:4873/");
+
+        let registry = get_registry(&layers(
+            npmrc,
+            &[("npm_config_registry", "https://npm.corp.dev/")],
+        ));
+        assert_eq!(registry.as_str(), "https://npm.corp.dev/");
+
+        let registry = get_registry(&layers(
+            npmrc,
+            &[("MNPM_REGISTRY", "https://mnpm.corp.dev/")],
+        ));
+        assert_eq!(registry.as_str(), "https://mnpm.corp.dev/");
+
+        let registry = get_registry(&layers("", &[]));
+        assert_eq!(registry.as_str(), NPM_REGISTRY_URL);
+    }
+
+    #[test]
+    fn scoped_registries() {
+        let config = Config::from_npmrc(
+            Some(Url::parse(NPM_REGISTRY_URL).unwrap()),
+            None,
+            Npmrc::parse("@corp:registry=https://npm.corp.dev/npm"),
+        )
+        .unwrap();
+
+        assert_eq!(config.get_registry("react").as_str(), NPM_REGISTRY_URL);
+        assert_eq!(
+            config.get_registry("@types/node").as_str(),
+            NPM_REGISTRY_URL
+        );
+        assert_eq!(
+            config.get_registry("@corp/ui").as_str(),
+            "https://npm.corp.dev/npm/"
+        );
+    }
+
+    #[test]
+    fn store_dir_priority() {
+        let get_store_dir = |layers: ConfigLayers| layers.parse::<PathBuf>("store-dir").unwrap();
+        let npmrc = "store-dir=/npmrc/store";
+        let env = [("XDG_DATA_HOME", "/xdg"), ("HOME", "/home/me")];
+
+        assert_eq!(
+            get_store_dir(layers("", &env)),
+            PathBuf::from("/xdg/mnpm/store")
+        );
+        assert_eq!(
+            get_store_dir(layers(npmrc, &env)),
+            PathBuf::from("/npmrc/store")
+        );
+        assert_eq!(
+            get_store_dir(layers(npmrc, &[("MNPM_STORE_DIR", "/env/store")])),
+            PathBuf::from("/env/store")
+        );
+        assert_eq!(
+            get_store_dir(layers("", &[("HOME", "/home/me")])),
+            PathBuf::from("/home/me/.local/share/mnpm/store")
+        );
+    }
+
+    #[test]
+    fn layer_priority() {
+        let mut layers = layers(
+            "network-concurrency=4\nfetch-timeout=1000",
+            &[("npm_config_network_concurrency", "8")],
+        );
+        layers.user = Npmrc::parse("fetch-timeout=2000\npackage-import-method=copy");
+        layers.user_path = Some(PathBuf::from("/home/me/.npmrc"));
+
+        assert_eq!(
+            layers.get_or_default("network-concurrency"),
+            Some((
+                String::from("8"),
+                Source::Env(String::from("npm_config_network_concurrency"))
+            ))
+        );
+        assert_eq!(
+            layers.get_or_default("fetch-timeout"),
+            Some((
+                String::from("1000"),
+                Source::Project(PathBuf::from("/app/.npmrc"))
+            ))
+        );
+        assert_eq!(
+            layers.get_or_default("package-import-method"),
+            Some((
+                String::from("copy"),
+                Source::User(PathBuf::from("/home/me/.npmrc"))
+            ))
+        );
+        assert_eq!(
+            layers.get_or_default("registry"),
+            Some((String::from(NPM_REGISTRY_URL), Source::Default))
+        );
+        assert_eq!(layers.get_or_default("unknown"), None);
+
+        layers
+            .cli
+            .insert(String::from("network-concurrency"), String::from("2"));
+        let config = Config::from_layers(&layers).unwrap();
+        assert_eq!(config.network.available_permits(), 2);
+        assert_eq!(config.fetch_timeout, Duration::from_millis(1000));
+        assert_eq!(config.package_import_method, PackageImportMethod::Copy);
+    }
+
+    #[test]
+    fn reports_invalid_settings() {
+        let error = Config::from_layers(&layers("fetch-timeout=soon", &[])).err();
+
+        assert_eq!(
+            error.map(|error| error.to_string()),
+            Some(String::from(
+                "invalid fetch-timeout soon from project config /app/.npmrc: invalid digit found in string"
+            ))
+        );
+        assert!(validate_setting("package-import-method", "symlink").is_err());
+        assert!(validate_setting("package-import-method", "copy").is_ok());
+        assert!(validate_setting("resolution-mode", "lowest").is_err());
+        assert!(validate_setting("resolution-mode", "time-based").is_ok());
+        assert!(validate_setting("before", "yesterday").is_err());
+    }
+
+    #[test]
+    fn parses_resolution_settings() {
+        let config = Config::from_layers(&layers("", &[])).unwrap();
+        assert_eq!(config.resolution_mode, ResolutionMode::Highest);
+        assert_eq!(config.before, None);
+
+        let config = Config::from_layers(&layers(
+            "resolution-mode=lowest-direct\nbefore=2023-01-31",
+            &[],
+        ))
+        .unwrap();
+        assert_eq!(config.resolution_mode, ResolutionMode::LowestDirect);
+        assert_eq!(
+            config.before.map(|before| before.to_rfc3339()).as_deref(),
+            Some("2023-01-31T00:00:00+00:00")
+        );
+
+        assert_eq!(
+            parse_date("2023-01-31T12:00:00+02:00").map(|date| date.to_rfc3339()),
+            Ok(String::from("2023-01-31T10:00:00+00:00"))
+        );
+    }
+
+    #[test]
+    fn lists_settings_with_credentials_masked() {
+        let layers = layers(
+            "registry=http://localhost:4873/\nkey=secret\n//localhost:4873/:_authToken=secret",
+            &[("HOME", "/home/me")],
+        );
+
+        let list = layers.list();
+        let find = |key: &str| {
+            list.iter()
+                .find(|(listed, ..)| listed == key)
+                .map(|(_, value, source)| (value.as_str(), source.clone()))
+        };
+        let project = Source::Project(PathBuf::from("/app/.npmrc"));
+
+        assert_eq!(
+            find("registry"),
+            Some(("http://localhost:4873/", project.clone()))
+        );
+        assert_eq!(find("key"), Some(("(protected)", project.clone())));
+        assert_eq!(
+            find("//localhost:4873/:_authToken"),
+            Some(("(protected)", project))
+        );
+        // the settings without a default and a value are not listed.
+        assert_eq!(find("proxy"), None);
+    }
+
+    #[test]
+    fn normalizes_path_prefix() {
+        let registry = normalize_registry(Url::parse("http://localhost:4873/npm").unwrap());
+
+        assert_eq!(registry.as_str(), "http://localhost:4873/npm/");
+        assert_eq!(
+            normalize_registry(registry.clone()).as_str(),
+            registry.as_str()
+        );
+    }
+}
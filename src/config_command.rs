@@ -0,0 +1,106 @@
+use std::{io::Write, path::PathBuf};
+
+use anyhow::Context;
+
+use crate::{
+    cli::ConfigLocation,
+    config::{mask, validate_setting, ConfigLayers},
+    npmrc::Npmrc,
+};
+
+/// Print the value of `key`.
+pub fn config_get(layers: &ConfigLayers, key: &str, out: &mut impl Write) -> anyhow::Result<()> {
+    let (value, _) = layers
+        .get_or_default(key)
+        .with_context(|| format!("{key} is not set"))?;
+
+    writeln!(out, "{}", mask(key, value))?;
+
+    Ok(())
+}
+
+/// Write `key=value` to the `.npmrc` at `location`.
+/// returns the path of the updated file.
+pub fn config_set(
+    layers: &ConfigLayers,
+    key: &str,
+    value: &str,
+    location: ConfigLocation,
+) -> anyhow::Result<PathBuf> {
+    validate_setting(key, value)?;
+
+    let path = match location {
+        ConfigLocation::User => layers.user_path.clone(),
+        ConfigLocation::Project => layers.project_path.clone(),
+    }
+    .context("failed to locate the config file, is HOME set?")?;
+
+    Npmrc::set_in_file(&path, key, value)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+
+    Ok(path)
+}
+
+/// Print every setting, with where its value comes from.
+pub fn config_list(layers: &ConfigLayers, out: &mut impl Write) -> anyhow::Result<()> {
+    for (key, value, source) in layers.list() {
+        writeln!(out, "{key}={value} ; {source}")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+
+    use super::*;
+
+    #[test]
+    fn sets_and_gets_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let load = || {
+            let mut layers = ConfigLayers::load(IndexMap::new(), dir.path()).unwrap();
+            layers.user_path = Some(dir.path().join("user.npmrc"));
+            layers.user = Npmrc::load(&dir.path().join("user.npmrc")).unwrap();
+            layers.env.clear();
+            layers
+        };
+        let get = |layers: &ConfigLayers, key: &str| {
+            let mut out = vec![];
+            config_get(layers, key, &mut out).map(|_| String::from_utf8(out).unwrap())
+        };
+
+        let layers = load();
+        assert_eq!(get(&layers, "package-import-method").unwrap(), "auto\n");
+        assert!(get(&layers, "unknown").is_err());
+        assert!(config_set(&layers, "fetch-timeout", "soon", ConfigLocation::User).is_err());
+
+        let path = config_set(&layers, "fetch-timeout", "1000", ConfigLocation::User).unwrap();
+        assert_eq!(path, dir.path().join("user.npmrc"));
+        config_set(&layers, "fetch-timeout", "2000", ConfigLocation::Project).unwrap();
+        config_set(
+            &layers,
+            "//npm.corp.dev/:_authToken",
+            "secret",
+            ConfigLocation::Project,
+        )
+        .unwrap();
+
+        let layers = load();
+        assert_eq!(get(&layers, "fetch-timeout").unwrap(), "2000\n");
+        assert_eq!(
+            get(&layers, "//npm.corp.dev/:_authToken").unwrap(),
+            "(protected)\n"
+        );
+
+        let mut out = vec![];
+        config_list(&layers, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(&format!(
+            "fetch-timeout=2000 ; project config {}\n",
+            dir.path().join(".npmrc").display()
+        )));
+        assert!(out.contains("package-import-method=auto ; default\n"));
+    }
+}
@@ -0,0 +1,157 @@
+use rustc_hash::FxHashSet as HashSet;
+use std::{
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use tokio::task;
+
+use crate::{
+    config::Config,
+    error::{Error, Result},
+    install_manifest::install_manifest,
+    install_package::link_resolution,
+    install_state::InstallState,
+    linker::{get_package_folder_name, read_virtual_store},
+    package_manifest::get_manifest_dependencies,
+    DEPS_FOLDER,
+};
+
+/// Deduplicate the installed packages, which keeps a single version of a package
+/// wherever it satisfies the requested ranges and unlinks the other copies.
+/// The versions picked by the last install are deduplicated, without resolving
+/// them again, unless package.json changed since, then it is installed again.
+/// With `check`, nothing is changed and the command fails if the installed packages
+/// are not the deduplicated ones.
+pub async fn dedupe(check: bool, config: &Config) -> Result<()> {
+    let started = Instant::now();
+    let mut root = task::spawn_blocking(get_manifest_dependencies).await??;
+    root.restrict_to(config.dependency_scope);
+
+    let state = InstallState::read(Path::new(DEPS_FOLDER)).await?;
+    let Some(state) = state.filter(|state| state.matches(&root)) else {
+        if check {
+            return Err(Error::OutdatedInstall);
+        }
+        return install_manifest(config).await;
+    };
+
+    let mut resolution = state.into_resolution();
+    resolution.dedupe(config.resolution_mode);
+    if !check {
+        link_resolution(resolution, started, config).await?;
+        return Ok(());
+    }
+
+    let deduped = resolution
+        .packages(config)
+        .iter()
+        .map(|dep| get_package_folder_name(&dep.id))
+        .collect();
+    let installed = read_virtual_store(PathBuf::from(DEPS_FOLDER)).await?;
+
+    compare(&installed, &deduped)
+}
+
+/// Fail with the packages to add and remove if `installed` differs from `deduped`.
+fn compare(installed: &HashSet<String>, deduped: &HashSet<String>) -> Result<()> {
+    let mut added: Vec<_> = deduped.difference(installed).cloned().collect();
+    let mut removed: Vec<_> = installed.difference(deduped).cloned().collect();
+    if added.is_empty() && removed.is_empty() {
+        return Ok(());
+    }
+
+    added.sort();
+    removed.sort();
+    Err(Error::DedupeChanges { added, removed })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::PoisonError;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{config::ResolutionMode, npmrc::Npmrc, test_registry::TestRegistry, CURRENT_DIR};
+
+    fn packages(folder_names: &[&str]) -> HashSet<String> {
+        folder_names.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn reports_changes() {
+        let deduped = packages(&["a@1.0.0", "b@1.3.0"]);
+        assert!(compare(&packages(&["b@1.3.0", "a@1.0.0"]), &deduped).is_ok());
+
+        let error = compare(&packages(&["a@1.0.0", "b@1.2.0", "b@1.3.0"]), &deduped).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "dedupe would change the installed packages:\n  - b@1.2.0"
+        );
+
+        let error = compare(&packages(&["b@1.2.0"]), &deduped).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "dedupe would change the installed packages:\n  + a@1.0.0\n  + b@1.3.0\n  - b@1.2.0"
+        );
+    }
+
+    #[test]
+    fn dedupes_the_installed_packages() {
+        let _current_dir = CURRENT_DIR.lock().unwrap_or_else(PoisonError::into_inner);
+        let pwd = std::env::current_dir().unwrap();
+        let project = tempfile::tempdir().unwrap();
+        let store_dir = tempfile::tempdir().unwrap();
+        let manifest = project.path().join("package.json");
+        std::fs::write(
+            &manifest,
+            json!({ "dependencies": { "a": "^1.0.0", "b": "^1.0.0" } }).to_string(),
+        )
+        .unwrap();
+        std::env::set_current_dir(project.path()).unwrap();
+
+        let result = tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let server = TestRegistry::with_packages(json!({
+                "a": { "1.0.0": { "dependencies": { "b": "^1.1.0" } } },
+                "b": { "1.0.0": {}, "1.1.0": {} },
+            }))
+            .await;
+            let mut config = Config::from_npmrc(
+                Some(server.url.clone()),
+                Some(store_dir.path().to_path_buf()),
+                Npmrc::default(),
+            )
+            .unwrap();
+            config.resolution_mode = ResolutionMode::LowestDirect;
+            install_manifest(&config).await?;
+            let installed = read_virtual_store(PathBuf::from(DEPS_FOLDER)).await?;
+
+            // the installed packages are deduplicated without the registry.
+            drop(server);
+            config.resolution_mode = ResolutionMode::Highest;
+            let changes = dedupe(true, &config).await;
+            dedupe(false, &config).await?;
+            let deduped = read_virtual_store(PathBuf::from(DEPS_FOLDER)).await?;
+            let checked = dedupe(true, &config).await;
+
+            std::fs::write(
+                &manifest,
+                json!({ "dependencies": { "a": "^1.0.0" } }).to_string(),
+            )?;
+            let outdated = dedupe(true, &config).await;
+
+            Ok::<_, Error>((installed, changes, deduped, checked, outdated))
+        });
+        std::env::set_current_dir(pwd).unwrap();
+
+        let (installed, changes, deduped, checked, outdated) = result.unwrap();
+        assert_eq!(installed, packages(&["a@1.0.0", "b@1.0.0", "b@1.1.0"]));
+        assert!(matches!(
+            changes,
+            Err(Error::DedupeChanges { added, removed }) if added.is_empty() && removed == ["b@1.0.0"]
+        ));
+        assert_eq!(deduped, packages(&["a@1.0.0", "b@1.1.0"]));
+        assert!(checked.is_ok());
+        assert!(matches!(outdated, Err(Error::OutdatedInstall)));
+    }
+}
@@ -1,258 +1,1602 @@
-use std::collections::{HashMap, HashSet};
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
+use chrono::{DateTime, Utc};
 use futures::{stream::FuturesUnordered, StreamExt};
+use tokio::sync::OnceCell;
 
 use crate::{
-    config::Config,
+    config::{Config, DependencyScope, ResolutionMode},
+    error::{Error, Result, UnresolvableRange},
+    git::{resolve_git, GitSpec},
     http::get_npm_package,
-    npm::{NpmPackageVersion, ResolvedDependencies, ResolvedDependencyTree, VersionRangeSpecifier},
-    resolve_version_range::resolve_version_from_version_range,
+    linker::get_package_folder_name,
+    local::{resolve_local, LocalSpec},
+    npm::{
+        NpmPackageVersion, NpmResolvedPackage, PackageId, ResolvedDependencies,
+        ResolvedDependencyTree, Version, VersionRangeSpecifier,
+    },
+    overrides::{OverrideScope, Overrides},
+    remote::{parse_tarball_url, resolve_remote},
+    reporter::Event,
+    resolve_version_range::{
+        closest_versions, resolve_version_from_version_range, satisfies, sorted_versions,
+        Error as ResolveError, VersionPreference,
+    },
 };
 
-#[derive(Debug, derive_more::Display, derive_more::Error)]
-pub enum Error {
-    DependencyResolveError,
-    VersionDoesNotExist,
+/// The version picked for every range of every package, once overridden.
+pub type ResolvedVersions = HashMap<String, HashMap<VersionRangeSpecifier, NpmPackageVersion>>;
+
+/// The packuments fetched during a resolution, so that a package is fetched
+/// once however many of its ranges are requested.
+/// Git, local and tarball url dependencies are resolved once per spec.
+#[derive(Default)]
+struct PackageCache {
+    packages: Mutex<HashMap<String, Arc<OnceCell<NpmResolvedPackage>>>>,
+    sources: Mutex<HashMap<VersionRangeSpecifier, Arc<OnceCell<NpmPackageVersion>>>>,
+}
+
+impl PackageCache {
+    async fn get(&self, name: &str, config: &Config) -> Result<Arc<OnceCell<NpmResolvedPackage>>> {
+        let cell = self
+            .packages
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .entry(name.to_string())
+            .or_default()
+            .clone();
+
+        cell.get_or_try_init(|| get_npm_package(name, config))
+            .await?;
+
+        Ok(cell)
+    }
+
+    async fn get_source<F>(
+        &self,
+        spec: &VersionRangeSpecifier,
+        resolve: impl FnOnce() -> F,
+    ) -> Result<NpmPackageVersion>
+    where
+        F: Future<Output = Result<NpmPackageVersion>>,
+    {
+        let cell = self
+            .sources
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .entry(spec.clone())
+            .or_default()
+            .clone();
+
+        cell.get_or_try_init(resolve).await.cloned()
+    }
+}
+
+/// The dependencies of the project, as listed in package.json.
+#[derive(Debug, Default)]
+pub struct RootDependencies {
+    pub dependencies: HashMap<String, VersionRangeSpecifier>,
+    pub dev_dependencies: HashMap<String, VersionRangeSpecifier>,
+    pub overrides: Overrides,
+    /// The folder of package.json, that relative local dependencies are relative to.
+    pub dir: PathBuf,
+}
+
+impl RootDependencies {
+    /// Drop the dependencies that are not part of `scope`.
+    pub fn restrict_to(&mut self, scope: DependencyScope) {
+        match scope {
+            DependencyScope::All => {}
+            DependencyScope::Production => self.dev_dependencies.clear(),
+            DependencyScope::Development => self.dependencies.clear(),
+        }
+    }
 }
 
+/// A range to resolve, requested by the project or by a package in `scope`.
+struct Request {
+    name: String,
+    range: VersionRangeSpecifier,
+    scope: OverrideScope,
+    optional: bool,
+    /// Requested by package.json.
+    direct: bool,
+    /// The packages that led to the request, `root` first.
+    path: Vec<String>,
+}
+
+/// The versions picked for the dependencies of the project, which the packages
+/// of the virtual store are built from.
+#[derive(Debug, Default)]
+pub struct Resolution {
+    /// The direct dependencies, production and dev ones.
+    pub root_deps: HashMap<String, VersionRangeSpecifier>,
+    /// The direct production dependencies.
+    pub prod_deps: HashMap<String, VersionRangeSpecifier>,
+    pub overrides: Overrides,
+    pub versions: ResolvedVersions,
+}
+
+impl Resolution {
+    /// Reuse the versions picked for a package for its other ranges, as few versions
+    /// of each package as possible are kept. With lowest first resolution `mode`s, the
+    /// versions of the direct ranges are kept whatever other ranges need.
+    pub fn dedupe(&mut self, mode: ResolutionMode) {
+        let pinned = match mode {
+            ResolutionMode::Highest => HashMap::default(),
+            ResolutionMode::LowestDirect | ResolutionMode::TimeBased => self.root_deps.clone(),
+        };
+        dedupe_versions(&mut self.versions, &pinned);
+    }
+
+    /// The packages of the virtual store.
+    pub fn packages(&self, config: &Config) -> Vec<ResolvedDependencies> {
+        construct_dependency_vec(
+            &self.versions,
+            &self.root_deps,
+            &self.prod_deps,
+            &self.overrides,
+            config,
+        )
+    }
+}
+
+/// Resolve the dependencies of `root` and all their transitive dependencies,
+/// into the packages of the virtual store.
 pub async fn resolve_deps(
-    deps: HashMap<String, VersionRangeSpecifier>,
-    client: &Config,
-) -> anyhow::Result<Vec<ResolvedDependencies>> {
-    // let mut package_to_get_from_npm = HashSet::new();
-    let mut futures = FuturesUnordered::new();
-    let mut fetched_packages = HashSet::new();
-
-    for (dep_name, dep_version_range) in deps {
-        // package_to_get_from_npm.insert((dep_name, dep_version_range, true));
-        if fetched_packages.contains(&dep_name) {
-            continue;
+    root: RootDependencies,
+    config: &Config,
+) -> Result<Vec<ResolvedDependencies>> {
+    Ok(resolve(root, config).await?.packages(config))
+}
+
+/// Pick the versions of the dependencies of `root` and all their transitive dependencies.
+/// Every `(name, range)` pair is resolved once per override scope, the resolution
+/// is complete when no pair is left in the worklist.
+pub async fn resolve(root: RootDependencies, config: &Config) -> Result<Resolution> {
+    let RootDependencies {
+        dependencies: deps,
+        dev_dependencies: dev_deps,
+        overrides,
+        dir,
+    } = root;
+    // a package in both `dependencies` and `devDependencies` is a production dependency.
+    let mut root_deps = dev_deps;
+    root_deps.extend(deps.clone());
+
+    let cache = PackageCache::default();
+    let resolve = |request: Request, preference: VersionPreference| {
+        let (cache, overrides, dir) = (&cache, &overrides, &dir);
+        async move {
+            let result =
+                get_npm_package_version(&request, overrides, preference, cache, dir, config).await;
+            (request, result)
         }
-        let future = get_npm_package_version(dep_name.clone(), dep_version_range, true, client);
-        fetched_packages.insert(dep_name);
-        futures.push(future);
-    }
-
-    let mut resolved_versions: HashMap<
-        String,
-        HashMap<VersionRangeSpecifier, (NpmPackageVersion, bool)>,
-    > = HashMap::new();
-
-    // while !package_to_get_from_npm.is_empty() {
-    // let mut futures = FuturesUnordered::new();
-    // for package in package_to_get_from_npm.iter() {
-    //     if fetched_packages.contains(&package.0) {
-    //         continue;
-    //     }
-    //     let future =
-    //         get_npm_package_version(package.0.clone(), package.1.clone(), package.2, client);
-    //     fetched_packages.insert(package.0.clone());
-    //     futures.push(future);
-    // }
-
-    // let mut versions: Vec<(
-    //     VersionRangeSpecifier,
-    //     Result<NpmPackageVersion, anyhow::Error>,
-    //     bool,
-    // )> = vec![];
-
-    // for fut in futures
-    //     .take(100)
-    //     .collect::<Vec<(
-    //         VersionRangeSpecifier,
-    //         Result<NpmPackageVersion, anyhow::Error>,
-    //         bool,
-    //     )>>()
-    //     .await
-    // {
-    //     versions.push(fut);
-    // }
-    // versions.push(join_all(futures).await.iter().tak)
-    //  = join_all(futures).await;
-
-    // package_to_get_from_npm.clear();
+    };
+
+    let mode = config.resolution_mode;
+    let direct_preference = VersionPreference {
+        lowest: mode != ResolutionMode::Highest,
+        published_before: config.before,
+    };
+    let mut preference = VersionPreference {
+        lowest: false,
+        published_before: config.before,
+    };
+    // with time-based resolution, transitive dependencies wait for every direct one,
+    // to be capped at the publish date of the newest.
+    let mut waiting_for_direct = mode == ResolutionMode::TimeBased;
+    let mut unresolved_direct = root_deps.len();
+    let mut newest_direct = None;
+    let mut deferred = vec![];
+
+    let mut worklist = FuturesUnordered::new();
+    let mut queued = HashSet::default();
+    // ranges that are required by some package, failing to resolve them fails the install.
+    let mut required = HashSet::default();
+
+    let root_scope = OverrideScope::default();
+    for (name, range) in &root_deps {
+        queued.insert((name.clone(), range.clone(), root_scope));
+        required.insert((name.clone(), range.clone()));
+        let request = Request {
+            name: name.clone(),
+            range: range.clone(),
+            scope: root_scope,
+            optional: false,
+            direct: true,
+            path: vec![String::from("root")],
+        };
+        worklist.push(resolve(request, direct_preference));
+    }
+
+    let mut resolved_versions = ResolvedVersions::default();
+    let mut errors = vec![];
+    let mut optional_errors = vec![];
 
     loop {
-        match futures.next().await {
-            Some(Ok((range, version, is_root))) => {
-                let version_clone = version.clone();
-                for (dep_name, dep_version_range) in version.dependencies {
-                    match resolved_versions.get(&dep_name) {
-                        Some(ranges) if !ranges.contains_key(&dep_version_range) => {
-                            let future =
-                                get_npm_package_version(dep_name, dep_version_range, false, client);
-                            // .then(|version| {
-                            // return (
-                            //     String::from(""),
-                            //     VersionRangeSpecifier::new(String::from("")),
-                            //     false,
-                            // );
-                            // });
-                            futures.push(future);
-                            // package_to_get_from_npm.insert((
-                            //     dep.0.to_owned(),
-                            //     dep.1.to_owned(),
-                            //     false,
-                            // ));
-                        }
-                        Some(_) => {}
-                        None => {
-                            // package_to_get_from_npm.insert((
-                            //     dep.0.to_owned(),
-                            //     dep.1.to_owned(),
-                            //     false,
-                            // ));
-                        }
-                    }
-                }
+        if waiting_for_direct && unresolved_direct == 0 {
+            waiting_for_direct = false;
+            preference.published_before = match (config.before, newest_direct) {
+                (Some(before), Some(newest)) => Some(before.min(newest)),
+                (before, newest) => before.or(newest),
+            };
+            for request in deferred.drain(..) {
+                worklist.push(resolve(request, preference));
+            }
+        }
+        let Some((request, result)) = worklist.next().await else {
+            break;
+        };
+        if request.direct {
+            unresolved_direct -= 1;
+        }
 
-                match resolved_versions.get_mut(&version.name) {
-                    Some(range_to_versions) => {
-                        range_to_versions.insert(range.clone(), (version_clone, is_root.clone()));
-                    }
-                    None => {
-                        let version_name = version.name.clone();
+        let picked = match result {
+            Ok(picked) => picked,
+            Err(error) if request.optional => {
+                optional_errors.push((request.name, request.range, error));
+                continue;
+            }
+            Err(error) if config.bail => {
+                report_unresolvable(&error, config);
+                return Err(error);
+            }
+            Err(error) => {
+                report_unresolvable(&error, config);
+                errors.push(error);
+                continue;
+            }
+        };
 
-                        let mut range_to_version = HashMap::new();
-                        range_to_version.insert(range.clone(), (version_clone, is_root.clone()));
-                        resolved_versions.insert(version_name, range_to_version);
-                    }
+        let PickedVersion {
+            range: picked_range,
+            scope,
+            version,
+            published,
+        } = picked;
+        if request.optional && !config.platform.supports(&version) {
+            continue;
+        }
+        if request.direct {
+            newest_direct = newest_direct.max(published);
+        }
+
+        for (dep_name, dep_range, dep_optional) in version.all_dependencies() {
+            if dep_optional && !config.optional {
+                continue;
+            }
+            if !dep_optional {
+                required.insert((dep_name.clone(), dep_range.clone()));
+            }
+            if queued.insert((dep_name.clone(), dep_range.clone(), scope)) {
+                let dependency = Request {
+                    name: dep_name.clone(),
+                    range: dep_range.clone(),
+                    scope,
+                    optional: dep_optional,
+                    direct: false,
+                    path: dependency_path(&request.path, &version),
+                };
+                if waiting_for_direct {
+                    deferred.push(dependency);
+                } else {
+                    worklist.push(resolve(dependency, preference));
                 }
             }
-            Some(Err(_)) => {}
-            None => {
-                break;
+        }
+
+        let versions = resolved_versions.entry(request.name).or_default();
+        // the version picked for a direct range is kept when a transitive dependency
+        // requests the same range from another override scope.
+        if request.direct {
+            versions.insert(picked_range, version);
+        } else {
+            versions.entry(picked_range).or_insert(version);
+        }
+    }
+
+    for (name, range, error) in optional_errors {
+        if required.contains(&(name.clone(), range.clone())) {
+            report_unresolvable(&error, config);
+            errors.push(error);
+        } else {
+            config.reporter.report(Event::Warning {
+                message: format!("skipped optional dependency {name}@{}: {error}", *range),
+            });
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(Error::from_errors(errors));
+    }
+
+    // the pending resolutions borrow the overrides.
+    drop(worklist);
+    let mut resolution = Resolution {
+        root_deps,
+        prod_deps: deps,
+        overrides,
+        versions: resolved_versions,
+    };
+    resolution.dedupe(mode);
+
+    Ok(resolution)
+}
+
+/// The path of the dependencies of `version`, requested along `path`.
+fn dependency_path(path: &[String], version: &NpmPackageVersion) -> Vec<String> {
+    let mut path = path.to_vec();
+    path.push(format!("{}@{}", version.name, version.version));
+    path
+}
+
+/// Report the diagnostic of `error` when no version matched a range.
+fn report_unresolvable(error: &Error, config: &Config) {
+    if let Error::NoMatchingVersion(unresolvable) = error {
+        config
+            .reporter
+            .report(Event::Unresolvable((**unresolvable).clone()));
+    }
+}
+
+/// Reuse the versions picked for a package for every other range of it they satisfy,
+/// so that as few versions as possible of each package are installed.
+/// The versions satisfying the most ranges are kept first, the highest one on ties.
+/// The ranges in `pinned` keep their version.
+fn dedupe_versions(
+    resolved: &mut ResolvedVersions,
+    pinned: &HashMap<String, VersionRangeSpecifier>,
+) {
+    for (name, versions) in resolved.iter_mut() {
+        let mut candidates: Vec<NpmPackageVersion> = vec![];
+        for version in versions.values() {
+            if candidates
+                .iter()
+                .all(|picked| picked.name != version.name || picked.version != version.version)
+            {
+                candidates.push(version.clone());
             }
         }
+        if candidates.len() < 2 {
+            continue;
+        }
+        candidates.sort_by_cached_key(|version| {
+            std::cmp::Reverse(version.version.parse::<node_semver::Version>().ok())
+        });
+
+        // a range is always covered by the version first picked for it, even for
+        // ranges that are not semver such as dist-tags. Aliases only cover themselves,
+        // and registry ranges are only covered by registry packages.
+        let covers = |candidate: &NpmPackageVersion, range: &VersionRangeSpecifier| {
+            let picked = &versions[range];
+            picked.name == candidate.name
+                && (picked.version == candidate.version
+                    || (candidate.source.is_none() && satisfies(&candidate.version, range)))
+        };
+        let mut uncovered: Vec<_> = versions.keys().cloned().collect();
+        let mut deduped = HashMap::default();
+        if let Some(range) = pinned
+            .get(name)
+            .filter(|range| versions.contains_key(*range))
+        {
+            let kept = &versions[range];
+            let (covered, rest) = uncovered
+                .into_iter()
+                .partition::<Vec<_>, _>(|range| covers(kept, range));
+            deduped.extend(covered.into_iter().map(|range| (range, kept.clone())));
+            uncovered = rest;
+        }
+        while !uncovered.is_empty() {
+            let (_, best) = candidates
+                .iter()
+                .enumerate()
+                .max_by_key(|(index, candidate)| {
+                    let covered = uncovered.iter().filter(|range| covers(candidate, range));
+                    (covered.count(), std::cmp::Reverse(*index))
+                })
+                .expect("every range has a candidate");
+
+            let (covered, rest) = uncovered
+                .into_iter()
+                .partition::<Vec<_>, _>(|range| covers(best, range));
+            deduped.extend(covered.into_iter().map(|range| (range, best.clone())));
+            uncovered = rest;
+        }
+
+        *versions = deduped;
     }
-    // while let Some(Ok((range, version, is_root))) = &futures.next().await {
-    //     // match version {
-    //     //     Ok(version) => {
-    //     for (dep_name, dep_version_range) in &version.dependencies {
-    //         match resolved_versions.get(dep_name) {
-    //             Some(ranges) if !ranges.contains_key(dep_version_range) => {
-    //                 let name = String::from("");
-    //                 let future =
-    //                     get_npm_package_version(dep_name, dep_version_range, false, client);
-    //                 // .then(|version| {
-    //                 // return (
-    //                 //     String::from(""),
-    //                 //     VersionRangeSpecifier::new(String::from("")),
-    //                 //     false,
-    //                 // );
-    //                 // });
-    //                 futures.push(future);
-    //                 // package_to_get_from_npm.insert((
-    //                 //     dep.0.to_owned(),
-    //                 //     dep.1.to_owned(),
-    //                 //     false,
-    //                 // ));
-    //             }
-    //             Some(_) => {}
-    //             None => {
-    //                 // package_to_get_from_npm.insert((
-    //                 //     dep.0.to_owned(),
-    //                 //     dep.1.to_owned(),
-    //                 //     false,
-    //                 // ));
-    //             }
-    //         }
-    //     }
-
-    //     match resolved_versions.get_mut(&version.name) {
-    //         Some(range_to_versions) => {
-    //             range_to_versions.insert(range.clone(), (version.clone(), is_root.clone()));
-    //         }
-    //         None => {
-    //             let version_name = version.name.clone();
-
-    //             let mut range_to_version = HashMap::new();
-    //             range_to_version.insert(range.clone(), (version.clone(), is_root.clone()));
-    //             resolved_versions.insert(version_name, range_to_version);
-    //         }
-    //     }
-    //     //     }
-    //     //     Err(error) => {
-    //     //         println!("{:?}", error);
-    //     //         return Err(Error::DependencyResolveError.into());
-    //     //     }
-    //     // }
-    // }
-    // }
-
-    construct_dependency_vec(resolved_versions)
 }
 
+/// Flatten `resolved` into the packages of the virtual store, with the packages linked
+/// next to each of them. Versions picked for a range of `root_deps` are marked as root,
+/// and packages that are not needed by the production dependencies `prod_deps` as dev.
+/// Ranges are looked up in `resolved` once `overrides` are applied to them.
 pub fn construct_dependency_vec(
-    resolved: HashMap<String, HashMap<VersionRangeSpecifier, (NpmPackageVersion, bool)>>,
-) -> anyhow::Result<Vec<ResolvedDependencies>> {
-    let mut resolved_deps = vec![];
-
-    for (_package, ranges) in resolved.iter() {
-        for (_range, (version, is_root)) in ranges {
-            let mut dependencies = vec![];
-
-            for dep in &version.dependencies {
-                if let Some(ranges) = resolved.get(dep.0) {
-                    if let Some((version, _)) = ranges.get(dep.1) {
-                        dependencies.push(version.to_owned())
+    resolved: &ResolvedVersions,
+    root_deps: &HashMap<String, VersionRangeSpecifier>,
+    prod_deps: &HashMap<String, VersionRangeSpecifier>,
+    overrides: &Overrides,
+    config: &Config,
+) -> Vec<ResolvedDependencies> {
+    let mut builder = GraphBuilder {
+        resolved,
+        overrides,
+        config,
+        nodes: HashMap::default(),
+        resolved_ids: HashMap::default(),
+        path: vec![],
+        back_edges: vec![],
+    };
+
+    let root_versions: Vec<_> = root_deps
+        .iter()
+        .filter_map(|(name, range)| {
+            let (version, scope) = builder.get_version(OverrideScope::default(), name, range)?;
+            Some((name.as_str(), version, scope))
+        })
+        .collect();
+    let root_scope: Scope = root_versions
+        .iter()
+        .map(|(name, version, _)| (*name, *version))
+        .collect();
+
+    let mut roots = HashMap::default();
+    let mut direct_names: HashMap<PackageId, Vec<String>> = HashMap::default();
+    for (name, version, scope) in root_versions {
+        let (id, _) = builder.resolve(version, scope, &[&root_scope]);
+        direct_names
+            .entry(id.clone())
+            .or_default()
+            .push(name.to_string());
+        roots.insert(name, id);
+    }
+
+    builder.link_peers(&roots);
+    let required = builder.reachable(roots.values(), false);
+    let prod = builder.reachable(
+        roots
+            .iter()
+            .filter(|(name, _)| prod_deps.contains_key(**name))
+            .map(|(_, id)| id),
+        true,
+    );
+
+    let mut resolved_deps: Vec<_> = builder
+        .nodes
+        .into_iter()
+        .map(|(id, node)| {
+            let mut names = direct_names.remove(&id).unwrap_or_default();
+            names.sort();
+            let optional = !required.contains(&id);
+            let dev = !prod.contains(&id);
+            let dependencies = node
+                .dependencies
+                .into_iter()
+                .map(|edge| (edge.name, edge.id))
+                .chain(
+                    node.peers
+                        .into_iter()
+                        .filter_map(|(name, id)| Some((name, id?))),
+                )
+                .collect();
+            ResolvedDependencies::new(
+                id,
+                node.version.to_owned(),
+                dependencies,
+                names,
+                optional,
+                dev,
+            )
+        })
+        .collect();
+
+    resolved_deps.sort_by_cached_key(|dep| get_package_folder_name(&dep.id));
+    resolved_deps
+}
+
+/// The id a version was given, with the peers that were not found.
+type PeerResolution = (PackageId, Vec<String>);
+
+/// The packages visible to the dependencies of a package, by name.
+type Scope<'a> = HashMap<&'a str, &'a NpmPackageVersion>;
+
+/// A dependency of a package of the virtual store.
+struct Edge {
+    name: String,
+    id: PackageId,
+    optional: bool,
+    /// Points back to a package of a dependency cycle, which was still being resolved.
+    back: bool,
+}
+
+/// A package of the virtual store while the graph is built.
+struct Node<'a> {
+    version: &'a NpmPackageVersion,
+    dependencies: Vec<Edge>,
+    /// The peers, linked once the packages of the dependent are known.
+    peers: Vec<(String, Option<PackageId>)>,
+}
+
+/// Builds the packages of the virtual store, resolving the peers of every package
+/// from the packages visible to it in its dependent, then in the dependents above.
+struct GraphBuilder<'a> {
+    resolved: &'a ResolvedVersions,
+    overrides: &'a Overrides,
+    config: &'a Config,
+    nodes: HashMap<PackageId, Node<'a>>,
+    /// The ids a version was given, with the peers missing when it was resolved.
+    /// A version resolves to the same id in every scope with the same peers
+    /// and the same overrides.
+    resolved_ids: HashMap<(&'a str, &'a Version, OverrideScope), Vec<PeerResolution>>,
+    /// The versions being resolved, to stop at dependency cycles.
+    path: Vec<&'a NpmPackageVersion>,
+    /// The back edges to packages still being resolved, as the package the edge
+    /// is from, the index of the edge and the position of its target in `path`.
+    /// Their ids are set once their target is resolved.
+    back_edges: Vec<(PackageId, usize, usize)>,
+}
+
+impl<'a> GraphBuilder<'a> {
+    /// The version picked for the dependency `name@range` of a package in `overrides`,
+    /// and the override scope of the dependency.
+    fn get_version(
+        &self,
+        overrides: OverrideScope,
+        name: &str,
+        range: &VersionRangeSpecifier,
+    ) -> Option<(&'a NpmPackageVersion, OverrideScope)> {
+        let (range, scope) = self.overrides.apply(overrides, name, range);
+        Some((self.resolved.get(name)?.get(&range)?, scope))
+    }
+
+    /// The resolved dependencies of `version`, with their override scope and whether
+    /// each is optional. Optional dependencies that were skipped are left out.
+    fn dependencies(
+        &self,
+        version: &'a NpmPackageVersion,
+        overrides: OverrideScope,
+    ) -> impl Iterator<Item = (&'a String, &'a NpmPackageVersion, OverrideScope, bool)> + '_ {
+        version
+            .all_dependencies()
+            .filter(|(_, _, optional)| !optional || self.config.optional)
+            .filter_map(move |(name, range, optional)| {
+                let (dependency, scope) = self.get_version(overrides, name, range)?;
+                Some((name, dependency, scope, optional))
+            })
+    }
+
+    /// Resolve `version` and its dependencies in `scope`, nearest scope first,
+    /// with the `overrides` it was picked with.
+    /// returns the id of `version` and the peers that were not found.
+    fn resolve(
+        &mut self,
+        version: &'a NpmPackageVersion,
+        overrides: OverrideScope,
+        scope: &[&Scope<'a>],
+    ) -> PeerResolution {
+        let lookup = |name: &str| {
+            scope
+                .iter()
+                .find_map(|packages| packages.get(name).copied())
+        };
+
+        let key = (version.name.as_str(), &version.version, overrides);
+        let previous = self.resolved_ids.get(&key).and_then(|ids| {
+            ids.iter().find(|(id, missing)| {
+                id.peers
+                    .iter()
+                    .all(|(name, peer)| lookup(name).map(|found| &found.version) == Some(peer))
+                    && missing.iter().all(|name| lookup(name).is_none())
+            })
+        });
+        if let Some(previous) = previous {
+            return previous.clone();
+        }
+
+        let mut peers = HashMap::default();
+        let mut missing = vec![];
+        for (name, range) in &version.peer_dependencies {
+            if version.dependencies.contains_key(name) {
+                continue;
+            }
+
+            match lookup(name) {
+                Some(peer) => {
+                    if !satisfies(&peer.version, range) {
+                        self.warn(format!(
+                            "{}@{} requires a peer of {name}@{} but {name}@{} was installed",
+                            version.name, version.version, **range, peer.version
+                        ));
+                    }
+                    peers.insert(name.clone(), peer);
+                }
+                None => {
+                    if !version.is_optional_peer(name) {
+                        self.warn(format!(
+                            "{}@{} requires a peer of {name}@{} but none was installed",
+                            version.name, version.version, **range
+                        ));
                     }
+                    missing.push(name.clone());
                 }
             }
+        }
 
-            resolved_deps.push(ResolvedDependencies::new(
-                version.to_owned(),
-                dependencies,
-                is_root.to_owned(),
-            ));
+        let version_dependencies: Vec<_> = self.dependencies(version, overrides).collect();
+        let mut own_scope: Scope = version_dependencies
+            .iter()
+            .map(|(name, dependency, _, _)| (name.as_str(), *dependency))
+            .collect();
+        own_scope.extend(peers.values().map(|peer| (peer.name.as_str(), *peer)));
+        own_scope.insert(&version.name, version);
+
+        let mut child_scope = vec![&own_scope];
+        child_scope.extend_from_slice(scope);
+
+        let depth = self.path.len();
+        self.path.push(version);
+        let mut dependencies = vec![];
+        let mut back_edges = vec![];
+        for (name, dependency, dependency_overrides, optional) in version_dependencies {
+            let ancestor = self.path.iter().position(|ancestor| {
+                ancestor.name == dependency.name && ancestor.version == dependency.version
+            });
+            if let Some(ancestor) = ancestor {
+                back_edges.push((dependencies.len(), ancestor));
+                dependencies.push(Edge {
+                    name: name.clone(),
+                    id: PackageId::from(dependency),
+                    optional,
+                    back: true,
+                });
+                continue;
+            }
+
+            let (id, dependency_missing) =
+                self.resolve(dependency, dependency_overrides, &child_scope);
+
+            // peers of dependencies that are not provided here must be provided by the
+            // dependent, so they are peers of this package too.
+            for (peer, _) in &id.peers {
+                if !own_scope.contains_key(peer.as_str()) {
+                    if let Some(found) = lookup(peer) {
+                        peers.insert(peer.clone(), found);
+                    }
+                }
+            }
+            missing.extend(
+                dependency_missing
+                    .into_iter()
+                    .filter(|peer| !own_scope.contains_key(peer.as_str())),
+            );
+            dependencies.push(Edge {
+                name: name.clone(),
+                id,
+                optional,
+                back: false,
+            });
+        }
+        self.path.pop();
+
+        let mut peer_ids: Vec<_> = peers
+            .iter()
+            .map(|(name, peer)| (name.clone(), peer.version.clone()))
+            .collect();
+        peer_ids.sort_by(|(a, _), (b, _)| a.cmp(b));
+        missing.sort();
+        missing.dedup();
+        let id = PackageId::new(version.name.clone(), version.version.clone(), peer_ids);
+
+        self.resolved_ids
+            .entry(key)
+            .or_default()
+            .push((id.clone(), missing.clone()));
+        if !self.nodes.contains_key(&id) {
+            self.nodes.insert(
+                id.clone(),
+                Node {
+                    version,
+                    dependencies,
+                    peers: peers.into_keys().map(|name| (name, None)).collect(),
+                },
+            );
+            self.back_edges.extend(
+                back_edges
+                    .into_iter()
+                    .map(|(edge, ancestor)| (id.clone(), edge, ancestor)),
+            );
+        }
+        self.link_back_edges(depth, &id);
+
+        (id, missing)
+    }
+
+    /// Point the back edges to the package at `depth` of the path to its `id`.
+    fn link_back_edges(&mut self, depth: usize, id: &PackageId) {
+        let (linked, pending) = std::mem::take(&mut self.back_edges)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, _, ancestor)| *ancestor == depth);
+        self.back_edges = pending;
+
+        for (from, edge, _) in linked {
+            if let Some(node) = self.nodes.get_mut(&from) {
+                node.dependencies[edge].id = id.clone();
+            }
+        }
+    }
+
+    /// Link the peers of every package to the packages linked next to it in its dependent.
+    fn link_peers(&mut self, roots: &HashMap<&str, PackageId>) {
+        let root_links: HashMap<String, PackageId> = roots
+            .iter()
+            .map(|(name, id)| (name.to_string(), id.clone()))
+            .collect();
+        let mut queue: VecDeque<_> = roots
+            .values()
+            .map(|id| (id.clone(), root_links.clone()))
+            .collect();
+        let mut visited = HashSet::default();
+
+        while let Some((id, dependent_links)) = queue.pop_front() {
+            if !visited.insert(id.clone()) {
+                continue;
+            }
+            let Some(node) = self.nodes.get_mut(&id) else {
+                continue;
+            };
+
+            for (name, peer) in &mut node.peers {
+                if peer.is_none() {
+                    *peer = dependent_links.get(name).cloned();
+                }
+            }
+
+            let mut links: HashMap<String, PackageId> = node
+                .dependencies
+                .iter()
+                .map(|edge| (edge.name.clone(), edge.id.clone()))
+                .collect();
+            links.extend(
+                node.peers
+                    .iter()
+                    .filter_map(|(name, peer)| Some((name.clone(), peer.clone()?))),
+            );
+            links.insert(id.name.clone(), id.clone());
+
+            // the target of a back edge is linked from its dependent up the cycle.
+            for edge in node.dependencies.iter().filter(|edge| !edge.back) {
+                queue.push_back((edge.id.clone(), links.clone()));
+            }
         }
     }
 
-    Ok(resolved_deps)
+    /// The packages reachable from `roots`, through optional dependencies
+    /// only if `through_optional`.
+    fn reachable<'b>(
+        &'b self,
+        roots: impl Iterator<Item = &'b PackageId>,
+        through_optional: bool,
+    ) -> HashSet<PackageId> {
+        let mut reachable = HashSet::default();
+        let mut queue: Vec<_> = roots.collect();
+
+        while let Some(id) = queue.pop() {
+            if !reachable.insert(id.clone()) {
+                continue;
+            }
+            let Some(node) = self.nodes.get(id) else {
+                continue;
+            };
+
+            queue.extend(
+                node.dependencies
+                    .iter()
+                    .filter(|edge| through_optional || !edge.optional)
+                    .map(|edge| &edge.id),
+            );
+            queue.extend(node.peers.iter().filter_map(|(_, peer)| peer.as_ref()));
+        }
+
+        reachable
+    }
+
+    fn warn(&self, message: String) {
+        self.config.reporter.report(Event::Warning { message });
+    }
 }
 
+/// The tree of the dependencies of `root_name@root_range`. A package that depends
+/// back on one of its ancestors gets a circular node for it, without its dependencies,
+/// so that cycles end.
 pub fn construct_dependency_tree(
     root_name: &String,
     root_range: &VersionRangeSpecifier,
-    resolved_versions: &HashMap<String, HashMap<VersionRangeSpecifier, NpmPackageVersion>>,
-) -> anyhow::Result<ResolvedDependencyTree> {
+    resolved_versions: &ResolvedVersions,
+) -> Result<ResolvedDependencyTree> {
+    construct_subtree(root_name, root_range, resolved_versions, &mut vec![])
+}
+
+fn construct_subtree<'a>(
+    root_name: &String,
+    root_range: &VersionRangeSpecifier,
+    resolved_versions: &'a ResolvedVersions,
+    path: &mut Vec<&'a NpmPackageVersion>,
+) -> Result<ResolvedDependencyTree> {
     let root_resolved_version = match resolved_versions.get(root_name) {
-        Some(versions) => versions.get(&root_range),
+        Some(versions) => versions.get(root_range),
         None => None,
     };
 
     let root_resolved_version = match root_resolved_version {
-        Some(version) => version.to_owned(),
+        Some(version) => version,
         None => {
-            return Err(Error::VersionDoesNotExist.into());
+            let mut requested_by: Vec<_> = path
+                .iter()
+                .map(|ancestor| format!("{}@{}", ancestor.name, ancestor.version))
+                .collect();
+            requested_by.push(format!("{root_name}@{}", **root_range));
+            return Err(Error::NoMatchingVersion(Box::new(UnresolvableRange {
+                name: root_name.to_owned(),
+                range: root_range.to_owned(),
+                available: vec![],
+                closest: vec![],
+                tags: vec![],
+                path: requested_by,
+            })));
         }
     };
 
+    if path.iter().any(|ancestor| {
+        ancestor.name == root_resolved_version.name
+            && ancestor.version == root_resolved_version.version
+    }) {
+        return Ok(ResolvedDependencyTree::circular(
+            root_name.to_owned(),
+            root_resolved_version.to_owned(),
+        ));
+    }
+
     let mut trees = Vec::new();
 
+    path.push(root_resolved_version);
     for (dep_name, dep_range) in &root_resolved_version.dependencies {
-        match construct_dependency_tree(dep_name, dep_range, resolved_versions) {
+        match construct_subtree(dep_name, dep_range, resolved_versions, path) {
             Ok(tree) => {
                 trees.push(tree);
             }
             Err(error) => return Err(error),
         }
     }
+    path.pop();
+    trees.sort_by(|a, b| a.name.cmp(&b.name));
 
-    let dep_tree = ResolvedDependencyTree::new(root_name.to_owned(), root_resolved_version, trees);
+    let dep_tree = ResolvedDependencyTree::new(
+        root_name.to_owned(),
+        root_resolved_version.to_owned(),
+        trees,
+    );
     Ok(dep_tree)
 }
 
+/// A version picked for a requested range.
+struct PickedVersion {
+    /// The range the version was picked for, once overridden.
+    range: VersionRangeSpecifier,
+    /// The override scope of the dependencies of the version.
+    scope: OverrideScope,
+    version: NpmPackageVersion,
+    published: Option<DateTime<Utc>>,
+}
+
+/// Pick the version for `request`, according to `preference`.
+/// The range is replaced first when `overrides` match it.
+/// Aliases pick a version of the package they stand for, and relative local
+/// dependencies of the project are relative to its folder `dir`.
 async fn get_npm_package_version(
-    package_name: String,
-    version_range: VersionRangeSpecifier,
-    is_root: bool,
-    client: &Config,
-) -> anyhow::Result<(VersionRangeSpecifier, NpmPackageVersion, bool)> {
-    let package = get_npm_package(&package_name, client).await?;
+    request: &Request,
+    overrides: &Overrides,
+    preference: VersionPreference,
+    cache: &PackageCache,
+    dir: &Path,
+    config: &Config,
+) -> Result<PickedVersion> {
+    let (version_range, scope) = overrides.apply(request.scope, &request.name, &request.range);
+    let source_version = if let Some(spec) = GitSpec::parse(&version_range) {
+        let resolve = || resolve_git(&request.name, &spec, config);
+        Some(cache.get_source(&version_range, resolve).await?)
+    } else if let Some(spec) = LocalSpec::parse(&version_range) {
+        let resolve = || resolve_local(&request.name, &spec, dir);
+        Some(cache.get_source(&version_range, resolve).await?)
+    } else if let Some(url) = parse_tarball_url(&version_range) {
+        let resolve = || resolve_remote(&request.name, &url, config);
+        Some(cache.get_source(&version_range, resolve).await?)
+    } else {
+        None
+    };
+    if let Some(version) = source_version {
+        config.reporter.report(Event::Resolved {
+            name: version.name.clone(),
+            version: version.version.clone(),
+        });
+
+        return Ok(PickedVersion {
+            range: version_range,
+            scope,
+            version,
+            published: None,
+        });
+    }
+    let (package_name, range) = match version_range.alias() {
+        Some((aliased, range)) => (aliased, range),
+        None => (request.name.as_str(), version_range.clone()),
+    };
+    let package = cache.get(package_name, config).await?;
+    let Some(package) = package.get() else {
+        unreachable!("initialized by PackageCache::get");
+    };
 
     let version =
-        resolve_version_from_version_range(&package, &version_range).map_err(|error| error)?;
+        resolve_version_from_version_range(package, &range, preference).map_err(|error| {
+            let mut path = request.path.clone();
+            path.push(format!("{}@{}", request.name, *version_range));
+            let mut tags = vec![];
+            if error == ResolveError::UnknownTag {
+                tags.extend(package.dist_tags.keys().cloned());
+                tags.sort();
+            }
+            Error::NoMatchingVersion(Box::new(UnresolvableRange {
+                name: package_name.to_string(),
+                closest: closest_versions(package, &range),
+                range,
+                available: sorted_versions(package),
+                tags,
+                path,
+            }))
+        })?;
+
+    config.reporter.report(Event::Resolved {
+        name: version.name.clone(),
+        version: version.version.clone(),
+    });
+
+    Ok(PickedVersion {
+        range: version_range,
+        scope,
+        published: package.published(&version.version),
+        version,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        npmrc::Npmrc,
+        package_manifest::read_root_dependencies,
+        platform::Platform,
+        reporter::RecordingReporter,
+        test_registry::{fixture_dependencies, registry_fixture, TestRegistry},
+    };
+
+    /// A registry serving `packages`, and a config using it.
+    async fn fixture_config(packages: Value) -> (TestRegistry, Config) {
+        let server = TestRegistry::with_packages(packages).await;
+        let config = Config::from_npmrc(Some(server.url.clone()), None, Npmrc::default()).unwrap();
+
+        (server, config)
+    }
+
+    /// The dependencies of the package.json `manifest`.
+    fn manifest_root(manifest: Value) -> RootDependencies {
+        read_root_dependencies(&manifest).unwrap()
+    }
+
+    /// Resolve the dependencies of the package.json `manifest` from a registry serving `packages`.
+    async fn resolve_fixture(
+        packages: Value,
+        manifest: Value,
+    ) -> Result<Vec<ResolvedDependencies>> {
+        let (_server, config) = fixture_config(packages).await;
+
+        resolve_deps(manifest_root(manifest), &config).await
+    }
+
+    /// Resolve the fixture `project` from the fixture registry.
+    async fn resolve_project(project: &str) -> Vec<ResolvedDependencies> {
+        let (server, config) = fixture_config(registry_fixture()).await;
+
+        let resolved = resolve_deps(
+            RootDependencies {
+                dependencies: fixture_dependencies(project),
+                ..RootDependencies::default()
+            },
+            &config,
+        )
+        .await
+        .unwrap();
+
+        // each package is fetched once, however many ranges of it are requested.
+        let fetched: Vec<_> = server.requests().into_iter().map(|r| r.path).collect();
+        let unique: HashSet<_> = fetched.iter().collect();
+        assert_eq!(fetched.len(), unique.len());
+
+        resolved
+    }
+
+    /// Every dependency of every resolved package is resolved too.
+    fn assert_complete(resolved: &[ResolvedDependencies]) {
+        let versions: HashSet<_> = resolved
+            .iter()
+            .map(|dep| (&dep.version.name, &dep.version.version))
+            .collect();
+        assert_eq!(versions.len(), resolved.len(), "duplicated packages");
+
+        for dep in resolved {
+            assert_eq!(
+                dep.dependencies.len(),
+                dep.version.dependencies.len(),
+                "unresolved dependencies of {}",
+                dep.version.name
+            );
+            for (_, child) in &dep.dependencies {
+                assert!(versions.contains(&(&child.name, &child.version)));
+            }
+        }
+    }
+
+    fn names(resolved: &[ResolvedDependencies]) -> Vec<String> {
+        resolved
+            .iter()
+            .map(|dep| format!("{}@{}", dep.version.name, dep.version.version))
+            .collect()
+    }
 
-    Ok((version_range.to_owned(), version, is_root))
+    #[tokio::test]
+    async fn resolves_basic_fixture() {
+        let resolved = resolve_project("basic").await;
+
+        assert_complete(&resolved);
+        assert_eq!(
+            names(&resolved),
+            [
+                "is-buffer@1.1.6",
+                "is-even@1.0.0",
+                "is-number@3.0.0",
+                "is-odd@0.1.2",
+                "js-tokens@4.0.0",
+                "kind-of@3.2.2",
+                "loose-envify@1.4.0",
+                "react@18.2.0",
+            ]
+        );
+        let roots: Vec<_> = resolved
+            .iter()
+            .filter(|dep| dep.is_root())
+            .map(|dep| dep.version.name.as_str())
+            .collect();
+        assert_eq!(roots, ["is-even", "react"]);
+    }
+
+    #[tokio::test]
+    async fn resolves_complex_fixture() {
+        let resolved = resolve_project("complex").await;
+
+        assert_complete(&resolved);
+        let names = names(&resolved);
+        assert_eq!(resolved.len(), 91);
+        for name in [
+            "@vue/compiler-core@3.3.4",
+            "ansi-styles@4.3.0",
+            "brace-expansion@1.1.11",
+            "is-buffer@1.1.6",
+            "nanoid@3.3.6",
+            "yallist@4.0.0",
+        ] {
+            assert!(names.contains(&name.to_string()), "{name} is missing");
+        }
+        // different ranges of the same package resolve to different versions.
+        for name in [
+            "readable-stream@1.0.34",
+            "readable-stream@1.1.14",
+            "readable-stream@2.3.8",
+        ] {
+            assert!(names.contains(&name.to_string()), "{name} is missing");
+        }
+    }
+
+    fn folder_names(resolved: &[ResolvedDependencies]) -> Vec<String> {
+        resolved
+            .iter()
+            .map(|dep| get_package_folder_name(&dep.id))
+            .collect()
+    }
+
+    fn find<'a>(
+        resolved: &'a [ResolvedDependencies],
+        folder_name: &str,
+    ) -> &'a ResolvedDependencies {
+        resolved
+            .iter()
+            .find(|dep| get_package_folder_name(&dep.id) == folder_name)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn resolves_peers_from_the_dependent() {
+        let resolved = resolve_project("with-peer").await;
+
+        let ajv_keywords = find(&resolved, "ajv-keywords@3.5.2_ajv@6.12.6");
+        assert!(ajv_keywords.is_root());
+        assert!(find(&resolved, "ajv@6.12.6").is_root());
+        let linked: Vec<_> = ajv_keywords
+            .dependencies
+            .iter()
+            .map(|(_, id)| get_package_folder_name(id))
+            .collect();
+        assert_eq!(linked, ["ajv@6.12.6"]);
+    }
+
+    #[tokio::test]
+    async fn resolves_peers_per_dependent() {
+        let (_server, mut config) = fixture_config(json!({
+            "plugin": { "1.0.0": {
+                "peerDependencies": { "host": "^1.0.0", "other": "^1.0.0", "opt": "^1.0.0" },
+                "peerDependenciesMeta": { "opt": { "optional": true } },
+            } },
+            "host": { "1.0.0": {}, "2.0.0": {} },
+            "a": { "1.0.0": { "dependencies": { "plugin": "^1.0.0", "host": "^1.0.0" } } },
+            "b": { "1.0.0": { "dependencies": { "plugin": "^1.0.0", "host": "^2.0.0" } } },
+            "c": { "1.0.0": { "dependencies": { "plugin": "^1.0.0" } } },
+        }))
+        .await;
+        let reporter = RecordingReporter::default();
+        config.reporter = Box::new(reporter.clone());
+
+        let manifest = json!({ "dependencies": {
+            "a": "^1.0.0", "b": "^1.0.0", "c": "^1.0.0", "host": "^1.0.0",
+        } });
+        let resolved = resolve_deps(manifest_root(manifest), &config)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            folder_names(&resolved),
+            [
+                "a@1.0.0",
+                "b@1.0.0",
+                // `c` does not depend on `host`, the one of its dependent is linked to it.
+                "c@1.0.0_host@1.0.0",
+                "host@1.0.0",
+                "host@2.0.0",
+                "plugin@1.0.0_host@1.0.0",
+                "plugin@1.0.0_host@2.0.0",
+            ]
+        );
+        let linked = |folder_name| {
+            let mut linked: Vec<_> = find(&resolved, folder_name)
+                .dependencies
+                .iter()
+                .map(|(_, id)| get_package_folder_name(id))
+                .collect();
+            linked.sort();
+            linked
+        };
+        assert_eq!(
+            linked("c@1.0.0_host@1.0.0"),
+            ["host@1.0.0", "plugin@1.0.0_host@1.0.0"]
+        );
+        assert_eq!(linked("plugin@1.0.0_host@2.0.0"), ["host@2.0.0"]);
+
+        let warnings: Vec<_> = reporter
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|event| match event {
+                Event::Warning { message } => Some(message.clone()),
+                _ => None,
+            })
+            .collect();
+        assert!(warnings.contains(&String::from(
+            "plugin@1.0.0 requires a peer of host@^1.0.0 but host@2.0.0 was installed"
+        )));
+        assert!(warnings.contains(&String::from(
+            "plugin@1.0.0 requires a peer of other@^1.0.0 but none was installed"
+        )));
+        assert!(!warnings.iter().any(|warning| warning.contains("opt@")));
+    }
+
+    #[tokio::test]
+    async fn resolves_optional_dependencies_for_the_platform() {
+        let server = TestRegistry::with_packages(json!({
+            "esbuild": { "1.0.0": { "optionalDependencies": {
+                "@esbuild/linux-x64": "1.0.0",
+                "@esbuild/darwin-arm64": "1.0.0",
+                "@esbuild/unpublished": "1.0.0",
+            } } },
+            "@esbuild/linux-x64": { "1.0.0": { "os": ["linux"], "cpu": ["x64"] } },
+            "@esbuild/darwin-arm64": { "1.0.0": { "os": ["darwin"], "cpu": ["arm64"] } },
+        }))
+        .await;
+        let resolve = |os: &str, cpu: &str, optional: bool| {
+            let mut config =
+                Config::from_npmrc(Some(server.url.clone()), None, Npmrc::default()).unwrap();
+            config.platform = Platform {
+                os: String::from(os),
+                cpu: String::from(cpu),
+                libc: None,
+            };
+            config.optional = optional;
+            let reporter = RecordingReporter::default();
+            config.reporter = Box::new(reporter.clone());
+
+            async move {
+                let manifest = json!({ "dependencies": { "esbuild": "^1.0.0" } });
+                let resolved = resolve_deps(manifest_root(manifest), &config)
+                    .await
+                    .unwrap();
+                let events = reporter.0.lock().unwrap().clone();
+                (resolved, events)
+            }
+        };
+
+        let (resolved, events) = resolve("linux", "x64", true).await;
+        assert_eq!(
+            names(&resolved),
+            ["@esbuild/linux-x64@1.0.0", "esbuild@1.0.0"]
+        );
+        assert!(resolved[0].optional);
+        assert!(!resolved[1].optional);
+        assert_eq!(resolved[1].dependencies.len(), 1);
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Event::Warning { message }
+                if message.starts_with("skipped optional dependency @esbuild/unpublished@1.0.0")
+        )));
+
+        let (resolved, _) = resolve("darwin", "arm64", true).await;
+        assert_eq!(
+            names(&resolved),
+            ["@esbuild/darwin-arm64@1.0.0", "esbuild@1.0.0"]
+        );
+
+        let (resolved, _) = resolve("linux", "x64", false).await;
+        assert_eq!(names(&resolved), ["esbuild@1.0.0"]);
+    }
+
+    #[tokio::test]
+    async fn marks_dev_only_packages() {
+        let resolved = resolve_fixture(
+            json!({
+                "app-lib": { "1.0.0": { "dependencies": { "shared": "^1.0.0" } } },
+                "test-runner": { "1.0.0": { "dependencies": { "shared": "^1.0.0", "reporter": "^1.0.0" } } },
+                "shared": { "1.0.0": {} },
+                "reporter": { "1.0.0": {} },
+            }),
+            json!({
+                "dependencies": { "app-lib": "^1.0.0" },
+                "devDependencies": { "test-runner": "^1.0.0" },
+            }),
+        )
+        .await
+        .unwrap();
+
+        let dev: Vec<_> = resolved
+            .iter()
+            .map(|dep| (dep.version.name.as_str(), dep.is_root(), dep.dev))
+            .collect();
+        assert_eq!(
+            dev,
+            [
+                ("app-lib", true, false),
+                ("reporter", false, true),
+                ("shared", false, false),
+                ("test-runner", true, true),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn resolves_overridden_ranges() {
+        let resolved = resolve_fixture(
+            json!({
+                "a": { "1.0.0": { "dependencies": { "b": "^1.0.0", "c": "^1.0.0" } } },
+                "b": { "1.0.0": {}, "1.1.0": {}, "2.0.0": {} },
+                "c": { "1.0.0": { "dependencies": { "b": "^1.0.0" } } },
+            }),
+            json!({
+                "dependencies": { "a": "^1.0.0", "b": "^2.0.0" },
+                "overrides": { "a": { "b": "1.0.0" } },
+            }),
+        )
+        .await
+        .unwrap();
+
+        // the override applies to every package below `a`, not to the direct dependency.
+        assert_eq!(
+            names(&resolved),
+            ["a@1.0.0", "b@1.0.0", "b@2.0.0", "c@1.0.0"]
+        );
+        let c = find(&resolved, "c@1.0.0");
+        assert_eq!(c.dependencies[0].1.version.to_string(), "1.0.0");
+    }
+
+    #[tokio::test]
+    async fn dedupes_satisfying_versions() {
+        let (_server, config) = fixture_config(json!({
+            "a": { "1.0.0": { "dependencies": { "b": "1.2.5" } } },
+            "b": { "1.2.5": {}, "1.3.0": {}, "2.0.0": {} },
+            "c": { "1.0.0": { "dependencies": { "b": "^1.2.0" } } },
+            "d": { "1.0.0": { "dependencies": { "b": ">=1.0.0" } } },
+        }))
+        .await;
+        let mut manifest =
+            json!({ "dependencies": { "a": "^1.0.0", "c": "^1.0.0", "d": "^1.0.0" } });
+
+        let resolved = resolve_deps(manifest_root(manifest.clone()), &config)
+            .await
+            .unwrap();
+        assert_eq!(
+            names(&resolved),
+            ["a@1.0.0", "b@1.2.5", "c@1.0.0", "d@1.0.0"]
+        );
+
+        // `b@2.0.0` is only kept for the range that `b@1.2.5` does not satisfy.
+        manifest["dependencies"]["b"] = json!("^2.0.0");
+        let resolved = resolve_deps(manifest_root(manifest), &config)
+            .await
+            .unwrap();
+        assert_eq!(
+            names(&resolved),
+            ["a@1.0.0", "b@1.2.5", "b@2.0.0", "c@1.0.0", "d@1.0.0"]
+        );
+        assert_eq!(
+            find(&resolved, "d@1.0.0").dependencies[0]
+                .1
+                .version
+                .to_string(),
+            "1.2.5"
+        );
+    }
+
+    #[tokio::test]
+    async fn resolves_with_resolution_modes() {
+        let server = TestRegistry::with_packages(json!({
+            "a": {
+                "1.0.0": { "dependencies": { "b": "^1.0.0" } },
+                "1.1.0": { "dependencies": { "b": "^1.0.0" } },
+                "time": { "1.0.0": "2020-09-01T00:00:00.000Z", "1.1.0": "2021-01-01T00:00:00.000Z" },
+            },
+            "b": {
+                "1.0.0": {}, "1.1.0": {}, "1.2.0": {},
+                "time": {
+                    "1.0.0": "2020-06-01T00:00:00.000Z",
+                    "1.1.0": "2020-08-01T00:00:00.000Z",
+                    "1.2.0": "2021-06-01T00:00:00.000Z",
+                },
+            },
+        }))
+        .await;
+        let resolve = |resolution_mode, before: Option<&str>| {
+            let mut config =
+                Config::from_npmrc(Some(server.url.clone()), None, Npmrc::default()).unwrap();
+            config.resolution_mode = resolution_mode;
+            config.before = before.map(|before| crate::config::parse_date(before).unwrap());
+            let manifest = json!({ "dependencies": { "a": "^1.0.0" } });
+            async move {
+                names(
+                    &resolve_deps(manifest_root(manifest), &config)
+                        .await
+                        .unwrap(),
+                )
+            }
+        };
+
+        assert_eq!(
+            resolve(ResolutionMode::Highest, None).await,
+            ["a@1.1.0", "b@1.2.0"]
+        );
+        assert_eq!(
+            resolve(ResolutionMode::LowestDirect, None).await,
+            ["a@1.0.0", "b@1.2.0"]
+        );
+        // `b` is capped at the publish date of `a@1.0.0`.
+        assert_eq!(
+            resolve(ResolutionMode::TimeBased, None).await,
+            ["a@1.0.0", "b@1.1.0"]
+        );
+        assert_eq!(
+            resolve(ResolutionMode::Highest, Some("2020-12-31")).await,
+            ["a@1.0.0", "b@1.1.0"]
+        );
+    }
+
+    #[test]
+    fn constructs_trees_with_cycles() {
+        let version = |name: &str, dependencies: serde_json::Value| -> NpmPackageVersion {
+            serde_json::from_value(serde_json::json!({
+                "name": name,
+                "version": "1.0.0",
+                "dependencies": dependencies,
+                "dist": { "shasum": "", "tarball": "" },
+            }))
+            .unwrap()
+        };
+        let range = VersionRangeSpecifier::new(String::from("^1.0.0"));
+        let mut resolved = ResolvedVersions::default();
+        for (name, dependencies) in [
+            ("a", serde_json::json!({ "b": "^1.0.0", "c": "^1.0.0" })),
+            ("b", serde_json::json!({ "a": "^1.0.0", "b": "^1.0.0" })),
+            ("c", serde_json::json!({})),
+        ] {
+            resolved
+                .entry(name.to_string())
+                .or_default()
+                .insert(range.clone(), version(name, dependencies));
+        }
+
+        let tree = construct_dependency_tree(&String::from("a"), &range, &resolved).unwrap();
+
+        assert_eq!(
+            tree.to_string(),
+            [
+                "a@1.0.0",
+                "├── b@1.0.0",
+                "│   ├── a@1.0.0 (circular)",
+                "│   └── b@1.0.0 (circular)",
+                "└── c@1.0.0",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn resolves_dependency_cycles() {
+        let resolved = resolve_fixture(
+            json!({
+                "a": { "1.0.0": {
+                    "dependencies": { "b": "^1.0.0" },
+                    "peerDependencies": { "p": "^1.0.0" },
+                } },
+                "b": { "1.0.0": { "dependencies": { "a": "^1.0.0", "b": "^1.0.0" } } },
+                "p": { "1.0.0": {} },
+            }),
+            json!({ "dependencies": { "a": "^1.0.0", "p": "^1.0.0" } }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            folder_names(&resolved),
+            ["a@1.0.0_p@1.0.0", "b@1.0.0", "p@1.0.0"]
+        );
+        // the back edges link the packages of the cycle, with their peers.
+        let mut linked: Vec<_> = find(&resolved, "b@1.0.0")
+            .dependencies
+            .iter()
+            .map(|(_, id)| get_package_folder_name(id))
+            .collect();
+        linked.sort();
+        assert_eq!(linked, ["a@1.0.0_p@1.0.0", "b@1.0.0"]);
+    }
+
+    #[tokio::test]
+    async fn reports_every_unresolvable_dependency() {
+        let (_server, mut config) = fixture_config(json!({
+            "a": { "1.0.0": { "dependencies": { "missing": "^1.0.0", "b": "^2.0.0" } } },
+            "b": { "1.0.0": {} },
+        }))
+        .await;
+        config.bail = false;
+
+        let manifest = json!({ "dependencies": { "a": "^1.0.0" } });
+        let Err(Error::Multiple(errors)) = resolve_deps(manifest_root(manifest), &config).await
+        else {
+            panic!("expected every error");
+        };
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn resolves_aliases() {
+        let resolved = resolve_fixture(
+            json!({
+                "a": { "1.0.0": { "dependencies": {
+                    "string-width": "^5.0.0",
+                    "string-width-cjs": "npm:string-width@^4.0.0",
+                } } },
+                "string-width": { "4.2.3": {}, "5.1.2": {} },
+            }),
+            json!({ "dependencies": { "a": "^1.0.0", "sw": "npm:string-width@^5.0.0" } }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            folder_names(&resolved),
+            ["a@1.0.0", "string-width@4.2.3", "string-width@5.1.2"]
+        );
+        assert_eq!(find(&resolved, "string-width@5.1.2").direct_names, ["sw"]);
+        let mut linked: Vec<_> = find(&resolved, "a@1.0.0")
+            .dependencies
+            .iter()
+            .map(|(name, id)| format!("{name} -> {}", get_package_folder_name(id)))
+            .collect();
+        linked.sort();
+        assert_eq!(
+            linked,
+            [
+                "string-width -> string-width@5.1.2",
+                "string-width-cjs -> string-width@4.2.3",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn explains_unresolvable_ranges() {
+        let (_server, mut config) = fixture_config(json!({
+            "a": { "1.0.0": { "dependencies": { "b": "^3" } } },
+            "b": { "1.0.0": {}, "2.0.0": {}, "2.1.0": {}, "4.0.0": {} },
+        }))
+        .await;
+        let reporter = RecordingReporter::default();
+        config.reporter = Box::new(reporter.clone());
+
+        let manifest = json!({ "dependencies": { "a": "^1.0.0" } });
+        let Err(error) = resolve_deps(manifest_root(manifest), &config).await else {
+            panic!("expected b@^3 to be unresolvable");
+        };
+        assert_eq!(
+            error.to_string(),
+            "no version of b matches ^3\n  \
+             required by root > a@1.0.0 > b@^3\n  \
+             available versions: 1.0.0, 2.0.0, 2.1.0, 4.0.0\n  \
+             closest versions: 2.1.0, 4.0.0"
+        );
+
+        let Error::NoMatchingVersion(unresolvable) = error else {
+            panic!("expected no matching version");
+        };
+        let events = reporter.0.lock().unwrap().clone();
+        assert!(events.contains(&Event::Unresolvable(*unresolvable)));
+    }
+
+    #[tokio::test]
+    async fn resolves_dist_tags() {
+        let (_server, config) = fixture_config(json!({
+            "a": { "1.0.0": { "dependencies": { "b": "canary" } } },
+            "b": {
+                "1.0.0": {},
+                "2.0.0-rc.1": {},
+                "dist-tags": { "next": "2.0.0-rc.1", "beta": "2.0.0-rc.1" },
+            },
+        }))
+        .await;
+
+        let manifest = json!({ "dependencies": { "b": "next" } });
+        let resolved = resolve_deps(manifest_root(manifest), &config)
+            .await
+            .unwrap();
+        assert_eq!(resolved[0].version.version.as_str(), "2.0.0-rc.1");
+
+        let manifest = json!({ "dependencies": { "a": "1.0.0" } });
+        let Err(error) = resolve_deps(manifest_root(manifest), &config).await else {
+            panic!("expected b@canary to be unresolvable");
+        };
+        assert_eq!(
+            error.to_string(),
+            "b has no dist-tag canary\n  \
+             required by root > a@1.0.0 > b@canary\n  \
+             available tags: beta, latest, next"
+        );
+    }
 }
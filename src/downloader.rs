@@ -1,13 +1,14 @@
+use rustc_hash::FxHashSet as HashSet;
 use std::{
-    collections::HashSet,
-    error,
-    io::ErrorKind,
-    path::{Path, PathBuf},
+    fmt,
+    io::{BufRead, ErrorKind, Read},
+    path::{Component, Path, PathBuf},
 };
 
-use async_compression::tokio::bufread::GzipDecoder;
-use derive_more::Display;
-use futures::{future::join_all, TryStreamExt};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures::TryStreamExt;
+use reqwest::Url;
+use sha2::{digest::DynDigest, Digest, Sha256, Sha384, Sha512};
 use tar::Archive;
 use tokio::{
     fs,
@@ -18,138 +19,990 @@ use tokio_util::{compat::FuturesAsyncReadCompatExt, io::SyncIoBridge};
 
 use crate::{
     config::Config,
+    error::{join_all_bail, Error, Result},
     http::get_package_tar,
-    npm::{ResolvedDependencies, UrlString, Version},
-    STORE_FOLDER,
+    npm::{NpmPackageVersion, NpmVersionDist, PackageSource, ResolvedDependencies, Version},
+    reporter::Event,
 };
 
-#[derive(Debug, Display, PartialEq)]
-pub enum Error {
-    UnpackError,
-}
-
-impl error::Error for Error {}
+/// The layout version of the store, bumped when the layout changes.
+const STORE_VERSION: &str = "v1";
 
 /// download packages to store.
-/// returns the top level package, if specified.
+/// Optional packages that fail to download are skipped with a warning.
+/// returns the packages that are in the store.
 pub async fn download_packages(
-    packages: &Vec<ResolvedDependencies>,
+    packages: Vec<ResolvedDependencies>,
     config: &Config,
-) -> anyhow::Result<Vec<ResolvedDependencies>> {
-    let mut top_level = vec![];
+) -> Result<Vec<ResolvedDependencies>> {
+    // a tarball may only be skipped if every package it is downloaded for is optional.
+    let mut required = HashSet::default();
+    for dep in packages.iter().filter(|dep| !dep.optional) {
+        required.insert(&dep.version.dist.tarball);
+    }
 
     let mut futures = Vec::new();
-    let mut downloaded = HashSet::new();
-    for dep in packages.iter() {
-        if downloaded.contains(&dep.version.dist.tarball) {
+    let mut downloaded = HashSet::default();
+    // packages from other sources are stored when they are resolved, or not stored at all.
+    let is_downloaded = |dep: &&ResolvedDependencies| {
+        matches!(
+            dep.version.source,
+            None | Some(PackageSource::Tarball { .. })
+        )
+    };
+    for dep in packages.iter().filter(is_downloaded) {
+        let tarball = &dep.version.dist.tarball;
+        if !downloaded.insert(tarball) {
             continue;
         }
 
-        if dep.is_root {
-            top_level.push(dep.to_owned());
-        }
-
-        futures.push(download_package_to_store(
-            dep.version.name.clone(),
-            dep.version.version.clone(),
-            dep.version.dist.tarball.clone(),
-            config,
-        ));
+        let optional = !required.contains(tarball);
+        futures.push(async move {
+            let result = download_package_to_store(
+                dep.version.name.clone(),
+                dep.version.version.clone(),
+                dep.version.dist.clone(),
+                config,
+            )
+            .await;
 
-        downloaded.insert(&dep.version.dist.tarball);
+            match result {
+                Err(error) if optional => {
+                    config.reporter.report(Event::Warning {
+                        message: format!("skipped optional dependency: {error}"),
+                    });
+                    Ok(Some(tarball))
+                }
+                result => result.map(|()| None),
+            }
+        });
     }
-    let _results = join_all(futures).await;
-    println!("download to store: ");
-    println!(
-        "{:?}",
-        _results
-            .iter()
-            .filter(|res| res.is_err())
-            .collect::<Vec<_>>()
-    );
+    let skipped: HashSet<_> = join_all_bail(futures, config.bail)
+        .await?
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
 
-    Ok(top_level)
+    Ok(packages
+        .into_iter()
+        .filter(|dep| !skipped.contains(&dep.version.dist.tarball))
+        .collect())
 }
 
 /// download a single package to store.
+/// packages already in the store are not downloaded again.
 pub async fn download_package_to_store(
     package_name: String,
     version: Version,
-    tar: UrlString,
+    dist: NpmVersionDist,
     config: &Config,
-) -> anyhow::Result<()> {
-    let tar_content = get_package_tar(&tar, config).await.unwrap();
-
-    let deps_dest = get_store_package_path(&package_name, &version);
-
-    let tgz = GzipDecoder::new(
-        tar_content
-            .bytes_stream()
-            .map_err(|e| io::Error::new(ErrorKind::Other, e))
-            .into_async_read()
-            .compat(),
-    );
-
-    fs::create_dir_all(&deps_dest).await.unwrap();
-
-    let mut extracted = HashSet::new();
-
-    task::spawn_blocking(move || {
-        let mut archive = Archive::new(SyncIoBridge::new(BufReader::new(tgz)));
-        let mut entries = archive.entries().unwrap();
-        while let Some(file) = &mut entries.next() {
-            match file {
-                Ok(file) => {
-                    let file_path = file.path().unwrap();
-                    let file_path = match file_path.strip_prefix("package") {
-                        Ok(path) => path.to_path_buf(),
-                        Err(_) => file_path.to_path_buf(),
-                    };
-
-                    if extracted.contains(&file_path) {
-                        continue;
-                    }
+) -> Result<()> {
+    let missing_integrity = || Error::MissingIntegrity {
+        name: package_name.clone(),
+        version: version.clone(),
+        url: dist.tarball.to_string(),
+    };
+    let digest = TarballDigest::from_dist(&dist).ok_or_else(missing_integrity)?;
+    let store_path =
+        get_store_package_path(&config.store_dir, &dist).ok_or_else(missing_integrity)?;
+    if fs::metadata(&store_path).await.is_ok() {
+        config.reporter.report(Event::Fetched {
+            name: package_name,
+            version,
+            cached: true,
+        });
+        return Ok(());
+    }
 
-                    extracted.insert(file_path.to_owned());
+    let extract_error = |source| Error::Extract {
+        name: package_name.clone(),
+        version: version.clone(),
+        url: dist.tarball.to_string(),
+        source,
+    };
 
-                    if let Some(parent) = file_path.parent() {
-                        match std::fs::create_dir_all(deps_dest.join(parent)) {
-                            Err(error) if error.kind() == ErrorKind::AlreadyExists => {}
-                            Err(error) => return Err(error),
-                            _ => {}
-                        }
-                    }
+    // the network permit is held until the package is extracted, the tarball is streamed.
+    let (tar_content, _permit): (Box<dyn io::AsyncBufRead + Send + Unpin>, _) =
+        match local_tarball_path(&dist.tarball) {
+            Some(path) => (
+                Box::new(BufReader::new(
+                    fs::File::open(path).await.map_err(extract_error)?,
+                )),
+                None,
+            ),
+            None => {
+                let (response, permit) =
+                    get_package_tar(&dist.tarball, config)
+                        .await
+                        .map_err(|source| Error::Download {
+                            name: package_name.clone(),
+                            version: version.clone(),
+                            url: dist.tarball.to_string(),
+                            source,
+                        })?;
+                (
+                    Box::new(
+                        response
+                            .bytes_stream()
+                            .map_err(|e| io::Error::new(ErrorKind::Other, e))
+                            .into_async_read()
+                            .compat(),
+                    ),
+                    Some(permit),
+                )
+            }
+        };
+    config.reporter.report(Event::Fetched {
+        name: package_name.clone(),
+        version: version.clone(),
+        cached: false,
+    });
 
-                    let dst = deps_dest.join(file_path);
-                    match file.unpack(dst) {
-                        Err(error) => {
-                            let formatted = format!("{:?}", error);
-                            println!("{formatted}");
-                            panic!("{:?}", error);
-                        }
-                        Ok(_) => {}
-                    }
-                }
-                Err(error) => {
-                    println!(
-                        "{}, {}, {}, {}",
-                        &package_name,
-                        &version,
-                        &tar,
-                        &deps_dest.to_str().unwrap_or("failed deps_dest"),
-                    );
-                    panic!("{:?}", error);
-                }
+    store_tarball(
+        tar_content,
+        &store_path,
+        &digest,
+        config,
+        extract_error,
+        |actual| Error::IntegrityMismatch {
+            name: package_name.clone(),
+            version: version.clone(),
+            url: dist.tarball.to_string(),
+            expected: digest.to_string(),
+            actual,
+        },
+    )
+    .await?;
+
+    config.reporter.report(Event::Extracted {
+        name: package_name,
+        version,
+    });
+
+    Ok(())
+}
+
+/// Unpack the package tarball `tgz` into the store at `store_path`, gzipped or not.
+/// The files are extracted next to the final location and moved in place once complete,
+/// so an interrupted download never looks like a stored package. The tarball is hashed
+/// while it is extracted, and it is only stored if it matches `digest`, otherwise
+/// `integrity_error` is called with the digest it has.
+pub(crate) async fn store_tarball(
+    tgz: impl io::AsyncBufRead + Send + Unpin + 'static,
+    store_path: &Path,
+    digest: &TarballDigest,
+    config: &Config,
+    extract_error: impl Fn(io::Error) -> Error,
+    integrity_error: impl FnOnce(String) -> Error,
+) -> Result<()> {
+    fs::create_dir_all(&config.store_dir).await?;
+    let temp_dir = tempfile::Builder::new()
+        .prefix(".tmp-")
+        .tempdir_in(&config.store_dir)?;
+
+    let deps_dest = temp_dir.path().to_path_buf();
+    let hasher = digest.algorithm.hasher();
+    let actual = task::spawn_blocking(move || extract(tgz, hasher, &deps_dest))
+        .await?
+        .map_err(extract_error)?;
+    if actual != digest.value {
+        return Err(integrity_error(
+            TarballDigest {
+                algorithm: digest.algorithm,
+                value: actual,
+            }
+            .to_string(),
+        ));
+    }
+
+    if let Some(parent) = store_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    // `temp_dir` removes the extracted files when the package was stored concurrently.
+    if let Err(error) = fs::rename(temp_dir.path(), store_path).await {
+        if fs::metadata(store_path).await.is_err() {
+            return Err(error.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Unpack the files of a package tarball into `deps_dest`, without the `package/`
+/// prefix. Files with a path escaping `deps_dest`, such as `../x` or `/x`, are an error,
+/// and so are links pointing out of it and files below a symlink of the package, which
+/// would be written wherever it points.
+/// returns the digest of the whole tarball computed by `hasher`.
+fn extract(
+    tgz: impl io::AsyncRead + Unpin,
+    hasher: Box<dyn DynDigest + Send>,
+    deps_dest: &Path,
+) -> std::io::Result<Vec<u8>> {
+    let mut tgz = HashingReader {
+        inner: SyncIoBridge::new(tgz),
+        hasher,
+    };
+    let mut archive = Archive::new(decompress(std::io::BufReader::new(&mut tgz))?);
+    let mut extracted = HashSet::default();
+
+    for file in archive.entries()? {
+        let mut file = file?;
+        let file_path = package_path(&file.path()?)?;
+        if !extracted.insert(file_path.clone()) {
+            continue;
+        }
+        if is_below_symlink(deps_dest, &file_path) {
+            return Err(outside_of_package(&file_path));
+        }
+
+        if let Some(parent) = file_path.parent() {
+            std::fs::create_dir_all(deps_dest.join(parent))?;
+        }
+
+        let entry_type = file.header().entry_type();
+        if entry_type.is_hard_link() {
+            // `unpack` would read the link name relative to the current directory.
+            let target = package_path(&link_name(&file)?)?;
+            if !extracted.contains(&target) || is_below_symlink(deps_dest, &target) {
+                return Err(outside_of_package(&file_path));
+            }
+            std::fs::hard_link(deps_dest.join(target), deps_dest.join(file_path))?;
+            continue;
+        }
+        if entry_type.is_symlink() {
+            let folder = file_path.parent().unwrap_or(Path::new(""));
+            if !stays_inside(&folder.join(link_name(&file)?)) {
+                return Err(outside_of_package(&file_path));
             }
         }
 
-        Ok(())
+        file.unpack(deps_dest.join(file_path))?;
+    }
+
+    // the digest covers the bytes after the archive as well, such as its padding.
+    drop(archive);
+    std::io::copy(&mut tgz, &mut std::io::sink())?;
+
+    Ok(tgz.hasher.finalize().to_vec())
+}
+
+/// The path of a tarball entry in the package, without the `package/` prefix.
+fn package_path(entry_path: &Path) -> std::io::Result<PathBuf> {
+    let path = entry_path
+        .strip_prefix("package")
+        .unwrap_or(entry_path)
+        .to_path_buf();
+    if path
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(outside_of_package(&path));
+    }
+
+    Ok(path)
+}
+
+fn link_name<R: Read>(file: &tar::Entry<R>) -> std::io::Result<PathBuf> {
+    match file.link_name()? {
+        Some(name) => Ok(name.into_owned()),
+        None => Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("the link {} has no target", file.path()?.display()),
+        )),
+    }
+}
+
+/// Whether `path`, relative to the package, stays in it once its `..` are applied.
+fn stays_inside(path: &Path) -> bool {
+    let mut depth = 0usize;
+    path.components().all(|component| match component {
+        Component::Normal(_) => {
+            depth += 1;
+            true
+        }
+        Component::CurDir => true,
+        Component::ParentDir => match depth.checked_sub(1) {
+            Some(parent) => {
+                depth = parent;
+                true
+            }
+            None => false,
+        },
+        Component::RootDir | Component::Prefix(_) => false,
     })
-    .await??;
+}
 
-    Ok(())
+/// Whether a folder of `path` in `deps_dest` is a symlink extracted before it.
+fn is_below_symlink(deps_dest: &Path, path: &Path) -> bool {
+    let mut folder = deps_dest.to_path_buf();
+    path.parent()
+        .into_iter()
+        .flat_map(Path::components)
+        .any(|component| {
+            folder.push(component);
+            folder.is_symlink()
+        })
+}
+
+fn outside_of_package(path: &Path) -> std::io::Error {
+    std::io::Error::new(
+        ErrorKind::InvalidData,
+        format!("{} is outside of the package", path.display()),
+    )
+}
+
+/// The content of `tarball`, gunzipped when it starts like gzip does.
+/// Registries serve gzipped tarballs, local `.tar` files are read as they are.
+fn decompress<'a>(mut tarball: impl BufRead + 'a) -> std::io::Result<Box<dyn Read + 'a>> {
+    const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+    if tarball.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(flate2::bufread::GzDecoder::new(tarball)))
+    } else {
+        Ok(Box::new(tarball))
+    }
+}
+
+/// Feeds the bytes read from `inner` to `hasher`.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Box<dyn DynDigest + Send>,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// The hash algorithms of subresource integrities that tarballs are checked against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    fn parse(algorithm: &str) -> Option<Self> {
+        match algorithm {
+            "sha1" => Some(Self::Sha1),
+            "sha256" => Some(Self::Sha256),
+            "sha384" => Some(Self::Sha384),
+            "sha512" => Some(Self::Sha512),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Sha1 => "sha1",
+            Self::Sha256 => "sha256",
+            Self::Sha384 => "sha384",
+            Self::Sha512 => "sha512",
+        }
+    }
+
+    fn hasher(self) -> Box<dyn DynDigest + Send> {
+        match self {
+            Self::Sha1 => Box::<sha1::Sha1>::default(),
+            Self::Sha256 => Box::<Sha256>::default(),
+            Self::Sha384 => Box::<Sha384>::default(),
+            Self::Sha512 => Box::<Sha512>::default(),
+        }
+    }
+}
+
+/// The digest a package tarball must have, from the `integrity` of its dist,
+/// or its `shasum` for packages published without one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TarballDigest {
+    pub algorithm: HashAlgorithm,
+    pub value: Vec<u8>,
+}
+
+impl TarballDigest {
+    /// The first supported hash of the `integrity` of `dist`, or its `shasum`.
+    /// returns None if the package has neither.
+    pub fn from_dist(dist: &NpmVersionDist) -> Option<Self> {
+        let integrity = dist
+            .integrity
+            .iter()
+            .flat_map(|integrity| integrity.split_whitespace())
+            .find_map(|hash| {
+                let (algorithm, digest) = hash.split_once('-')?;
+                let value = BASE64
+                    .decode(digest)
+                    .ok()
+                    .filter(|digest| digest.len() > 1)?;
+                Some(Self {
+                    algorithm: HashAlgorithm::parse(algorithm)?,
+                    value,
+                })
+            });
+
+        integrity.or_else(|| {
+            Some(Self {
+                algorithm: HashAlgorithm::Sha1,
+                value: from_hex(&dist.shasum).filter(|digest| digest.len() > 1)?,
+            })
+        })
+    }
+}
+
+impl fmt::Display for TarballDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.algorithm.name(),
+            BASE64.encode(&self.value)
+        )
+    }
+}
+
+/// The path of a tarball on disk, for `file:` urls.
+fn local_tarball_path(tarball: &str) -> Option<PathBuf> {
+    let url = Url::parse(tarball).ok()?;
+    if url.scheme() == "file" {
+        url.to_file_path().ok()
+    } else {
+        None
+    }
+}
+
+/// Read the package.json of the gzipped package tarball `tgz`.
+/// returns None if the tarball has none.
+pub fn read_tarball_manifest(tgz: impl Read) -> std::io::Result<Option<Vec<u8>>> {
+    let mut archive = Archive::new(decompress(std::io::BufReader::new(tgz))?);
+
+    for file in archive.entries()? {
+        let mut file = file?;
+        let file_path = file.path()?.to_path_buf();
+        let file_path = file_path.strip_prefix("package").unwrap_or(&file_path);
+
+        if file_path == Path::new("package.json") {
+            let mut manifest = vec![];
+            file.read_to_end(&mut manifest)?;
+            return Ok(Some(manifest));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The `sha512` subresource integrity of `content`, as registries publish it.
+pub fn sha512_integrity(content: &[u8]) -> String {
+    format!("sha512-{}", BASE64.encode(Sha512::digest(content)))
+}
+
+/// The folder of a package in the content-addressable store, derived from the
+/// `integrity` of its tarball, or its `shasum` for packages published without one.
+/// returns None if the package has neither.
+pub fn get_store_package_path(store_dir: &Path, dist: &NpmVersionDist) -> Option<PathBuf> {
+    let digest = TarballDigest::from_dist(dist)?;
+    let hex = to_hex(&digest.value);
+
+    Some(
+        store_dir
+            .join(STORE_VERSION)
+            .join(digest.algorithm.name())
+            .join(&hex[..2])
+            .join(&hex[2..]),
+    )
+}
+
+/// The folder the files of a package are linked from, in the store or on disk
+/// for local packages.
+/// returns None for tarballs without integrity or shasum.
+pub fn get_package_store_path(store_dir: &Path, package: &NpmPackageVersion) -> Option<PathBuf> {
+    match &package.source {
+        Some(PackageSource::Git { commit, .. }) => Some(get_git_store_path(store_dir, commit)),
+        Some(PackageSource::Directory { path } | PackageSource::Link { path }) => {
+            Some(path.clone())
+        }
+        Some(PackageSource::Tarball { .. } | PackageSource::Remote { .. }) | None => {
+            get_store_package_path(store_dir, &package.dist)
+        }
+    }
+}
+
+/// The file of the store recording the integrity of the tarball downloaded from `url`.
+pub fn get_remote_index_path(store_dir: &Path, url: &str) -> PathBuf {
+    store_dir
+        .join(STORE_VERSION)
+        .join("remote")
+        .join(hash_key(url))
+}
+
+/// The sha256 of `value` as hex, to name files and folders after. Names of the store
+/// identify what they hold, so the hash must not collide like `Hasher`s do.
+pub(crate) fn hash_key(value: &str) -> String {
+    to_hex(&Sha256::digest(value.as_bytes()))
+}
+
+/// The folder of a packed commit of a git repository in the store.
+pub fn get_git_store_path(store_dir: &Path, commit: &str) -> PathBuf {
+    let (prefix, rest) = commit.split_at(2.min(commit.len()));
+    store_dir
+        .join(STORE_VERSION)
+        .join("git")
+        .join(prefix)
+        .join(rest)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
 }
 
-pub fn get_store_package_path(package_name: &String, version: &Version) -> PathBuf {
-    Path::new(STORE_FOLDER).join(format!("{}@{}", &package_name, &version))
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hash::FxHashMap as HashMap;
+
+    use super::*;
+    use crate::{
+        npmrc::Npmrc,
+        reporter::RecordingReporter,
+        test_registry::{TestRegistry, TestResponse},
+    };
+
+    fn dist(tarball: &str, shasum: &str, integrity: Option<&str>) -> NpmVersionDist {
+        serde_json::from_value(serde_json::json!({
+            "tarball": tarball,
+            "shasum": shasum,
+            "integrity": integrity,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn store_path_from_integrity() {
+        let store_dir = Path::new("/store");
+
+        assert_eq!(
+            get_store_package_path(
+                store_dir,
+                &dist("https://registry/a.tgz", "76b5055f", Some("sha512-AAEC"))
+            )
+            .unwrap(),
+            Path::new("/store/v1/sha512/00/0102")
+        );
+        assert_eq!(
+            get_store_package_path(store_dir, &dist("https://registry/a.tgz", "76B5055F", None))
+                .unwrap(),
+            Path::new("/store/v1/sha1/76/b5055f")
+        );
+        assert!(
+            get_store_package_path(store_dir, &dist("https://registry/a.tgz", "", None)).is_none()
+        );
+    }
+
+    #[test]
+    fn remote_index_path_from_the_url() {
+        // the sha256 of `https://example.com/a.tgz`.
+        assert_eq!(
+            get_remote_index_path(Path::new("/store"), "https://example.com/a.tgz"),
+            Path::new(
+                "/store/v1/remote/1c1362bc472e2b4515b74af18abb60dd396e0bea55e6650fce6816f9d9890a41"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn reuses_stored_packages() {
+        let tarball = TestResponse::tarball(&[
+            ("package.json", r#"{"name":"is-odd","version":"1.0.0"}"#),
+            ("lib/index.js", "module.exports = n => n % 2 === 1;"),
+        ]);
+        let shasum = to_hex(&sha1::Sha1::digest(&tarball.body));
+        let server = TestRegistry::with_routes(HashMap::from_iter([(
+            String::from("/is-odd/-/is-odd-1.0.0.tgz"),
+            tarball,
+        )]))
+        .await;
+
+        let store_dir = tempfile::tempdir().unwrap();
+        let mut config = Config::from_npmrc(
+            Some(server.url.clone()),
+            Some(store_dir.path().to_path_buf()),
+            Npmrc::default(),
+        )
+        .unwrap();
+        let reporter = RecordingReporter::default();
+        config.reporter = Box::new(reporter.clone());
+        let dist = dist(
+            &format!("{}is-odd/-/is-odd-1.0.0.tgz", server.url),
+            &shasum,
+            None,
+        );
+
+        for _ in 0..2 {
+            download_package_to_store(
+                String::from("is-odd"),
+                Version::new(String::from("1.0.0")),
+                dist.clone(),
+                &config,
+            )
+            .await
+            .unwrap();
+        }
+
+        let store_path = get_store_package_path(store_dir.path(), &dist).unwrap();
+        assert!(store_path.join("package.json").exists());
+        assert!(store_path.join("lib/index.js").exists());
+        assert_eq!(server.requests().len(), 1);
+
+        let name = String::from("is-odd");
+        let version = Version::new(String::from("1.0.0"));
+        assert_eq!(
+            *reporter.0.lock().unwrap(),
+            [
+                Event::Fetched {
+                    name: name.clone(),
+                    version: version.clone(),
+                    cached: false
+                },
+                Event::Extracted {
+                    name: name.clone(),
+                    version: version.clone()
+                },
+                Event::Fetched {
+                    name,
+                    version,
+                    cached: true
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_broken_tarballs() {
+        let server = TestRegistry::with_routes(HashMap::from_iter([(
+            String::from("/is-odd/-/is-odd-1.0.0.tgz"),
+            TestResponse {
+                status: 200,
+                body: b"not a tarball".to_vec(),
+            },
+        )]))
+        .await;
+
+        let store_dir = tempfile::tempdir().unwrap();
+        let config = Config::from_npmrc(
+            Some(server.url.clone()),
+            Some(store_dir.path().to_path_buf()),
+            Npmrc::default(),
+        )
+        .unwrap();
+        let download = |path: &str| {
+            download_package_to_store(
+                String::from("is-odd"),
+                Version::new(String::from("1.0.0")),
+                dist(
+                    &format!("{}{path}", server.url),
+                    "e0432a7379f2d20b6ebbc2cb11e69beaaf31cd63",
+                    None,
+                ),
+                &config,
+            )
+        };
+
+        let error = download("is-odd/-/is-odd-1.0.0.tgz").await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "failed to extract is-odd@1.0.0 from {}is-odd/-/is-odd-1.0.0.tgz",
+                server.url
+            )
+        );
+        assert!(matches!(
+            download("missing.tgz").await,
+            Err(Error::Download { name, .. }) if name == "is-odd"
+        ));
+        assert!(!store_dir.path().join(STORE_VERSION).exists());
+    }
+
+    #[tokio::test]
+    async fn extracts_uncompressed_tarballs() {
+        let mut archive = tar::Builder::new(vec![]);
+        let manifest = br#"{"name":"is-odd","version":"1.0.0"}"#;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest.len() as u64);
+        header.set_mode(0o644);
+        archive
+            .append_data(&mut header, "package/package.json", &manifest[..])
+            .unwrap();
+        let tar = archive.into_inner().unwrap();
+        assert_eq!(
+            read_tarball_manifest(&tar[..]).unwrap().as_deref(),
+            Some(&manifest[..])
+        );
+
+        let store_dir = tempfile::tempdir().unwrap();
+        let config =
+            Config::from_npmrc(None, Some(store_dir.path().to_path_buf()), Npmrc::default())
+                .unwrap();
+        let integrity = sha512_integrity(&tar);
+        let dist = dist("file:///is-odd-1.0.0.tar", "", Some(&integrity));
+        let digest = TarballDigest::from_dist(&dist).unwrap();
+        let store_path = get_store_package_path(store_dir.path(), &dist).unwrap();
+
+        store_tarball(
+            std::io::Cursor::new(tar),
+            &store_path,
+            &digest,
+            &config,
+            Error::Io,
+            |actual| panic!("unexpected digest {actual}"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            std::fs::read(store_path.join("package.json")).unwrap(),
+            manifest
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_files_outside_of_the_package() {
+        // `tar::Builder` refuses such paths, so the header is written by hand.
+        let mut archive = tar::Builder::new(flate2::write::GzEncoder::new(
+            vec![],
+            flate2::Compression::default(),
+        ));
+        let content = b"pwned";
+        let mut header = tar::Header::new_gnu();
+        let name = b"package/../../escaped.js";
+        header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name);
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        archive.append(&header, &content[..]).unwrap();
+        let body = archive.into_inner().unwrap().finish().unwrap();
+        let integrity = sha512_integrity(&body);
+        let server = TestRegistry::with_routes(HashMap::from_iter([(
+            String::from("/is-odd/-/is-odd-1.0.0.tgz"),
+            TestResponse { status: 200, body },
+        )]))
+        .await;
+
+        let root = tempfile::tempdir().unwrap();
+        let store_dir = root.path().join("a/b/store");
+        let config = Config::from_npmrc(
+            Some(server.url.clone()),
+            Some(store_dir.clone()),
+            Npmrc::default(),
+        )
+        .unwrap();
+
+        let result = download_package_to_store(
+            String::from("is-odd"),
+            Version::new(String::from("1.0.0")),
+            dist(
+                &format!("{}is-odd/-/is-odd-1.0.0.tgz", server.url),
+                "",
+                Some(&integrity),
+            ),
+            &config,
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Extract { .. })));
+        assert!(!root.path().join("a/b/escaped.js").exists());
+        assert!(!store_dir.join("escaped.js").exists());
+        assert!(!store_dir.join(STORE_VERSION).exists());
+    }
+
+    #[tokio::test]
+    async fn rejects_links_outside_of_the_package() {
+        let outside = tempfile::tempdir().unwrap();
+        let secret = outside.path().join("secret");
+        std::fs::write(&secret, "secret").unwrap();
+        let extract_entries = |entries: Vec<(&'static str, Option<(tar::EntryType, PathBuf)>)>| {
+            let mut archive = tar::Builder::new(vec![]);
+            for (path, link) in entries {
+                let mut header = tar::Header::new_gnu();
+                header.set_mode(0o644);
+                match link {
+                    Some((entry_type, target)) => {
+                        header.set_entry_type(entry_type);
+                        header.set_size(0);
+                        archive.append_link(&mut header, path, target).unwrap();
+                    }
+                    None => {
+                        header.set_size(4);
+                        archive
+                            .append_data(&mut header, path, &b"code"[..])
+                            .unwrap();
+                    }
+                }
+            }
+            let tarball = std::io::Cursor::new(archive.into_inner().unwrap());
+            let deps_dest = tempfile::tempdir().unwrap();
+
+            async move {
+                let dest = deps_dest.path().to_path_buf();
+                let result = task::spawn_blocking(move || {
+                    extract(tarball, HashAlgorithm::Sha512.hasher(), &dest)
+                })
+                .await
+                .unwrap();
+                (result.map(drop), deps_dest)
+            }
+        };
+        let symlink = |target: &Path| Some((tar::EntryType::Symlink, target.to_path_buf()));
+        let hard_link = |target: &Path| Some((tar::EntryType::Link, target.to_path_buf()));
+
+        for entries in [
+            vec![
+                ("package/lib", symlink(outside.path())),
+                ("package/lib/x.js", None),
+            ],
+            vec![
+                ("package/lib", symlink(Path::new("../.."))),
+                ("package/lib/x.js", None),
+            ],
+            // the symlink stays in the package, but the files below it are written where it points.
+            vec![
+                ("package/lib", symlink(Path::new("src"))),
+                ("package/lib/x.js", None),
+            ],
+            vec![("package/x.js", hard_link(&secret))],
+            vec![("package/x.js", hard_link(Path::new("package/../../secret")))],
+        ] {
+            let (result, _) = extract_entries(entries).await;
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+        }
+        assert_eq!(
+            std::fs::read_dir(outside.path()).unwrap().count(),
+            1,
+            "a file was written outside of the package"
+        );
+
+        let (result, deps_dest) = extract_entries(vec![
+            ("package/src/index.js", None),
+            ("package/bin/cli.js", symlink(Path::new("../src/index.js"))),
+            (
+                "package/copy.js",
+                hard_link(Path::new("package/src/index.js")),
+            ),
+        ])
+        .await;
+        result.unwrap();
+        for path in ["bin/cli.js", "copy.js"] {
+            assert_eq!(std::fs::read(deps_dest.path().join(path)).unwrap(), b"code");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_tarballs_not_matching_their_integrity() {
+        let published = TestResponse::tarball(&[("package.json", r#"{"name":"is-odd"}"#)]);
+        let integrity = sha512_integrity(&published.body);
+        let server = TestRegistry::with_routes(HashMap::from_iter([(
+            String::from("/is-odd/-/is-odd-1.0.0.tgz"),
+            TestResponse::tarball(&[
+                ("package.json", r#"{"name":"is-odd"}"#),
+                ("index.js", "require('child_process')"),
+            ]),
+        )]))
+        .await;
+
+        let store_dir = tempfile::tempdir().unwrap();
+        let config = Config::from_npmrc(
+            Some(server.url.clone()),
+            Some(store_dir.path().to_path_buf()),
+            Npmrc::default(),
+        )
+        .unwrap();
+        let dist = dist(
+            &format!("{}is-odd/-/is-odd-1.0.0.tgz", server.url),
+            "",
+            Some(&integrity),
+        );
+
+        let result = download_package_to_store(
+            String::from("is-odd"),
+            Version::new(String::from("1.0.0")),
+            dist.clone(),
+            &config,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(Error::IntegrityMismatch { expected, .. }) if expected == integrity
+        ));
+        let store_path = get_store_package_path(store_dir.path(), &dist).unwrap();
+        assert!(!store_path.exists());
+    }
+
+    #[tokio::test]
+    async fn skips_failed_optional_packages() {
+        let server =
+            TestRegistry::with_packages(serde_json::json!({ "is-odd": { "1.0.0": {} } })).await;
+        let store_dir = tempfile::tempdir().unwrap();
+        let mut config = Config::from_npmrc(
+            Some(server.url.clone()),
+            Some(store_dir.path().to_path_buf()),
+            Npmrc::default(),
+        )
+        .unwrap();
+        let reporter = RecordingReporter::default();
+        config.reporter = Box::new(reporter.clone());
+
+        let is_odd = crate::http::get_npm_package("is-odd", &config)
+            .await
+            .unwrap();
+        let package = |name: &str, optional: bool| {
+            let version: crate::npm::NpmPackageVersion = match name {
+                "is-odd" => is_odd.versions[0].clone(),
+                _ => serde_json::from_value(serde_json::json!({
+                    "name": name,
+                    "version": "1.0.0",
+                    "dist": {
+                        "tarball": format!("{}{name}/-/{name}-1.0.0.tgz", server.url),
+                        "shasum": format!("{:040x}", name.len()),
+                    },
+                }))
+                .unwrap(),
+            };
+            let direct_names = vec![name.to_string()];
+            ResolvedDependencies::new(
+                (&version).into(),
+                version,
+                vec![],
+                direct_names,
+                optional,
+                false,
+            )
+        };
+
+        let installed = download_packages(
+            vec![package("is-odd", false), package("fsevents", true)],
+            &config,
+        )
+        .await
+        .unwrap();
+        assert_eq!(installed, [package("is-odd", false)]);
+        assert!(reporter.0.lock().unwrap().iter().any(|event| matches!(
+            event,
+            Event::Warning { message } if message.starts_with("skipped optional dependency")
+        )));
+
+        assert!(download_packages(vec![package("fsevents", false)], &config)
+            .await
+            .is_err());
+    }
 }
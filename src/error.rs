@@ -0,0 +1,292 @@
+use std::{fmt, future::Future, io, path::PathBuf};
+
+use futures::future::{join_all, try_join_all};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::npm::{Version, VersionRangeSpecifier};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything that can make an install fail.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("invalid url {url}")]
+    InvalidUrl {
+        url: String,
+        #[source]
+        source: url::ParseError,
+    },
+
+    #[error("failed to fetch {name} from {url}")]
+    Fetch {
+        name: String,
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    #[error("{name} was not found at {url}")]
+    PackageNotFound { name: String, url: String },
+
+    #[error("failed to fetch {name} from {url}: {status}")]
+    HttpStatus {
+        name: String,
+        url: String,
+        status: reqwest::StatusCode,
+    },
+
+    #[error("invalid metadata for {name} from {url}")]
+    InvalidMetadata {
+        name: String,
+        url: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("{0}")]
+    NoMatchingVersion(Box<UnresolvableRange>),
+
+    #[error("failed to resolve {url}: {reason}")]
+    Git { url: String, reason: String },
+
+    #[error("invalid local package {}: {reason}", path.display())]
+    LocalPackage { path: PathBuf, reason: String },
+
+    #[error("invalid tarball {url}: {reason}")]
+    RemoteTarball { url: String, reason: String },
+
+    #[error("the prepare script of {name} failed: {reason}")]
+    Prepare { name: String, reason: String },
+
+    #[error("failed to download {name}@{version} from {url}")]
+    Download {
+        name: String,
+        version: Version,
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    #[error("failed to extract {name}@{version} from {url}")]
+    Extract {
+        name: String,
+        version: Version,
+        url: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("{name}@{version} from {url} has no integrity or shasum")]
+    MissingIntegrity {
+        name: String,
+        version: Version,
+        url: String,
+    },
+
+    #[error(
+        "{name}@{version} from {url} does not match its integrity {expected}, it has {actual}"
+    )]
+    IntegrityMismatch {
+        name: String,
+        version: Version,
+        url: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("failed to link {name}@{version} at {}", path.display())]
+    Link {
+        name: String,
+        version: Version,
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to locate manifest file")]
+    ManifestNotFound,
+
+    #[error("invalid manifest {}: {reason}", path.display())]
+    InvalidManifest { path: PathBuf, reason: String },
+
+    #[error("{0} is not a dependency of this project")]
+    DependencyNotFound(String),
+
+    #[error("node_modules is not installed from package.json, run mnpm install first")]
+    OutdatedInstall,
+
+    #[error("dedupe would change the installed packages:\n{}", format_changes(.added, .removed))]
+    DedupeChanges {
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Task(#[from] tokio::task::JoinError),
+
+    #[error("{} errors occurred:\n{}", .0.len(), format_errors(.0))]
+    Multiple(Vec<Error>),
+}
+
+impl Error {
+    /// Combine `errors`, unwrapping a single error.
+    pub fn from_errors(mut errors: Vec<Error>) -> Self {
+        if errors.len() == 1 {
+            errors.remove(0)
+        } else {
+            Self::Multiple(errors)
+        }
+    }
+}
+
+fn format_errors(errors: &[Error]) -> String {
+    errors
+        .iter()
+        .map(|error| {
+            let mut message = format!("  - {error}");
+            let mut source = std::error::Error::source(error);
+            while let Some(error) = source {
+                message.push_str(&format!(": {error}"));
+                source = error.source();
+            }
+            message
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn format_changes(added: &[String], removed: &[String]) -> String {
+    let added = added.iter().map(|package| format!("  + {package}"));
+    let removed = removed.iter().map(|package| format!("  - {package}"));
+
+    added.chain(removed).collect::<Vec<_>>().join("\n")
+}
+
+/// Why no version of a package matches a requested range.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UnresolvableRange {
+    pub name: String,
+    pub range: VersionRangeSpecifier,
+    /// Every version of the package, lowest first.
+    pub available: Vec<Version>,
+    /// The versions nearest to the range, below and above it.
+    pub closest: Vec<Version>,
+    /// The dist-tags of the package, sorted, when the range is a tag it does not have.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// The packages that led to the range, from the project to the range itself,
+    /// such as `root`, `a@1.0.0` and `b@^3`.
+    pub path: Vec<String>,
+}
+
+/// The number of versions listed in messages, the newest ones.
+const LISTED_VERSIONS: usize = 20;
+
+impl fmt::Display for UnresolvableRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |versions: &[Version]| {
+            versions
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        if self.tags.is_empty() {
+            write!(f, "no version of {} matches {}", self.name, *self.range)?;
+        } else {
+            write!(f, "{} has no dist-tag {}", self.name, *self.range)?;
+        }
+        if !self.path.is_empty() {
+            write!(f, "\n  required by {}", self.path.join(" > "))?;
+        }
+        if !self.tags.is_empty() {
+            return write!(f, "\n  available tags: {}", self.tags.join(", "));
+        }
+
+        let hidden = self.available.len().saturating_sub(LISTED_VERSIONS);
+        if self.available.is_empty() {
+            write!(f, "\n  no version is available")?;
+        } else if hidden == 0 {
+            write!(f, "\n  available versions: {}", join(&self.available))?;
+        } else {
+            write!(
+                f,
+                "\n  available versions: {} and {hidden} older ones",
+                join(&self.available[hidden..])
+            )?;
+        }
+        if !self.closest.is_empty() {
+            write!(f, "\n  closest versions: {}", join(&self.closest))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Await all `futures`.
+/// With `bail`, fail as soon as one fails. Otherwise wait for all of them
+/// and report every error.
+pub async fn join_all_bail<T>(
+    futures: impl IntoIterator<Item = impl Future<Output = Result<T>>>,
+    bail: bool,
+) -> Result<Vec<T>> {
+    if bail {
+        return try_join_all(futures).await;
+    }
+
+    let mut values = vec![];
+    let mut errors = vec![];
+    for result in join_all(futures).await {
+        match result {
+            Ok(value) => values.push(value),
+            Err(error) => errors.push(error),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(values)
+    } else {
+        Err(Error::from_errors(errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn not_found(name: &str) -> Error {
+        Error::DependencyNotFound(name.to_string())
+    }
+
+    #[tokio::test]
+    async fn bails_on_first_error() {
+        let futures = [
+            Box::pin(async { Err(not_found("a")) }),
+            Box::pin(async { Ok(1) }) as std::pin::Pin<Box<dyn Future<Output = Result<i32>>>>,
+        ];
+
+        let error = join_all_bail(futures, true).await.unwrap_err();
+        assert!(matches!(error, Error::DependencyNotFound(name) if name == "a"));
+    }
+
+    #[tokio::test]
+    async fn collects_every_error() {
+        let futures = ["a", "b", "c"].map(|name| async move {
+            match name {
+                "b" => Ok(name),
+                _ => Err(not_found(name)),
+            }
+        });
+
+        let error = join_all_bail(futures, false).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "2 errors occurred:\n  - a is not a dependency of this project\n  - c is not a dependency of this project"
+        );
+    }
+}
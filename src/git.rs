@@ -0,0 +1,553 @@
+use rustc_hash::FxHashMap as HashMap;
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{Arc, OnceLock, PoisonError},
+};
+
+use serde_json::{json, Value};
+use tokio::{fs, process::Command, sync::Mutex};
+
+use crate::{
+    config::Config,
+    downloader::{get_git_store_path, hash_key},
+    error::{Error, Result},
+    npm::{NpmPackageVersion, PackageSource},
+    reporter::Event,
+};
+
+/// The clones of the repositories of git dependencies, inside the store.
+const GIT_CACHE_FOLDER: &str = "git";
+
+/// Git refuses concurrent fetches of the same repository, so each clone is updated by
+/// one resolution at a time, keyed by its path.
+static FETCH_LOCKS: OnceLock<std::sync::Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>> = OnceLock::new();
+
+/// A dependency on a git repository, such as `git+https://host/repo.git#v1.0.0`,
+/// `git+ssh://git@host/repo.git` or `github:user/repo#main`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GitSpec {
+    /// The url git clones, without the `git+` prefix.
+    pub url: String,
+    /// The branch, tag or commit to check out, `HEAD` when missing.
+    pub committish: Option<String>,
+}
+
+impl GitSpec {
+    /// Parse `spec`, returns None if it is not a git dependency.
+    /// Urls starting with `-` are not git dependencies, git would read them as options.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (url, committish) = match spec.split_once('#') {
+            Some((url, committish)) => (url, Some(committish).filter(|c| !c.is_empty())),
+            None => (spec, None),
+        };
+
+        let url = if let Some(repo) = url.strip_prefix("github:") {
+            format!("https://github.com/{}.git", repo.trim_end_matches(".git"))
+        } else if let Some(url) = url.strip_prefix("git+") {
+            url.to_string()
+        } else if url.starts_with("git://") {
+            url.to_string()
+        } else {
+            return None;
+        };
+        if url.starts_with('-') {
+            return None;
+        }
+
+        Some(Self {
+            url,
+            committish: committish.map(str::to_string),
+        })
+    }
+}
+
+/// Resolve `spec` to a commit and pack that commit into the store, once its `prepare`
+/// script ran. The repository is cloned into the store the first time and fetched again
+/// by later resolutions, unless they request a commit that was already fetched.
+/// `name` is the name of the package when its package.json has none.
+pub async fn resolve_git(name: &str, spec: &GitSpec, config: &Config) -> Result<NpmPackageVersion> {
+    let git_error = |reason| Error::Git {
+        url: spec.url.clone(),
+        reason,
+    };
+
+    let committish = spec.committish.as_deref().unwrap_or("HEAD");
+    // git would read it as an option.
+    if committish.starts_with('-') {
+        return Err(git_error(format!(
+            "{committish} is not a branch, tag or commit"
+        )));
+    }
+
+    let repo = fetch_repository(spec, config).await?;
+    let commit = git(
+        &repo,
+        &["rev-parse", "--verify", &format!("{committish}^{{commit}}")],
+    )
+    .await
+    .map_err(|_| git_error(format!("{committish} is not a branch, tag or commit")))?;
+
+    let store_path = get_git_store_path(&config.store_dir, &commit);
+    let cached = fs::metadata(&store_path).await.is_ok();
+    if !cached {
+        pack(name, &repo, &commit, &store_path, config).await?;
+    }
+
+    let manifest = fs::read(store_path.join("package.json"))
+        .await
+        .map_err(|error| git_error(format!("failed to read package.json, {error}")))?;
+    let manifest = serde_json::from_slice(&manifest)
+        .map_err(|error| git_error(format!("invalid package.json, {error}")))?;
+    let version = NpmPackageVersion::from_manifest(
+        manifest,
+        name,
+        &format!("git.{commit}"),
+        json!({ "shasum": "", "tarball": format!("{}#{commit}", spec.url) }),
+        PackageSource::Git {
+            url: spec.url.clone(),
+            commit: commit.clone(),
+        },
+    )
+    .map_err(git_error)?;
+
+    config.reporter.report(Event::Fetched {
+        name: version.name.clone(),
+        version: version.version.clone(),
+        cached,
+    });
+
+    Ok(version)
+}
+
+/// Clone the repository of `spec` into the store, or fetch it if it was cloned before.
+/// returns the path of the clone.
+async fn fetch_repository(spec: &GitSpec, config: &Config) -> Result<PathBuf> {
+    let git_error = |reason| Error::Git {
+        url: spec.url.clone(),
+        reason,
+    };
+    let cache_dir = config.store_dir.join(GIT_CACHE_FOLDER);
+    let repo = cache_dir.join(cache_key(&spec.url));
+
+    let lock = fetch_lock(&repo);
+    let _lock = lock.lock().await;
+    if fs::metadata(&repo).await.is_ok() {
+        let fetched = match &spec.committish {
+            Some(commit) if is_commit_hash(commit) => {
+                git(&repo, &["cat-file", "-e", &format!("{commit}^{{commit}}")])
+                    .await
+                    .is_ok()
+            }
+            _ => false,
+        };
+        if !fetched {
+            let _permit = config
+                .network
+                .acquire()
+                .await
+                .expect("the semaphore is never closed");
+            git(&repo, &["fetch", "--quiet", "--prune", "--force", "origin"])
+                .await
+                .map_err(git_error)?;
+        }
+
+        return Ok(repo);
+    }
+
+    // cloned next to the final location and moved in place once complete,
+    // so an interrupted clone is never used.
+    fs::create_dir_all(&cache_dir).await?;
+    let temp_dir = tempfile::Builder::new()
+        .prefix(".tmp-")
+        .tempdir_in(&cache_dir)?;
+    let temp_path = temp_dir.path().to_string_lossy().to_string();
+    let _permit = config
+        .network
+        .acquire()
+        .await
+        .expect("the semaphore is never closed");
+    git(
+        &cache_dir,
+        &["clone", "--quiet", "--mirror", "--", &spec.url, &temp_path],
+    )
+    .await
+    .map_err(git_error)?;
+    fs::rename(temp_dir.path(), &repo).await?;
+
+    Ok(repo)
+}
+
+/// The lock of the clone at `repo`.
+fn fetch_lock(repo: &Path) -> Arc<Mutex<()>> {
+    let mut locks = FETCH_LOCKS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+
+    locks.entry(repo.to_path_buf()).or_default().clone()
+}
+
+/// Check `commit` out of `repo`, run its `prepare` script and move it to `store_path`,
+/// without its git folder and dependencies.
+async fn pack(
+    name: &str,
+    repo: &Path,
+    commit: &str,
+    store_path: &Path,
+    config: &Config,
+) -> Result<()> {
+    let git_error = |reason| Error::Git {
+        url: repo.display().to_string(),
+        reason,
+    };
+
+    fs::create_dir_all(&config.store_dir).await?;
+    let checkout = tempfile::Builder::new()
+        .prefix(".tmp-")
+        .tempdir_in(&config.store_dir)?;
+    let checkout_path = checkout.path().to_string_lossy().to_string();
+    let repo_path = repo.to_string_lossy().to_string();
+    git(
+        &config.store_dir,
+        &[
+            "clone",
+            "--quiet",
+            "--no-checkout",
+            "--",
+            &repo_path,
+            &checkout_path,
+        ],
+    )
+    .await
+    .map_err(git_error)?;
+    git(checkout.path(), &["checkout", "--quiet", commit])
+        .await
+        .map_err(git_error)?;
+
+    prepare(name, checkout.path()).await?;
+
+    for folder in [".git", "node_modules"] {
+        match fs::remove_dir_all(checkout.path().join(folder)).await {
+            Err(error) if error.kind() != ErrorKind::NotFound => return Err(error.into()),
+            _ => {}
+        }
+    }
+
+    if let Some(parent) = store_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    // `checkout` removes the files when the commit was packed concurrently.
+    if let Err(error) = fs::rename(checkout.path(), store_path).await {
+        if fs::metadata(store_path).await.is_err() {
+            return Err(error.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Run the `prepare` script of the package checked out at `dir`, if it has one.
+/// Its dependencies and devDependencies are not installed first, so scripts that need
+/// them, such as a `tsc` build, fail and the failure says so.
+async fn prepare(name: &str, dir: &Path) -> Result<()> {
+    let prepare_error = |reason| Error::Prepare {
+        name: name.to_string(),
+        reason,
+    };
+
+    let manifest: Value = match fs::read(dir.join("package.json")).await {
+        Ok(manifest) => serde_json::from_slice(&manifest)
+            .map_err(|error| prepare_error(format!("invalid package.json, {error}")))?,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error.into()),
+    };
+    let Some(script) = manifest.pointer("/scripts/prepare").and_then(Value::as_str) else {
+        return Ok(());
+    };
+
+    let output = Command::new("sh")
+        .args(["-c", script])
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .output()
+        .await?;
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let has_dependencies = ["/dependencies", "/devDependencies"].iter().any(|field| {
+        manifest
+            .pointer(field)
+            .and_then(Value::as_object)
+            .map_or(false, |deps| !deps.is_empty())
+    });
+    let reason = failure_reason(&output);
+    Err(prepare_error(if has_dependencies {
+        format!("{reason}, the dependencies of git packages are not installed before prepare")
+    } else {
+        reason
+    }))
+}
+
+/// Run git in `dir`, returns its output or why it failed.
+async fn git(dir: &Path, args: &[&str]) -> std::result::Result<String, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        // fail rather than wait for credentials.
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|error| format!("failed to run git, {error}"))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Err(failure_reason(&output))
+    }
+}
+
+fn failure_reason(output: &std::process::Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    match stderr.trim() {
+        "" => output.status.to_string(),
+        stderr => stderr.to_string(),
+    }
+}
+
+fn is_commit_hash(committish: &str) -> bool {
+    committish.len() == 40 && committish.chars().all(|char| char.is_ascii_hexdigit())
+}
+
+/// The folder of the clone of `url`, readable and unique.
+fn cache_key(url: &str) -> String {
+    let readable: String = url
+        .rsplit('/')
+        .find(|segment| !segment.is_empty())
+        .unwrap_or_default()
+        .trim_end_matches(".git")
+        .chars()
+        .map(|char| {
+            if char.is_ascii_alphanumeric() {
+                char
+            } else {
+                '-'
+            }
+        })
+        .collect();
+
+    format!("{readable}-{}", hash_key(url))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+    use crate::{npmrc::Npmrc, reporter::RecordingReporter};
+
+    fn run_git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed");
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    /// A bare repository with a commit per `(version, prepare script)`,
+    /// each tagged `v<version>`. returns the url of the repository.
+    fn bare_repository(dir: &Path, commits: &[(&str, Option<&str>)]) -> String {
+        let work = dir.join("work");
+        std::fs::create_dir_all(&work).unwrap();
+        run_git(&work, &["init", "--quiet", "--initial-branch=main"]);
+        for (version, prepare) in commits {
+            let mut manifest = json!({ "name": "forked", "version": version });
+            if let Some(prepare) = prepare {
+                manifest["scripts"] = json!({ "prepare": prepare });
+            }
+            std::fs::write(work.join("package.json"), manifest.to_string()).unwrap();
+            std::fs::write(
+                work.join("index.js"),
+                format!("module.exports = '{version}';"),
+            )
+            .unwrap();
+            run_git(&work, &["add", "."]);
+            run_git(&work, &["commit", "--quiet", "-m", version]);
+            run_git(&work, &["tag", &format!("v{version}")]);
+        }
+
+        let bare = dir.join("forked.git");
+        run_git(
+            dir,
+            &["clone", "--quiet", "--bare", "work", bare.to_str().unwrap()],
+        );
+        format!("file://{}", bare.display())
+    }
+
+    #[test]
+    fn parses_git_specs() {
+        let parse = |spec: &str| GitSpec::parse(spec).map(|spec| (spec.url, spec.committish));
+        let spec = |url: &str, committish: Option<&str>| {
+            Some((url.to_string(), committish.map(str::to_string)))
+        };
+
+        assert_eq!(
+            parse("git+https://example.com/forked.git#v1.0.0"),
+            spec("https://example.com/forked.git", Some("v1.0.0"))
+        );
+        assert_eq!(
+            parse("git+ssh://git@example.com/forked.git"),
+            spec("ssh://git@example.com/forked.git", None)
+        );
+        assert_eq!(
+            parse("git+file:///repos/forked.git#"),
+            spec("file:///repos/forked.git", None)
+        );
+        assert_eq!(
+            parse("github:user/forked#main"),
+            spec("https://github.com/user/forked.git", Some("main"))
+        );
+        assert_eq!(
+            parse("git://example.com/forked.git"),
+            spec("git://example.com/forked.git", None)
+        );
+        assert_eq!(parse("^1.0.0"), None);
+        assert_eq!(parse("npm:forked@^1.0.0"), None);
+        assert_eq!(parse("git+--upload-pack=touch pwned"), None);
+    }
+
+    #[tokio::test]
+    async fn resolves_and_packs_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = bare_repository(
+            dir.path(),
+            &[("1.0.0", None), ("1.1.0", Some("echo built > built.txt"))],
+        );
+        let store_dir = dir.path().join("store");
+        let mut config =
+            Config::from_npmrc(None, Some(store_dir.clone()), Npmrc::default()).unwrap();
+        let reporter = RecordingReporter::default();
+        config.reporter = Box::new(reporter.clone());
+
+        let resolve = |committish: &str| {
+            let spec = GitSpec::parse(&format!("git+{url}#{committish}")).unwrap();
+            let config = &config;
+            async move { resolve_git("alias", &spec, config).await }
+        };
+
+        let error = resolve("--output=pwned").await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("failed to resolve {url}: --output=pwned is not a branch, tag or commit")
+        );
+        assert!(!store_dir.exists());
+
+        let tagged = resolve("v1.0.0").await.unwrap();
+        let commit = run_git(&dir.path().join("work"), &["rev-parse", "v1.0.0"]);
+        assert_eq!(tagged.name, "forked");
+        assert_eq!(tagged.version.to_string(), format!("1.0.0+git.{commit}"));
+        assert_eq!(
+            tagged.source,
+            Some(PackageSource::Git {
+                url: url.clone(),
+                commit: commit.clone()
+            })
+        );
+        let packed = get_git_store_path(&store_dir, &commit);
+        assert!(packed.join("index.js").exists());
+        assert!(!packed.join(".git").exists());
+
+        // the same commit by hash, without fetching again.
+        let by_hash = resolve(&commit).await.unwrap();
+        assert_eq!(by_hash, tagged);
+
+        let head = resolve("main").await.unwrap();
+        assert!(head.version.starts_with("1.1.0+git."));
+        let Some(PackageSource::Git { commit, .. }) = head.source else {
+            panic!("expected a git package");
+        };
+        let packed = get_git_store_path(&store_dir, &commit);
+        assert_eq!(
+            std::fs::read_to_string(packed.join("built.txt")).unwrap(),
+            "built\n"
+        );
+
+        let cached: Vec<_> = reporter
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|event| match event {
+                Event::Fetched { cached, .. } => Some(*cached),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(cached, [false, true, false]);
+
+        let Err(Error::Git { reason, .. }) = resolve("v2.0.0").await else {
+            panic!("expected a missing ref");
+        };
+        assert_eq!(reason, "v2.0.0 is not a branch, tag or commit");
+    }
+
+    #[tokio::test]
+    async fn reports_failing_prepare_scripts() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = bare_repository(dir.path(), &[("1.0.0", Some("echo broken >&2; exit 1"))]);
+        let config =
+            Config::from_npmrc(None, Some(dir.path().join("store")), Npmrc::default()).unwrap();
+
+        let spec = GitSpec::parse(&format!("git+{url}")).unwrap();
+        let Err(Error::Prepare { name, reason }) = resolve_git("forked", &spec, &config).await
+        else {
+            panic!("expected the prepare script to fail");
+        };
+        assert_eq!((name.as_str(), reason.as_str()), ("forked", "broken"));
+    }
+
+    #[tokio::test]
+    async fn prepares_without_dependencies() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = bare_repository(
+            dir.path(),
+            &[(
+                "1.0.0",
+                Some("test -d node_modules || { echo tsc: not found >&2; exit 1; }"),
+            )],
+        );
+        let work = dir.path().join("work");
+        let mut manifest: Value =
+            serde_json::from_slice(&std::fs::read(work.join("package.json")).unwrap()).unwrap();
+        manifest["devDependencies"] = json!({ "typescript": "^5.0.0" });
+        std::fs::write(work.join("package.json"), manifest.to_string()).unwrap();
+        run_git(&work, &["commit", "--quiet", "-am", "typescript"]);
+        run_git(&work, &["push", "--quiet", &url, "main"]);
+        let config =
+            Config::from_npmrc(None, Some(dir.path().join("store")), Npmrc::default()).unwrap();
+
+        let spec = GitSpec::parse(&format!("git+{url}#main")).unwrap();
+        let Err(Error::Prepare { reason, .. }) = resolve_git("forked", &spec, &config).await
+        else {
+            panic!("expected the prepare script to fail");
+        };
+        assert_eq!(
+            reason,
+            "tsc: not found, the dependencies of git packages are not installed before prepare"
+        );
+    }
+
+    #[test]
+    fn locks_each_clone() {
+        let lock = fetch_lock(Path::new("/store/git/a"));
+        assert!(Arc::ptr_eq(&lock, &fetch_lock(Path::new("/store/git/a"))));
+        assert!(!Arc::ptr_eq(&lock, &fetch_lock(Path::new("/store/git/b"))));
+    }
+}
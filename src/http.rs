@@ -1,60 +1,410 @@
-use std::str::FromStr;
-
-use derive_more::Display;
+use reqwest::{header::AUTHORIZATION, RequestBuilder, StatusCode, Url};
+use tokio::sync::SemaphorePermit;
 
 use crate::{
     config::Config,
+    error::{Error, Result},
     npm::{NpmResolvedPackage, UrlString},
 };
 
-const NPM_REGISTRY_URL: &str = "registry.npmjs.org/";
 const INSTALL_FETCH_HEADER: &str =
     "application/vnd.npm.install-v1+json; q=1.0, application/json; q=0.8, */*";
 
-#[derive(Debug, Display, derive_more::Error)]
-pub enum Error {
-    HttpError,
+/// The metadata url of `name` on `registry`.
+/// The `/` of scoped packages is escaped like the npm cli does, `@scope%2fname`.
+pub fn get_package_url(registry: &Url, name: &str) -> Result<Url> {
+    let path = name.replace('/', "%2f");
+    registry.join(&path).map_err(|source| Error::InvalidUrl {
+        url: format!("{registry}{path}"),
+        source,
+    })
 }
 
-pub async fn get_npm_package(name: &String, config: &Config) -> anyhow::Result<NpmResolvedPackage> {
-    let package_url =
-        reqwest::Url::from_str(format!("https://{NPM_REGISTRY_URL}").as_str())?.join(name)?;
+pub async fn get_npm_package(name: &str, config: &Config) -> Result<NpmResolvedPackage> {
+    let package_url = get_package_url(config.get_registry(name), name)?;
+    let fetch_error = |source| Error::Fetch {
+        name: name.to_string(),
+        url: package_url.to_string(),
+        source,
+    };
 
-    let response = match config
-        .client
-        .get(package_url.clone())
+    let _permit = config
+        .network
+        .acquire()
+        .await
+        .expect("the semaphore is never closed");
+    let response = authorize(config.client.get(package_url.clone()), &package_url, config)
         .header(reqwest::header::ACCEPT, INSTALL_FETCH_HEADER)
         .send()
         .await
-    {
-        Ok(response) => response,
-        Err(error) => {
-            println!("Fetch: {name}, {package_url}, {:?}", error);
-            return Err(Error::HttpError.into());
-        }
-    };
+        .map_err(fetch_error)?;
 
-    let text = match response.text().await {
-        Ok(response) => match serde_json::from_str(response.as_str()) {
-            Ok(json) => Ok(json),
-            Err(error) => {
-                println!("{:?}", response);
-                println!("JSON: {name}, {package_url}, {:?}", error,);
-                return Err(error.into());
-            }
-        },
-        Err(error) => {
-            println!("TEXT: {name}, {package_url}, {:?}", error,);
-            return Err(error.into());
+    match response.status() {
+        status if status.is_success() => {}
+        StatusCode::NOT_FOUND => {
+            return Err(Error::PackageNotFound {
+                name: name.to_string(),
+                url: package_url.to_string(),
+            })
         }
-    };
+        status => {
+            return Err(Error::HttpStatus {
+                name: name.to_string(),
+                url: package_url.to_string(),
+                status,
+            })
+        }
+    }
+
+    let body = response.bytes().await.map_err(fetch_error)?;
 
-    text
+    serde_json::from_slice(&body).map_err(|source| Error::InvalidMetadata {
+        name: name.to_string(),
+        url: package_url.to_string(),
+        source,
+    })
 }
 
-pub async fn get_package_tar(
+/// Request the tarball at `tarball`, failing on error statuses.
+/// The response comes with its permit of the `network` semaphore, to hold until
+/// the body is read.
+pub async fn get_package_tar<'a>(
     tarball: &UrlString,
-    config: &Config,
-) -> Result<reqwest::Response, reqwest::Error> {
-    Ok(config.client.get(tarball.as_str()).send().await?)
+    config: &'a Config,
+) -> Result<(reqwest::Response, SemaphorePermit<'a>), reqwest::Error> {
+    let permit = config
+        .network
+        .acquire()
+        .await
+        .expect("the semaphore is never closed");
+
+    // `Url` parse errors are not `reqwest` errors, let the request builder report them.
+    let Ok(tarball_url) = Url::parse(tarball) else {
+        let response = config.client.get(tarball.as_str()).send().await?;
+        return Ok((response, permit));
+    };
+
+    let response = authorize(config.client.get(tarball_url.clone()), &tarball_url, config)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok((response, permit))
+}
+
+/// Attach the `.npmrc` credentials that match `url` to `request`.
+fn authorize(request: RequestBuilder, url: &Url, config: &Config) -> RequestBuilder {
+    match config.get_credentials(url) {
+        Some(credentials) => request.header(AUTHORIZATION, credentials.header_value()),
+        None => request,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hash::FxHashMap as HashMap;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        config::normalize_registry,
+        npmrc::Npmrc,
+        test_registry::{tls_fixture, TestProxy, TestRegistry, TestResponse, Tls},
+    };
+
+    fn packument(name: &str) -> serde_json::Value {
+        json!({
+            "name": name,
+            "dist-tags": { "latest": "1.0.0" },
+            "versions": {
+                "1.0.0": {
+                    "name": name,
+                    "version": "1.0.0",
+                    "dist": {
+                        "shasum": "",
+                        "tarball": format!("http://localhost/{name}/-/1.0.0.tgz")
+                    }
+                }
+            },
+            "modified": "2022-06-19T02:40:54.045Z"
+        })
+    }
+
+    #[test]
+    fn package_url() {
+        let registry = Url::parse("https://registry.npmjs.org/").unwrap();
+        assert_eq!(
+            get_package_url(&registry, "react").unwrap().as_str(),
+            "https://registry.npmjs.org/react"
+        );
+        assert_eq!(
+            get_package_url(&registry, "@types/node").unwrap().as_str(),
+            "https://registry.npmjs.org/@types%2fnode"
+        );
+
+        let registry = normalize_registry(Url::parse("http://localhost:4873/npm").unwrap());
+        assert_eq!(
+            get_package_url(&registry, "@types/node").unwrap().as_str(),
+            "http://localhost:4873/npm/@types%2fnode"
+        );
+    }
+
+    #[tokio::test]
+    async fn fetches_from_http_registry_with_path_prefix() {
+        let server = TestRegistry::with_routes(HashMap::from_iter([
+            (
+                String::from("/npm/is-even"),
+                TestResponse::json(&packument("is-even")),
+            ),
+            (
+                String::from("/npm/@types%2fnode"),
+                TestResponse::json(&packument("@types/node")),
+            ),
+        ]))
+        .await;
+
+        let config = Config::from_npmrc(
+            Some(server.url.join("npm").unwrap()),
+            None,
+            Npmrc::default(),
+        )
+        .unwrap();
+
+        let package = get_npm_package("is-even", &config).await.unwrap();
+        assert_eq!(package.name, "is-even");
+
+        let package = get_npm_package("@types/node", &config).await.unwrap();
+        assert_eq!(package.name, "@types/node");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].header("accept"), Some(INSTALL_FETCH_HEADER));
+        assert_eq!(requests[0].header("authorization"), None);
+    }
+
+    #[tokio::test]
+    async fn sends_scoped_registry_credentials() {
+        let public = TestRegistry::with_routes(HashMap::from_iter([(
+            String::from("/react"),
+            TestResponse::json(&packument("react")),
+        )]))
+        .await;
+        let corp = TestRegistry::with_routes(HashMap::from_iter([
+            (
+                String::from("/npm/@corp%2fui"),
+                TestResponse::json(&packument("@corp/ui")),
+            ),
+            (
+                String::from("/npm/@corp/ui/-/ui-1.0.0.tgz"),
+                TestResponse::status(200),
+            ),
+        ]))
+        .await;
+
+        let corp_host = corp.url.authority().to_string();
+        let npmrc = Npmrc::parse_with_env(
+            &format!(
+                "@corp:registry={}npm/\n//{corp_host}/npm/:_authToken=${{CORP_TOKEN}}",
+                corp.url
+            ),
+            |key| (key == "CORP_TOKEN").then(|| String::from("secret")),
+        );
+        let config = Config::from_npmrc(Some(public.url.clone()), None, npmrc).unwrap();
+
+        let package = get_npm_package("@corp/ui", &config).await.unwrap();
+        assert_eq!(package.name, "@corp/ui");
+        get_npm_package("react", &config).await.unwrap();
+        let _tarball = get_package_tar(
+            &UrlString::new(format!("{}npm/@corp/ui/-/ui-1.0.0.tgz", corp.url)),
+            &config,
+        )
+        .await
+        .unwrap();
+
+        let corp_requests = corp.requests();
+        assert_eq!(corp_requests.len(), 2);
+        for request in corp_requests {
+            assert_eq!(request.header("authorization"), Some("Bearer secret"));
+        }
+
+        let public_requests = public.requests();
+        assert_eq!(public_requests.len(), 1);
+        assert_eq!(public_requests[0].header("authorization"), None);
+    }
+
+    #[tokio::test]
+    async fn reports_failed_requests() {
+        let server = TestRegistry::with_routes(HashMap::from_iter([
+            (String::from("/broken"), TestResponse::status(500)),
+            (
+                String::from("/garbage"),
+                TestResponse {
+                    status: 200,
+                    body: b"not json".to_vec(),
+                },
+            ),
+        ]))
+        .await;
+        let config = Config::from_npmrc(Some(server.url.clone()), None, Npmrc::default()).unwrap();
+
+        let error = get_npm_package("missing", &config).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("missing was not found at {}missing", server.url)
+        );
+        assert!(matches!(
+            get_npm_package("broken", &config).await,
+            Err(Error::HttpStatus { status, .. }) if status == StatusCode::INTERNAL_SERVER_ERROR
+        ));
+        assert!(matches!(
+            get_npm_package("garbage", &config).await,
+            Err(Error::InvalidMetadata { name, .. }) if name == "garbage"
+        ));
+        assert!(get_package_tar(
+            &UrlString::new(format!("{}missing/-/missing-1.0.0.tgz", server.url)),
+            &config
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn holds_a_network_permit_for_tarballs() {
+        let server = registry(None).await;
+        let config = Config::from_npmrc(
+            Some(server.url.clone()),
+            None,
+            Npmrc::parse("network-concurrency=1"),
+        )
+        .unwrap();
+        let tarball = UrlString::new(format!("{}is-even/-/is-even-1.0.0.tgz", server.url));
+
+        let (response, permit) = get_package_tar(&tarball, &config).await.unwrap();
+        assert_eq!(config.network.available_permits(), 0);
+        response.bytes().await.unwrap();
+        drop(permit);
+        assert_eq!(config.network.available_permits(), 1);
+    }
+
+    /// A registry serving `is-even` and its tarball.
+    async fn registry(tls: Option<Tls>) -> TestRegistry {
+        TestRegistry::with_routes_and_tls(
+            HashMap::from_iter([
+                (
+                    String::from("/is-even"),
+                    TestResponse::json(&packument("is-even")),
+                ),
+                (
+                    String::from("/is-even/-/is-even-1.0.0.tgz"),
+                    TestResponse::tarball(&[("package.json", "{}")]),
+                ),
+            ]),
+            tls,
+        )
+        .await
+    }
+
+    /// Fetch the metadata and the tarball of `is-even` from `registry`.
+    async fn fetch(registry: &Url, npmrc: &str) -> Result<()> {
+        let config = Config::from_npmrc(Some(registry.clone()), None, Npmrc::parse(npmrc)).unwrap();
+
+        get_npm_package("is-even", &config).await?;
+        let _tarball = get_package_tar(
+            &UrlString::new(format!("{registry}is-even/-/is-even-1.0.0.tgz")),
+            &config,
+        )
+        .await
+        .map_err(|source| Error::Fetch {
+            name: String::from("is-even"),
+            url: registry.to_string(),
+            source,
+        })?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn verifies_registry_certificates() {
+        let server = registry(Some(Tls::Server)).await;
+        let cafile = tls_fixture("ca.pem");
+
+        assert!(fetch(&server.url, "").await.is_err());
+        fetch(&server.url, &format!("cafile={}", cafile.display()))
+            .await
+            .unwrap();
+        fetch(&server.url, "strict-ssl=false").await.unwrap();
+
+        // the metadata and tarball requests of the two successful fetches.
+        assert_eq!(server.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn sends_client_certificates() {
+        let server = registry(Some(Tls::ClientAuth)).await;
+        let cafile = format!("cafile={}", tls_fixture("ca.pem").display());
+        // `key` is inline like npm stores it, `cert` a path.
+        let key = std::fs::read_to_string(tls_fixture("client.key"))
+            .unwrap()
+            .replace('\n', "\\n");
+
+        assert!(fetch(&server.url, &cafile).await.is_err());
+        fetch(
+            &server.url,
+            &format!(
+                "{cafile}\ncert={}\nkey={key}",
+                tls_fixture("client.pem").display()
+            ),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn routes_requests_through_proxies() {
+        let http_server = registry(None).await;
+        let https_server = registry(Some(Tls::Server)).await;
+        let proxy = TestProxy::start().await;
+        let cafile = format!("cafile={}", tls_fixture("ca.pem").display());
+
+        fetch(&http_server.url, &format!("proxy={}", proxy.url))
+            .await
+            .unwrap();
+        assert_eq!(
+            proxy
+                .requests()
+                .iter()
+                .map(|request| (request.method.as_str(), request.path.clone()))
+                .collect::<Vec<_>>(),
+            [
+                ("GET", format!("{}is-even", http_server.url)),
+                (
+                    "GET",
+                    format!("{}is-even/-/is-even-1.0.0.tgz", http_server.url)
+                ),
+            ]
+        );
+        assert_eq!(http_server.requests()[0].path, "/is-even");
+
+        fetch(
+            &https_server.url,
+            &format!("{cafile}\nhttps-proxy={}", proxy.url),
+        )
+        .await
+        .unwrap();
+        let requests = proxy.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[2].method, "CONNECT");
+        assert_eq!(requests[2].path, https_server.url.authority());
+
+        fetch(
+            &https_server.url,
+            &format!("{cafile}\nproxy={}\nnoproxy=localhost,127.0.0.1", proxy.url),
+        )
+        .await
+        .unwrap();
+        assert_eq!(proxy.requests().len(), 4);
+        assert_eq!(https_server.requests().len(), 4);
+    }
 }
@@ -1,67 +1,327 @@
-use futures::future::join_all;
-use std::collections::HashMap;
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use std::{
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use tokio::{fs, task};
 
 use crate::{
-    config::Config,
-    dependency_resolver::resolve_deps,
+    config::{Config, PackageImportMethod},
+    dependency_resolver::{resolve, Resolution, RootDependencies},
     downloader::download_packages,
-    linker::{hardlink_package, symlink_dep, symlink_direct},
-    npm::VersionRangeSpecifier,
-    package_manifest::update_package_manifest,
+    error::{join_all_bail, Result},
+    install_state::InstallState,
+    linker::{
+        detect_link_mode, get_package_folder_name, hardlink_package, prune, symlink_dep,
+        symlink_direct, LinkMode,
+    },
+    npm::{NpmPackageVersion, VersionRangeSpecifier},
+    package_manifest::{get_manifest_dependencies, update_package_manifest},
+    reporter::Event,
+    DEPS_FOLDER,
 };
 
+/// Install `deps` next to the dependencies of package.json and save them to it.
 pub async fn install_package(
     deps: HashMap<String, VersionRangeSpecifier>,
     config: &Config,
-) -> anyhow::Result<()> {
-    let resolved_deps = resolve_deps(deps, config).await?;
+) -> Result<()> {
+    let mut root = task::spawn_blocking(get_manifest_dependencies).await??;
+    for (name, range) in &deps {
+        // packages already in devDependencies stay there.
+        match root.dev_dependencies.get_mut(name) {
+            Some(dev_range) => *dev_range = range.clone(),
+            None => {
+                root.dependencies.insert(name.clone(), range.clone());
+            }
+        }
+    }
+
+    let top_level = install(root, config).await?;
+
+    update_package_manifest(
+        top_level
+            .into_iter()
+            .filter_map(|(name, version)| {
+                let requested = deps.get(&name)?;
+                // packages from other sources are saved the way they were requested.
+                let range = match version.source {
+                    Some(_) => requested.clone(),
+                    None => saved_range(&name, &version, requested),
+                };
+                Some((name, range))
+            })
+            .collect(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// The range saved to package.json for the direct dependency `name` requested as
+/// `requested` that resolved to `version`.
+/// Ranges given by the user, such as `~1.2.0` or `1.2.3`, are saved as they are,
+/// tags and `*` are saved as a caret range of `version`.
+pub(crate) fn saved_range(
+    name: &str,
+    version: &NpmPackageVersion,
+    requested: &VersionRangeSpecifier,
+) -> VersionRangeSpecifier {
+    let range = requested
+        .alias()
+        .map_or_else(|| requested.clone(), |(_, range)| range);
+    if is_semver_range(&range) {
+        requested.clone()
+    } else {
+        default_range(name, version)
+    }
+}
+
+/// The range saved to package.json for the direct dependency `name` updated from
+/// `previous` to `version`. The operator of `previous` is kept with the version bumped,
+/// `~1.2.0` is saved as `~1.3.1` and an exact version as the new one. Other ranges
+/// are kept as long as they match `version`, otherwise a caret range is saved.
+pub(crate) fn updated_range(
+    name: &str,
+    version: &NpmPackageVersion,
+    previous: &VersionRangeSpecifier,
+) -> VersionRangeSpecifier {
+    let range = previous
+        .alias()
+        .map_or_else(|| previous.clone(), |(_, range)| range);
+    let range = range.trim();
+
+    let operator = ["^", "~", ">=", "="]
+        .into_iter()
+        .find(|operator| range.starts_with(operator))
+        .unwrap_or_default();
+    if range[operator.len()..]
+        .trim()
+        .parse::<node_semver::Version>()
+        .is_ok()
+    {
+        return with_alias(name, version, format!("{operator}{}", version.version));
+    }
+
+    let matches = match (
+        range.parse::<node_semver::Range>(),
+        version.version.parse::<node_semver::Version>(),
+    ) {
+        (Ok(range), Ok(version)) => range.satisfies(&version),
+        _ => false,
+    };
+    if matches && is_semver_range(&VersionRangeSpecifier::new(range.to_string())) {
+        previous.clone()
+    } else {
+        default_range(name, version)
+    }
+}
+
+/// Whether `range` is a semver range narrower than `*`, rather than a tag.
+fn is_semver_range(range: &VersionRangeSpecifier) -> bool {
+    !matches!(range.trim(), "" | "*" | "x" | "X") && range.parse::<node_semver::Range>().is_ok()
+}
+
+/// A caret range of `version`, an `npm:` alias when `name` is not the name of the package.
+/// Prereleases, such as the ones of `next` or `canary` tags, are saved exactly, as a
+/// caret range would accept newer prereleases of the same version.
+fn default_range(name: &str, version: &NpmPackageVersion) -> VersionRangeSpecifier {
+    let is_prerelease = version
+        .version
+        .parse::<node_semver::Version>()
+        .map_or(false, |version| version.is_prerelease());
+    let range = if is_prerelease {
+        version.version.to_string()
+    } else {
+        format!("^{}", version.version)
+    };
+
+    with_alias(name, version, range)
+}
+
+fn with_alias(name: &str, version: &NpmPackageVersion, range: String) -> VersionRangeSpecifier {
+    if name == version.name {
+        VersionRangeSpecifier::new(range)
+    } else {
+        VersionRangeSpecifier::new(format!("npm:{}@{range}", version.name))
+    }
+}
+
+/// Resolve, download and link the dependencies and dev dependencies of `root`, or only
+/// one of them depending on the dependency scope of `config`.
+/// Packages already in the store or in `node_modules` are reused, and packages
+/// that are not part of `root` anymore are unlinked.
+/// returns the resolved top level packages, with the name they are linked under.
+pub(crate) async fn install(
+    mut root: RootDependencies,
+    config: &Config,
+) -> Result<Vec<(String, NpmPackageVersion)>> {
+    root.restrict_to(config.dependency_scope);
 
-    let top_level = download_packages(&resolved_deps, config).await?;
+    let started = Instant::now();
+    let resolution = resolve(root, config).await?;
+
+    link_resolution(resolution, started, config).await
+}
+
+/// Download and link the packages of `resolution`, and unlink the other ones.
+/// The resolution is recorded for later commands. `started` is when the install started.
+/// returns the top level packages, with the name they are linked under.
+pub(crate) async fn link_resolution(
+    resolution: Resolution,
+    started: Instant,
+    config: &Config,
+) -> Result<Vec<(String, NpmPackageVersion)>> {
+    fs::create_dir_all(DEPS_FOLDER).await?;
+    fs::create_dir_all(&config.store_dir).await?;
+    let link_mode = match config.package_import_method {
+        PackageImportMethod::Auto => detect_link_mode(&config.store_dir, Path::new(DEPS_FOLDER))?,
+        PackageImportMethod::Hardlink => LinkMode::Hardlink,
+        PackageImportMethod::Copy => LinkMode::Copy,
+    };
+    if link_mode == LinkMode::Copy && config.package_import_method == PackageImportMethod::Auto {
+        config.reporter.report(Event::Warning {
+            message: format!(
+                "the store at {} is on a different filesystem than the project, packages will be copied",
+                config.store_dir.display()
+            ),
+        });
+    }
+
+    let resolved_deps = download_packages(resolution.packages(config), config).await?;
+    let installed: HashSet<_> = resolved_deps.iter().map(|dep| &dep.id).collect();
 
     let mut futures = vec![];
-    for dep in resolved_deps.iter() {
-        futures.push(hardlink_package(&dep.version.name, &dep.version.version))
+    for dep in &resolved_deps {
+        futures.push(hardlink_package(&dep.version, &dep.id, link_mode, config));
     }
-    let _results = join_all(futures).await;
-    println!("hardlink: ");
-    println!(
-        "{:?}",
-        _results
-            .iter()
-            .filter(|res| res.is_err())
-            .collect::<Vec<_>>()
-    );
+    join_all_bail(futures, config.bail).await?;
 
     let mut futures = vec![];
-    for package in resolved_deps.iter() {
-        for dep in package.dependencies.iter() {
-            futures.push(symlink_dep(
-                &dep.name,
-                &dep.version,
-                &package.version.name,
-                &package.version.version,
-            ));
+    for package in &resolved_deps {
+        for (name, dep) in package
+            .dependencies
+            .iter()
+            .filter(|(_, dep)| installed.contains(dep))
+        {
+            futures.push(symlink_dep(dep, name, &package.id));
         }
     }
-    let _results = join_all(futures).await;
-    println!("symlink deps: ");
-    println!(
-        "{:?}",
-        _results
-            .iter()
-            .filter(|res| res.is_err())
-            .collect::<Vec<_>>()
-    );
+    join_all_bail(futures, config.bail).await?;
 
-    for top_level_dep in top_level {
-        symlink_direct(&top_level_dep.version.name, &top_level_dep.version.version).await?;
+    let mut top_level_versions = vec![];
+    for top_level_dep in &resolved_deps {
+        for name in &top_level_dep.direct_names {
+            symlink_direct(&top_level_dep.id, name).await?;
+            top_level_versions.push((name.clone(), top_level_dep.version.clone()));
+        }
+    }
 
-        update_package_manifest(HashMap::from([(
-            top_level_dep.version.name,
-            VersionRangeSpecifier::new(format!("^{}", top_level_dep.version.version)),
-        )]))
+    prune(
+        PathBuf::from(DEPS_FOLDER),
+        resolved_deps
+            .iter()
+            .map(|dep| get_package_folder_name(&dep.id))
+            .collect(),
+        top_level_versions
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect(),
+    )
+    .await?;
+    InstallState::from(&resolution)
+        .write(Path::new(DEPS_FOLDER))
         .await?;
+
+    config.reporter.report(Event::Summary {
+        packages: resolved_deps.len(),
+        direct: top_level_versions
+            .iter()
+            .map(|(name, version)| (name.clone(), version.version.clone()))
+            .collect(),
+        duration: started.elapsed(),
+    });
+
+    Ok(top_level_versions)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn version(name: &str, version: &str) -> NpmPackageVersion {
+        serde_json::from_value(json!({
+            "name": name,
+            "version": version,
+            "dist": { "shasum": "", "tarball": "" },
+        }))
+        .unwrap()
     }
 
-    Ok(())
+    #[test]
+    fn saves_requested_ranges() {
+        let cases = [
+            ("react", "react", "18.2.0", "latest", "^18.2.0"),
+            (
+                "react",
+                "react",
+                "18.3.0-canary.1",
+                "canary",
+                "18.3.0-canary.1",
+            ),
+            ("react", "react", "18.2.0", "*", "^18.2.0"),
+            ("react", "react", "18.2.0", "~18.2.0", "~18.2.0"),
+            ("react", "react", "18.2.0", "18.2.0", "18.2.0"),
+            ("react", "react", "18.2.0", ">=16 <19", ">=16 <19"),
+            (
+                "react",
+                "preact",
+                "10.0.0",
+                "npm:preact@~10.0.0",
+                "npm:preact@~10.0.0",
+            ),
+            (
+                "react",
+                "preact",
+                "10.0.0",
+                "npm:preact",
+                "npm:preact@^10.0.0",
+            ),
+        ];
+
+        for (name, package, resolved, requested, expected) in cases {
+            let requested = VersionRangeSpecifier::new(requested.to_string());
+            let range = saved_range(name, &version(package, resolved), &requested);
+            assert_eq!(*range, expected, "{}", *requested);
+        }
+    }
+
+    #[test]
+    fn keeps_the_operator_of_updated_ranges() {
+        let cases = [
+            ("react", "^18.0.0", "18.3.1", "^18.3.1"),
+            ("react", "~18.2.0", "18.2.5", "~18.2.5"),
+            ("react", "~18.2.0", "19.0.0", "~19.0.0"),
+            ("react", ">=16.0.0", "18.3.1", ">=18.3.1"),
+            ("react", "18.2.0", "19.0.0", "19.0.0"),
+            ("react", "=18.2.0", "19.0.0", "=19.0.0"),
+            ("react", "16 || 18", "18.3.1", "16 || 18"),
+            ("react", "16 || 17", "18.3.1", "^18.3.1"),
+            ("react", "latest", "18.3.1", "^18.3.1"),
+            (
+                "preact",
+                "npm:preact@~10.0.0",
+                "10.0.3",
+                "npm:preact@~10.0.3",
+            ),
+        ];
+
+        for (package, previous, resolved, expected) in cases {
+            let previous = VersionRangeSpecifier::new(previous.to_string());
+            let range = updated_range("react", &version(package, resolved), &previous);
+            assert_eq!(*range, expected, "{}", *previous);
+        }
+    }
 }
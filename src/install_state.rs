@@ -0,0 +1,193 @@
+use rustc_hash::FxHashMap as HashMap;
+use std::{io::ErrorKind, path::Path};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::{
+    dependency_resolver::{Resolution, RootDependencies},
+    error::Result,
+    npm::{
+        NpmPackageVersion, NpmVersionDist, PackageSource, PeerDependencyMeta, Version,
+        VersionRangeSpecifier,
+    },
+    overrides::Overrides,
+    STORE_FOLDER,
+};
+
+/// The file of the virtual store recording what the last install resolved.
+const STATE_FILE: &str = "state.json";
+
+/// What the last install resolved, so that commands can work on the installed
+/// packages without resolving them again.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InstallState {
+    root_deps: HashMap<String, VersionRangeSpecifier>,
+    prod_deps: HashMap<String, VersionRangeSpecifier>,
+    overrides: Overrides,
+    versions: HashMap<String, HashMap<VersionRangeSpecifier, InstalledVersion>>,
+}
+
+/// The parts of a resolved version the packages of the virtual store are built from.
+#[derive(Debug, Serialize, Deserialize)]
+struct InstalledVersion {
+    name: String,
+    version: Version,
+    dependencies: HashMap<String, VersionRangeSpecifier>,
+    peer_dependencies: HashMap<String, VersionRangeSpecifier>,
+    peer_dependencies_meta: HashMap<String, PeerDependencyMeta>,
+    optional_dependencies: HashMap<String, VersionRangeSpecifier>,
+    dist: NpmVersionDist,
+    source: Option<PackageSource>,
+}
+
+impl InstallState {
+    /// Read the state of the install in `deps_folder`, None if nothing was installed.
+    pub async fn read(deps_folder: &Path) -> Result<Option<Self>> {
+        let content = match fs::read(deps_folder.join(STORE_FOLDER).join(STATE_FILE)).await {
+            Ok(content) => content,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+
+        // states written by other versions are read as no install.
+        Ok(serde_json::from_slice(&content).ok())
+    }
+
+    pub async fn write(&self, deps_folder: &Path) -> Result<()> {
+        let content = serde_json::to_vec(self).map_err(std::io::Error::from)?;
+        let virtual_store = deps_folder.join(STORE_FOLDER);
+        fs::create_dir_all(&virtual_store).await?;
+        fs::write(virtual_store.join(STATE_FILE), content).await?;
+
+        Ok(())
+    }
+
+    /// Whether the install was made from the dependencies and the overrides of `root`.
+    pub fn matches(&self, root: &RootDependencies) -> bool {
+        let mut root_deps = root.dev_dependencies.clone();
+        root_deps.extend(root.dependencies.clone());
+
+        self.root_deps == root_deps
+            && self.prod_deps == root.dependencies
+            && self.overrides == root.overrides
+    }
+
+    pub fn into_resolution(self) -> Resolution {
+        let versions = self
+            .versions
+            .into_iter()
+            .map(|(name, versions)| {
+                let versions = versions
+                    .into_iter()
+                    .map(|(range, version)| (range, version.into()))
+                    .collect();
+                (name, versions)
+            })
+            .collect();
+
+        Resolution {
+            root_deps: self.root_deps,
+            prod_deps: self.prod_deps,
+            overrides: self.overrides,
+            versions,
+        }
+    }
+}
+
+impl From<&Resolution> for InstallState {
+    fn from(resolution: &Resolution) -> Self {
+        let versions = resolution
+            .versions
+            .iter()
+            .map(|(name, versions)| {
+                let versions = versions
+                    .iter()
+                    .map(|(range, version)| (range.clone(), version.into()))
+                    .collect();
+                (name.clone(), versions)
+            })
+            .collect();
+
+        Self {
+            root_deps: resolution.root_deps.clone(),
+            prod_deps: resolution.prod_deps.clone(),
+            overrides: resolution.overrides.clone(),
+            versions,
+        }
+    }
+}
+
+impl From<&NpmPackageVersion> for InstalledVersion {
+    fn from(version: &NpmPackageVersion) -> Self {
+        Self {
+            name: version.name.clone(),
+            version: version.version.clone(),
+            dependencies: version.dependencies.clone(),
+            peer_dependencies: version.peer_dependencies.clone(),
+            peer_dependencies_meta: version.peer_dependencies_meta.clone(),
+            optional_dependencies: version.optional_dependencies.clone(),
+            dist: version.dist.clone(),
+            source: version.source.clone(),
+        }
+    }
+}
+
+impl From<InstalledVersion> for NpmPackageVersion {
+    fn from(version: InstalledVersion) -> Self {
+        // installed versions already support the platform, so `os`, `cpu` and `libc`
+        // are not kept.
+        Self {
+            name: version.name,
+            version: version.version,
+            dependencies: version.dependencies,
+            peer_dependencies: version.peer_dependencies,
+            peer_dependencies_meta: version.peer_dependencies_meta,
+            optional_dependencies: version.optional_dependencies,
+            os: vec![],
+            cpu: vec![],
+            libc: vec![],
+            dist: version.dist,
+            engines: None,
+            source: version.source,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn matches_the_overrides() {
+        let dependencies: HashMap<_, _> = [(
+            String::from("a"),
+            VersionRangeSpecifier::new(String::from("^1")),
+        )]
+        .into_iter()
+        .collect();
+        let overrides = |range: &str| {
+            Overrides::from_manifest(&json!({ "overrides": { "b": range } }), &dependencies)
+                .unwrap()
+        };
+        let state = InstallState::from(&Resolution {
+            root_deps: dependencies.clone(),
+            prod_deps: dependencies.clone(),
+            overrides: overrides("1.0.0"),
+            ..Resolution::default()
+        });
+
+        let mut root = RootDependencies {
+            dependencies: dependencies.clone(),
+            overrides: overrides("1.0.0"),
+            ..RootDependencies::default()
+        };
+        assert!(state.matches(&root));
+        root.overrides = overrides("1.1.0");
+        assert!(!state.matches(&root));
+        root.overrides = Overrides::default();
+        assert!(!state.matches(&root));
+    }
+}
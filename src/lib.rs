@@ -1,13 +1,35 @@
+pub mod cli;
 pub mod config;
+pub mod config_command;
+pub mod dedupe;
 pub mod dependency_resolver;
 pub mod downloader;
+pub mod error;
+pub mod git;
 pub mod http;
 pub mod install_manifest;
 pub mod install_package;
+mod install_state;
 mod linker;
+pub mod local;
 pub mod npm;
+pub mod npmrc;
+pub mod overrides;
 mod package_manifest;
+pub mod package_spec;
+pub mod platform;
+pub mod remote;
+pub mod remove_package;
+pub mod reporter;
 mod resolve_version_range;
+#[cfg(test)]
+mod test_registry;
+pub mod update_package;
 
+/// The virtual store of a project, inside `DEPS_FOLDER`.
 pub const STORE_FOLDER: &str = ".mnpm";
 pub const DEPS_FOLDER: &str = "node_modules";
+
+/// Held by the tests that change the current directory, which is shared by all tests.
+#[cfg(test)]
+pub(crate) static CURRENT_DIR: std::sync::Mutex<()> = std::sync::Mutex::new(());
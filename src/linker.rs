@@ -1,54 +1,61 @@
+use rustc_hash::FxHashSet as HashSet;
 use std::{
     io::{ErrorKind, Result},
+    os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
 };
 use tokio::task;
 
-use crate::{downloader::get_store_package_path, npm::Version, DEPS_FOLDER, STORE_FOLDER};
+use crate::{
+    config::Config,
+    downloader::get_package_store_path,
+    error::{self, Error},
+    npm::{NpmPackageVersion, PackageId, PackageSource, Version},
+    reporter::Event,
+    DEPS_FOLDER, STORE_FOLDER,
+};
+
+/// `errno` of a hardlink across filesystems.
+const EXDEV: i32 = 18;
 
-pub async fn symlink_dep(
-    dep_name: &String,
-    dep_version: &Version,
-    dest_name: &String,
-    dest_version: &Version,
-) -> anyhow::Result<()> {
-    let original = get_dep_symlink_path(&dep_name, dep_version);
+/// Link `dep` into the `node_modules` folder of `dest` in the virtual store, as `name`,
+/// which differs from the name of `dep` for aliases.
+pub async fn symlink_dep(dep: &PackageId, name: &str, dest: &PackageId) -> error::Result<()> {
+    let mut original = get_dep_symlink_path(dep);
+    // scoped dependencies are linked one folder deeper, in `node_modules/@scope/`.
+    if name.starts_with('@') {
+        original = Path::new("..").join(original);
+    }
 
-    let link = get_local_store_package_path(dest_name, dest_version);
+    let link = get_local_store_package_path(dest);
     let mut parent = link
         .parent()
         .expect("failed to get package folder")
         .to_path_buf();
-    if dest_name.starts_with("@") {
+    if dest.name.starts_with('@') {
         parent = parent
             .parent()
             .expect("failed to get package folder")
             .to_path_buf();
     }
 
-    parent = parent.join(&dep_name);
-
-    let name = dep_name.clone();
-
-    task::spawn_blocking(
-        move || match std::os::unix::fs::symlink(&original, &parent) {
-            Err(error) if error.kind() == ErrorKind::AlreadyExists => Ok(()),
-            Err(error) => {
-                // TODO: check what pnpm does with @floating-ui/react-dom
-                // I bet they convert the / to something else like _
+    parent = parent.join(name);
 
-                println!("{:?}, {:?}, {:?}, {:?}", error, original, parent, name);
+    let link_error = |path, source| Error::Link {
+        name: dep.name.clone(),
+        version: dep.version.clone(),
+        path,
+        source,
+    };
 
-                return Err(error.into());
-            }
-            Ok(_) => Ok(()),
-        },
-    )
-    .await?
+    task::spawn_blocking(move || force_symlink(&original, &parent).map_err(|error| (parent, error)))
+        .await?
+        .map_err(|(path, source)| link_error(path, source))
 }
 
-pub async fn symlink_direct(name: &String, version: &Version) -> Result<()> {
-    let path_base = if name.starts_with("@") {
+/// Link `id` into the `node_modules` folder of the project as `name`.
+pub async fn symlink_direct(id: &PackageId, name: &str) -> error::Result<()> {
+    let path_base = if name.starts_with('@') {
         Path::new("../")
     } else {
         Path::new(".")
@@ -56,114 +63,341 @@ pub async fn symlink_direct(name: &String, version: &Version) -> Result<()> {
 
     let original = path_base
         .join(STORE_FOLDER)
-        .join(format!("{}@{}", name, version))
+        .join(get_package_folder_name(id))
         .join(DEPS_FOLDER)
-        .join(name);
+        .join(&id.name);
 
     let link = Path::new(DEPS_FOLDER).join(name);
 
-    if let Some(parent) = link.parent() {
-        tokio::fs::create_dir_all(parent).await?;
+    let path = link.clone();
+    task::spawn_blocking(move || force_symlink(&original, &link))
+        .await?
+        .map_err(|source| Error::Link {
+            name: id.name.clone(),
+            version: id.version.clone(),
+            path,
+            source,
+        })
+}
+
+/// Point `link` to `original`, replacing whatever `link` pointed to before.
+fn force_symlink(original: &Path, link: &Path) -> Result<()> {
+    match std::fs::symlink_metadata(link) {
+        Ok(metadata) if metadata.is_symlink() => {
+            if std::fs::read_link(link)? == original {
+                return Ok(());
+            }
+            std::fs::remove_file(link)?;
+        }
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(link)?,
+        Ok(_) => std::fs::remove_file(link)?,
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            if let Some(parent) = link.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        Err(error) => return Err(error),
     }
 
-    task::spawn_blocking(|| std::os::unix::fs::symlink(original, link)).await?
+    match std::os::unix::fs::symlink(original, link) {
+        // linked concurrently by another dependent of the same package.
+        Err(error)
+            if error.kind() == ErrorKind::AlreadyExists
+                && std::fs::read_link(link).ok().as_deref() == Some(original) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
 }
 
-fn get_dep_symlink_path(name: &String, version: &Version) -> PathBuf {
-    let folder_name = if name.starts_with("@") {
-        name.replace("/", "+")
-    } else {
-        name.to_string()
+/// Remove the packages of the virtual store in `deps_folder` that are not in `packages`,
+/// and the direct dependency links that are not in `direct_deps`.
+pub async fn prune(
+    deps_folder: PathBuf,
+    packages: HashSet<String>,
+    direct_deps: HashSet<String>,
+) -> error::Result<()> {
+    task::spawn_blocking(move || {
+        prune_virtual_store(&deps_folder.join(STORE_FOLDER), &packages)?;
+        prune_direct_links(&deps_folder, &direct_deps)
+    })
+    .await??;
+
+    Ok(())
+}
+
+/// The folder names of the packages in the virtual store of `deps_folder`.
+pub async fn read_virtual_store(deps_folder: PathBuf) -> error::Result<HashSet<String>> {
+    let packages =
+        task::spawn_blocking(move || virtual_store_packages(&deps_folder.join(STORE_FOLDER)))
+            .await??;
+
+    Ok(packages)
+}
+
+fn virtual_store_packages(virtual_store: &Path) -> Result<HashSet<String>> {
+    let entries = match std::fs::read_dir(virtual_store) {
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(HashSet::default()),
+        entries => entries?,
     };
 
+    let mut packages = HashSet::default();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            packages.insert(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+
+    Ok(packages)
+}
+
+fn prune_virtual_store(virtual_store: &Path, packages: &HashSet<String>) -> Result<()> {
+    let entries = match std::fs::read_dir(virtual_store) {
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+        entries => entries?,
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let folder_name = entry.file_name().to_string_lossy().to_string();
+
+        if entry.file_type()?.is_dir() && !packages.contains(&folder_name) {
+            std::fs::remove_dir_all(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn prune_direct_links(deps_folder: &Path, direct_deps: &HashSet<String>) -> Result<()> {
+    let entries = match std::fs::read_dir(deps_folder) {
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+        entries => entries?,
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let file_type = entry.file_type()?;
+
+        if name.starts_with('@') && file_type.is_dir() {
+            for scoped_entry in std::fs::read_dir(entry.path())? {
+                let scoped_entry = scoped_entry?;
+                let scoped_name = format!("{name}/{}", scoped_entry.file_name().to_string_lossy());
+                prune_direct_link(&scoped_entry.path(), &scoped_name, direct_deps)?;
+            }
+        } else if file_type.is_symlink() {
+            prune_direct_link(&entry.path(), &name, direct_deps)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove `link` if it is not a direct dependency anymore.
+/// Only links into the virtual store are removed, other files are left untouched.
+fn prune_direct_link(link: &Path, name: &String, direct_deps: &HashSet<String>) -> Result<()> {
+    if direct_deps.contains(name) {
+        return Ok(());
+    }
+
+    let Ok(original) = std::fs::read_link(link) else {
+        return Ok(());
+    };
+
+    if original
+        .components()
+        .any(|component| component.as_os_str() == STORE_FOLDER)
+    {
+        std::fs::remove_file(link)?;
+    }
+
+    Ok(())
+}
+
+fn get_dep_symlink_path(id: &PackageId) -> PathBuf {
     Path::new("..")
         .join("..")
-        .join(format!("{}@{}", folder_name, version))
+        .join(get_package_folder_name(id))
         .join(DEPS_FOLDER)
-        .join(name)
+        .join(&id.name)
 }
 
-pub fn get_local_store_package_path(package_name: &String, version: &Version) -> PathBuf {
-    let folder_name = if package_name.starts_with("@") {
-        package_name.replace("/", "+")
-    } else {
-        package_name.to_string()
-    };
+/// The folder of a package in the virtual store, `name@version` with the `/`
+/// of scoped packages replaced by `+`.
+pub fn get_virtual_store_folder_name(package_name: &str, version: &Version) -> String {
+    format!("{}@{}", package_name.replace('/', "+"), version)
+}
 
+/// The folder of `id` in the virtual store, `name@version` followed by `_peer@version`
+/// for each of its peers, such as `ajv-keywords@3.5.2_ajv@6.12.6`.
+pub fn get_package_folder_name(id: &PackageId) -> String {
+    let mut folder_name = get_virtual_store_folder_name(&id.name, &id.version);
+    for (name, version) in &id.peers {
+        folder_name.push('_');
+        folder_name.push_str(&get_virtual_store_folder_name(name, version));
+    }
+
+    folder_name
+}
+
+pub fn get_local_store_package_path(id: &PackageId) -> PathBuf {
     Path::new(DEPS_FOLDER)
         .join(STORE_FOLDER)
-        .join(format!("{}@{}", folder_name, &version))
+        .join(get_package_folder_name(id))
         .join("node_modules")
-        .join(&package_name)
+        .join(&id.name)
+}
+
+/// How files are linked from the store into the virtual store of a project.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkMode {
+    Hardlink,
+    /// Hardlinks cannot cross filesystems, so files are copied when the store
+    /// and the project are on different filesystems.
+    Copy,
 }
 
-/// Hardlink all files form `source` recursively into `dest`.
-pub async fn hardlink_package(package_name: &String, version: &Version) -> anyhow::Result<()> {
-    let original = get_store_package_path(package_name, version);
+/// Hardlink when `store_dir` and `deps_folder` are on the same filesystem, copy otherwise.
+pub fn detect_link_mode(store_dir: &Path, deps_folder: &Path) -> Result<LinkMode> {
+    let store_device = std::fs::metadata(store_dir)?.dev();
+    let project_device = std::fs::metadata(deps_folder)?.dev();
 
-    let link = get_local_store_package_path(package_name, version);
+    if store_device == project_device {
+        Ok(LinkMode::Hardlink)
+    } else {
+        Ok(LinkMode::Copy)
+    }
+}
 
-    if let Some(parent) = link.parent() {
-        tokio::fs::create_dir_all(parent).await?;
+/// Hardlink all files of `package` from the store recursively into the virtual store.
+/// `link:` packages are symlinked instead.
+pub async fn hardlink_package(
+    package: &NpmPackageVersion,
+    id: &PackageId,
+    link_mode: LinkMode,
+    config: &Config,
+) -> error::Result<()> {
+    let original = get_package_store_path(&config.store_dir, package).ok_or_else(|| {
+        Error::MissingIntegrity {
+            name: package.name.clone(),
+            version: package.version.clone(),
+            url: package.dist.tarball.to_string(),
+        }
+    })?;
+
+    let link = get_local_store_package_path(id);
+
+    if let Some(PackageSource::Link { .. }) = package.source {
+        let dest = link.clone();
+        task::spawn_blocking(move || force_symlink(&original, &dest))
+            .await?
+            .map_err(|source| Error::Link {
+                name: package.name.clone(),
+                version: package.version.clone(),
+                path: link,
+                source,
+            })?;
+    } else {
+        // folders on disk change between installs, so they are linked again every time,
+        // other packages only when a previous install did not link them yet.
+        let replace = matches!(package.source, Some(PackageSource::Directory { .. }));
+        if replace || tokio::fs::metadata(&link).await.is_err() {
+            let dest = link.clone();
+            task::spawn_blocking(move || link_package(&original, &dest, link_mode, replace))
+                .await?
+                .map_err(|source| Error::Link {
+                    name: package.name.clone(),
+                    version: package.version.clone(),
+                    path: link,
+                    source,
+                })?;
+        }
     }
 
-    task::spawn_blocking(|| hardlink(original, link)).await??;
+    config.reporter.report(Event::Linked {
+        name: package.name.clone(),
+        version: package.version.clone(),
+    });
 
     Ok(())
 }
 
-fn hardlink(source: PathBuf, dest: PathBuf) -> anyhow::Result<()> {
-    let files = std::fs::read_dir(source)?;
+/// Link the files of `source` into `dest` as `hardlink` does, in a temporary folder next
+/// to `dest` that is moved in place once complete. An interrupted install never leaves
+/// a partly linked package behind, that later installs would take as linked.
+/// What `dest` had is replaced if `replace`, kept otherwise.
+fn link_package(source: &Path, dest: &Path, link_mode: LinkMode, replace: bool) -> Result<()> {
+    let parent = dest.parent().unwrap_or_else(|| Path::new("."));
+    std::fs::create_dir_all(parent)?;
+    let temp_dir = tempfile::Builder::new()
+        .prefix(".tmp-")
+        .tempdir_in(parent)?;
+    let linked = temp_dir.path().join("package");
+    hardlink(source, &linked, link_mode)?;
+
+    if replace && std::fs::symlink_metadata(dest).is_ok() {
+        // removed along with `temp_dir`.
+        std::fs::rename(dest, temp_dir.path().join("previous"))?;
+    }
+    // `temp_dir` removes the linked files when the package was linked concurrently.
+    match std::fs::rename(&linked, dest) {
+        Err(_) if std::fs::symlink_metadata(dest).is_ok() => Ok(()),
+        result => result,
+    }
+}
 
-    files.for_each(|file| {
-        if let Ok(file) = file {
-            if let Ok(file_type) = file.file_type() {
-                if file_type.is_dir() && file.file_name() != "node_modules" {
-                    let sub_dir = dest.join(&file.file_name());
-
-                    match std::fs::create_dir_all(&sub_dir) {
-                        Err(error) if error.kind() != ErrorKind::AlreadyExists => {
-                            // return Err(error);
-                        }
-                        _ => {}
-                    }
-
-                    match hardlink(file.path().clone(), sub_dir) {
-                        Err(error) if error.downcast_ref() == Some(&ErrorKind::AlreadyExists) => {
-                            // return Err(error)
-                        }
-                        _ => {}
-                    }
-                } else if file_type.is_file() {
-                    match std::fs::create_dir_all(&dest) {
-                        Err(error) if error.kind() != ErrorKind::AlreadyExists => {
-                            // return Err(error.into())
-                        }
-                        _ => {}
-                    }
-
-                    match std::fs::hard_link(file.path(), dest.join(file.file_name())) {
-                        Err(error) if error.kind() != ErrorKind::AlreadyExists => {
-                            // return Err(error.into())
-                        }
-                        _ => {}
-                    }
-                }
+/// Link the files of `source` into `dest` recursively, skipping nested `node_modules`.
+fn hardlink(source: &Path, dest: &Path, link_mode: LinkMode) -> Result<()> {
+    let files = std::fs::read_dir(source)?;
+    std::fs::create_dir_all(dest)?;
+
+    for file in files {
+        let file = file?;
+        let file_type = file.file_type()?;
+
+        if file_type.is_dir() && file.file_name() != "node_modules" {
+            hardlink(&file.path(), &dest.join(file.file_name()), link_mode)?;
+        } else if file_type.is_file() {
+            match link_file(&file.path(), &dest.join(file.file_name()), link_mode) {
+                Err(error) if error.kind() != ErrorKind::AlreadyExists => return Err(error),
+                _ => {}
             }
         }
-    });
+    }
 
     Ok(())
 }
 
+fn link_file(original: &Path, link: &Path, link_mode: LinkMode) -> Result<()> {
+    match link_mode {
+        LinkMode::Hardlink => match std::fs::hard_link(original, link) {
+            Err(error) if error.raw_os_error() == Some(EXDEV) => {
+                std::fs::copy(original, link).map(|_| ())
+            }
+            result => result,
+        },
+        LinkMode::Copy => std::fs::copy(original, link).map(|_| ()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn id(name: &str, version: &str) -> PackageId {
+        PackageId::new(
+            String::from(name),
+            Version::new(String::from(version)),
+            vec![],
+        )
+    }
+
     #[test]
     fn symlink_path_no_scope() {
-        let path =
-            get_dep_symlink_path(&String::from("react"), &Version::new(String::from("1.0.0")));
+        let path = get_dep_symlink_path(&id("react", "1.0.0"));
 
         assert_eq!(
             path.to_str().unwrap().to_string(),
@@ -173,10 +407,7 @@ mod tests {
 
     #[test]
     fn symlink_path_with_scope() {
-        let path = get_dep_symlink_path(
-            &String::from("@react/dom"),
-            &Version::new(String::from("1.0.0")),
-        );
+        let path = get_dep_symlink_path(&id("@react/dom", "1.0.0"));
 
         assert_eq!(
             path.to_str().unwrap().to_string(),
@@ -186,27 +417,189 @@ mod tests {
 
     #[test]
     fn local_store_path_no_scope() {
-        let path = get_local_store_package_path(
-            &String::from("react"),
-            &Version::new(String::from("1.0.0")),
-        );
+        let path = get_local_store_package_path(&id("react", "1.0.0"));
 
         assert_eq!(
             path.to_str().unwrap().to_string(),
-            format!("node_modules/.fpm/react@1.0.0/node_modules/react")
+            format!("node_modules/.mnpm/react@1.0.0/node_modules/react")
         )
     }
 
     #[test]
     fn local_store_path_with_scope() {
-        let path = get_local_store_package_path(
-            &String::from("@react/dom"),
-            &Version::new(String::from("1.0.0")),
-        );
+        let path = get_local_store_package_path(&id("@react/dom", "1.0.0"));
 
         assert_eq!(
             path.to_str().unwrap().to_string(),
-            format!("node_modules/.fpm/@react+dom@1.0.0/node_modules/@react/dom")
+            format!("node_modules/.mnpm/@react+dom@1.0.0/node_modules/@react/dom")
+        )
+    }
+
+    #[test]
+    fn folder_name_with_peers() {
+        let mut package = id("ajv-keywords", "3.5.2");
+        assert_eq!(get_package_folder_name(&package), "ajv-keywords@3.5.2");
+
+        package.peers = vec![
+            (
+                String::from("@types/node"),
+                Version::new(String::from("20.0.0")),
+            ),
+            (String::from("ajv"), Version::new(String::from("6.12.6"))),
+        ];
+        assert_eq!(
+            get_package_folder_name(&package),
+            "ajv-keywords@3.5.2_@types+node@20.0.0_ajv@6.12.6"
+        );
+        assert_eq!(
+            get_dep_symlink_path(&package).to_str().unwrap(),
+            "../../ajv-keywords@3.5.2_@types+node@20.0.0_ajv@6.12.6/node_modules/ajv-keywords"
+        );
+    }
+
+    #[test]
+    fn force_symlink_relinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let link = dir.path().join("node_modules/@react/dom");
+
+        force_symlink(Path::new("../a"), &link).unwrap();
+        force_symlink(Path::new("../a"), &link).unwrap();
+        assert_eq!(std::fs::read_link(&link).unwrap(), Path::new("../a"));
+
+        force_symlink(Path::new("../b"), &link).unwrap();
+        assert_eq!(std::fs::read_link(&link).unwrap(), Path::new("../b"));
+    }
+
+    #[test]
+    fn prunes_unused_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        let deps_folder = dir.path().join(DEPS_FOLDER);
+        let virtual_store = deps_folder.join(STORE_FOLDER);
+
+        for package in ["react@1.0.0", "is-odd@1.0.0", "@react+dom@1.0.0"] {
+            std::fs::create_dir_all(virtual_store.join(package)).unwrap();
+        }
+        for (name, package) in [
+            ("react", "react@1.0.0"),
+            ("is-odd", "is-odd@1.0.0"),
+            ("@react/dom", "@react+dom@1.0.0"),
+        ] {
+            force_symlink(
+                &Path::new(".").join(STORE_FOLDER).join(package),
+                &deps_folder.join(name),
+            )
+            .unwrap();
+        }
+        std::fs::create_dir_all(deps_folder.join("not-managed")).unwrap();
+
+        prune_virtual_store(
+            &virtual_store,
+            &HashSet::from_iter([String::from("react@1.0.0")]),
+        )
+        .unwrap();
+        prune_direct_links(&deps_folder, &HashSet::from_iter([String::from("react")])).unwrap();
+
+        assert_eq!(
+            virtual_store_packages(&virtual_store).unwrap(),
+            HashSet::from_iter([String::from("react@1.0.0")])
+        );
+        assert!(!virtual_store.join("is-odd@1.0.0").exists());
+        assert!(!virtual_store.join("@react+dom@1.0.0").exists());
+        assert!(deps_folder.join("react").is_symlink());
+        assert!(!deps_folder.join("is-odd").is_symlink());
+        assert!(!deps_folder.join("@react/dom").is_symlink());
+        assert!(deps_folder.join("not-managed").exists());
+    }
+
+    #[test]
+    fn hardlinks_on_the_same_filesystem() {
+        let dir = tempfile::tempdir().unwrap();
+        let store_dir = dir.path().join("store");
+        let deps_folder = dir.path().join(DEPS_FOLDER);
+        std::fs::create_dir_all(&store_dir).unwrap();
+        std::fs::create_dir_all(&deps_folder).unwrap();
+
+        assert_eq!(
+            detect_link_mode(&store_dir, &deps_folder).unwrap(),
+            LinkMode::Hardlink
+        );
+
+        std::fs::create_dir_all(store_dir.join("lib")).unwrap();
+        std::fs::write(store_dir.join("lib/index.js"), "").unwrap();
+        for link_mode in [LinkMode::Hardlink, LinkMode::Copy] {
+            let dest = deps_folder.join(format!("{link_mode:?}"));
+            hardlink(&store_dir, &dest, link_mode).unwrap();
+
+            let nlink = std::fs::metadata(dest.join("lib/index.js"))
+                .unwrap()
+                .nlink();
+            assert_eq!(nlink > 1, link_mode == LinkMode::Hardlink);
+        }
+    }
+
+    #[test]
+    fn links_packages_once_complete() {
+        let dir = tempfile::tempdir().unwrap();
+        let store_dir = dir.path().join("store");
+        std::fs::create_dir_all(store_dir.join("lib")).unwrap();
+        std::fs::write(store_dir.join("lib/index.js"), "").unwrap();
+        let parent = dir.path().join(DEPS_FOLDER).join("@react");
+        let entries = || {
+            let mut entries: Vec<_> = std::fs::read_dir(&parent)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            entries.sort();
+            entries
+        };
+
+        let missing = link_package(
+            &dir.path().join("missing"),
+            &parent.join("missing"),
+            LinkMode::Hardlink,
+            false,
+        );
+        assert!(missing.is_err());
+        assert!(entries().is_empty());
+
+        link_package(&store_dir, &parent.join("dom"), LinkMode::Hardlink, false).unwrap();
+        assert!(parent.join("dom/lib/index.js").exists());
+        assert_eq!(entries(), ["dom"]);
+    }
+
+    #[test]
+    fn relinks_replaced_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("packages/utils");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("index.js"), "").unwrap();
+        let dest = dir.path().join(DEPS_FOLDER).join("utils");
+        link_package(&source, &dest, LinkMode::Hardlink, true).unwrap();
+
+        std::fs::remove_file(source.join("index.js")).unwrap();
+        std::fs::write(source.join("main.js"), "").unwrap();
+        link_package(&source, &dest, LinkMode::Hardlink, false).unwrap();
+        assert!(dest.join("index.js").exists());
+
+        link_package(&source, &dest, LinkMode::Hardlink, true).unwrap();
+        assert!(!dest.join("index.js").exists());
+        assert!(dest.join("main.js").exists());
+        assert_eq!(
+            std::fs::read_dir(dest.parent().unwrap()).unwrap().count(),
+            1
+        );
+    }
+
+    #[test]
+    fn hardlink_reports_missing_packages() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let error = hardlink(
+            &dir.path().join("store"),
+            &dir.path().join(DEPS_FOLDER),
+            LinkMode::Hardlink,
         )
+        .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
     }
 }
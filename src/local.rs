@@ -0,0 +1,320 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use reqwest::Url;
+use serde_json::{json, Value};
+use tokio::{fs, task};
+
+use crate::{
+    downloader::{hash_key, read_tarball_manifest, sha512_integrity},
+    error::{Error, Result},
+    npm::{NpmPackageVersion, PackageSource, VersionRangeSpecifier},
+};
+
+/// A dependency on a package on disk: a folder - `file:./vendor/foo`,
+/// a tarball - `file:./packages/foo.tgz`, or a folder to symlink - `link:../foo`.
+/// Relative paths are relative to the folder of the package.json declaring them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LocalSpec {
+    Directory(PathBuf),
+    Tarball(PathBuf),
+    Link(PathBuf),
+}
+
+impl LocalSpec {
+    /// Parse `spec`, returns None if it is not a local dependency.
+    pub fn parse(spec: &str) -> Option<Self> {
+        if let Some(path) = spec.strip_prefix("link:") {
+            return Some(Self::Link(PathBuf::from(path)));
+        }
+
+        let path = spec.strip_prefix("file:")?;
+        // `file:///path` urls as well as `file:path`.
+        let path = PathBuf::from(path.strip_prefix("//").unwrap_or(path));
+        let file_name = path.file_name()?.to_string_lossy();
+        if [".tgz", ".tar.gz", ".tar"]
+            .iter()
+            .any(|extension| file_name.ends_with(extension))
+        {
+            Some(Self::Tarball(path))
+        } else {
+            Some(Self::Directory(path))
+        }
+    }
+
+    /// The same spec with its path made absolute from `dir`.
+    #[must_use]
+    pub fn rebase(self, dir: &Path) -> Self {
+        match self {
+            Self::Directory(path) => Self::Directory(dir.join(path)),
+            Self::Tarball(path) => Self::Tarball(dir.join(path)),
+            Self::Link(path) => Self::Link(dir.join(path)),
+        }
+    }
+}
+
+impl fmt::Display for LocalSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Directory(path) | Self::Tarball(path) => write!(f, "file:{}", path.display()),
+            Self::Link(path) => write!(f, "link:{}", path.display()),
+        }
+    }
+}
+
+/// Read the package of `spec`, with a relative path relative to `dir`. `name` is the
+/// name of the package when it has no package.json or its package.json has no name.
+/// The dependencies of folders and tarballs are installed, not the ones of links.
+/// Their own local dependencies are made absolute, relative to the folder they are in.
+pub async fn resolve_local(name: &str, spec: &LocalSpec, dir: &Path) -> Result<NpmPackageVersion> {
+    let (LocalSpec::Directory(path) | LocalSpec::Tarball(path) | LocalSpec::Link(path)) = spec;
+    let local_error = |reason| Error::LocalPackage {
+        path: path.clone(),
+        reason,
+    };
+    let path = match fs::canonicalize(dir.join(path)).await {
+        Ok(path) => path,
+        Err(error) => return Err(local_error(error.to_string())),
+    };
+    let url = Url::from_file_path(&path)
+        .map_err(|()| local_error(String::from("invalid path")))?
+        .to_string();
+
+    let package_dir = match spec {
+        LocalSpec::Tarball(_) => path.parent().unwrap_or(&path).to_path_buf(),
+        LocalSpec::Directory(_) | LocalSpec::Link(_) => path.clone(),
+    };
+
+    let version = match spec {
+        LocalSpec::Directory(_) => {
+            let manifest = read_manifest(&path.join("package.json"))
+                .await
+                .map_err(local_error)?;
+            NpmPackageVersion::from_manifest(
+                manifest,
+                name,
+                &format!("file.{}", hash_key(&url)),
+                json!({ "shasum": "", "tarball": url }),
+                PackageSource::Directory { path },
+            )
+        }
+        LocalSpec::Tarball(_) => {
+            let content = fs::read(&path).await?;
+            let (integrity, manifest) = task::spawn_blocking(move || {
+                let manifest = read_tarball_manifest(content.as_slice());
+                (sha512_integrity(&content), manifest)
+            })
+            .await?;
+            let manifest = match manifest {
+                Ok(Some(manifest)) => serde_json::from_slice(&manifest)
+                    .map_err(|error| local_error(format!("invalid package.json, {error}")))?,
+                Ok(None) => {
+                    return Err(local_error(String::from("the tarball has no package.json")))
+                }
+                Err(error) => return Err(local_error(error.to_string())),
+            };
+            // a tarball gets a folder in the virtual store per content, as in the store.
+            NpmPackageVersion::from_manifest(
+                manifest,
+                name,
+                &format!("file.{}", hash_key(&integrity)),
+                json!({ "shasum": "", "tarball": url, "integrity": integrity }),
+                PackageSource::Tarball { path },
+            )
+        }
+        LocalSpec::Link(_) => {
+            let manifest = match read_manifest(&path.join("package.json")).await {
+                Ok(Value::Object(manifest)) => manifest,
+                _ => serde_json::Map::new(),
+            };
+            // only the name and version of links are read, not their dependencies.
+            let manifest: serde_json::Map<_, _> = manifest
+                .into_iter()
+                .filter(|(key, _)| key == "name" || key == "version")
+                .collect();
+            NpmPackageVersion::from_manifest(
+                Value::Object(manifest),
+                name,
+                &format!("link.{}", hash_key(&url)),
+                json!({ "shasum": "", "tarball": url }),
+                PackageSource::Link { path },
+            )
+        }
+    };
+
+    let mut version = version.map_err(local_error)?;
+    for range in version
+        .dependencies
+        .values_mut()
+        .chain(version.optional_dependencies.values_mut())
+    {
+        if let Some(spec) = LocalSpec::parse(range) {
+            *range = VersionRangeSpecifier::new(spec.rebase(&package_dir).to_string());
+        }
+    }
+
+    Ok(version)
+}
+
+async fn read_manifest(path: &Path) -> std::result::Result<Value, String> {
+    let manifest = fs::read(path)
+        .await
+        .map_err(|error| format!("failed to read package.json, {error}"))?;
+
+    serde_json::from_slice(&manifest).map_err(|error| format!("invalid package.json, {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    #[test]
+    fn parses_local_specs() {
+        let cases = [
+            (
+                "file:./vendor/foo",
+                Some(LocalSpec::Directory(PathBuf::from("./vendor/foo"))),
+            ),
+            (
+                "file:../foo",
+                Some(LocalSpec::Directory(PathBuf::from("../foo"))),
+            ),
+            (
+                "file:///vendor/foo",
+                Some(LocalSpec::Directory(PathBuf::from("/vendor/foo"))),
+            ),
+            (
+                "file:packages/foo-1.0.0.tgz",
+                Some(LocalSpec::Tarball(PathBuf::from("packages/foo-1.0.0.tgz"))),
+            ),
+            (
+                "file:foo.tar.gz",
+                Some(LocalSpec::Tarball(PathBuf::from("foo.tar.gz"))),
+            ),
+            (
+                "file:foo-1.0.0.tar",
+                Some(LocalSpec::Tarball(PathBuf::from("foo-1.0.0.tar"))),
+            ),
+            (
+                "link:../foo",
+                Some(LocalSpec::Link(PathBuf::from("../foo"))),
+            ),
+            ("git+file:///foo.git", None),
+            ("^1.0.0", None),
+        ];
+
+        for (spec, expected) in cases {
+            assert_eq!(LocalSpec::parse(spec), expected, "{spec}");
+        }
+    }
+
+    #[tokio::test]
+    async fn resolves_local_packages() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let vendored = dir.path().join("vendor/foo");
+        std::fs::create_dir_all(&vendored).unwrap();
+        std::fs::write(
+            vendored.join("package.json"),
+            r#"{ "name": "foo", "version": "1.0.0", "dependencies": { "bar": "^1.0.0" } }"#,
+        )
+        .unwrap();
+
+        let tarball = dir.path().join("foo-2.0.0.tgz");
+        let mut archive = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        let manifest = br#"{ "name": "foo", "version": "2.0.0" }"#;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest.len() as u64);
+        header.set_mode(0o644);
+        archive
+            .append_data(&mut header, "package/package.json", &manifest[..])
+            .unwrap();
+        let content = archive.into_inner().unwrap().finish().unwrap();
+        std::fs::File::create(&tarball)
+            .unwrap()
+            .write_all(&content)
+            .unwrap();
+
+        let directory = resolve_local("foo", &LocalSpec::Directory(vendored.clone()), dir.path())
+            .await
+            .unwrap();
+        assert!(directory.version.starts_with("1.0.0+file."));
+        assert_eq!(directory.dependencies.len(), 1);
+        assert_eq!(
+            directory.source,
+            Some(PackageSource::Directory {
+                path: vendored.canonicalize().unwrap()
+            })
+        );
+
+        let packed = resolve_local("foo", &LocalSpec::Tarball(tarball.clone()), dir.path())
+            .await
+            .unwrap();
+        assert!(packed.version.starts_with("2.0.0+file."));
+        assert_eq!(packed.dist.integrity, Some(sha512_integrity(&content)));
+        assert_eq!(
+            Url::parse(&packed.dist.tarball).unwrap().to_file_path(),
+            Ok(tarball.canonicalize().unwrap())
+        );
+
+        let link = resolve_local("bar", &LocalSpec::Link(vendored), dir.path())
+            .await
+            .unwrap();
+        assert_eq!(link.name, "foo");
+        assert!(link.version.starts_with("1.0.0+link."));
+        assert!(link.dependencies.is_empty());
+
+        let missing =
+            resolve_local("baz", &LocalSpec::Link(dir.path().join("baz")), dir.path()).await;
+        assert!(matches!(missing, Err(Error::LocalPackage { .. })));
+    }
+
+    #[tokio::test]
+    async fn resolves_relative_to_the_declaring_package() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        for (package, manifest) in [
+            (
+                "packages/app",
+                r#"{ "dependencies": { "shared": "file:../shared" } }"#,
+            ),
+            (
+                "packages/shared",
+                r#"{ "name": "shared", "version": "1.0.0" }"#,
+            ),
+        ] {
+            std::fs::create_dir_all(dir.join(package)).unwrap();
+            std::fs::write(dir.join(package).join("package.json"), manifest).unwrap();
+        }
+
+        let app = resolve_local(
+            "app",
+            &LocalSpec::parse("file:./packages/app").unwrap(),
+            &dir,
+        )
+        .await
+        .unwrap();
+        let shared = LocalSpec::parse(&app.dependencies["shared"]).unwrap();
+        assert_eq!(
+            shared,
+            LocalSpec::Directory(dir.join("packages/app/../shared"))
+        );
+
+        // the rebased spec resolves the same from anywhere.
+        let shared = resolve_local("shared", &shared, Path::new("/"))
+            .await
+            .unwrap();
+        assert_eq!(
+            shared.source,
+            Some(PackageSource::Directory {
+                path: dir.join("packages/shared")
+            })
+        );
+    }
+}
@@ -1,67 +1,79 @@
 #![deny(clippy::pedantic, clippy::cargo)]
+// duplicated versions of transitive dependencies are out of our hands.
+#![allow(clippy::multiple_crate_versions)]
+use anyhow::Context;
+use clap::Parser;
 use mnpm::{
-    config::Config, install_manifest::install_manifest, install_package::install_package,
-    npm::VersionRangeSpecifier, DEPS_FOLDER, STORE_FOLDER,
+    cli::{Cli, Command, ConfigCommand},
+    config::{Config, ConfigLayers},
+    config_command::{config_get, config_list, config_set},
+    dedupe::dedupe,
+    install_manifest::install_manifest,
+    install_package::install_package,
+    remove_package::remove_package,
+    update_package::update_package,
+};
+use std::{
+    env,
+    io::{self, Write},
+    process::ExitCode,
 };
-use rustc_hash::FxHashMap as HashMap;
-use std::{collections::HashMap, env, fs, io::ErrorKind};
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let mut args = env::args();
-    args.next();
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
 
-    let mut packages = HashMap::new();
-    for package_name in args {
-        packages.insert(
-            package_name,
-            VersionRangeSpecifier::new(String::from("latest")),
-        );
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {error:#}");
+            ExitCode::FAILURE
+        }
     }
-    println!("{packages:?}");
+}
 
-    match fs::remove_dir_all(STORE_FOLDER) {
-        // Err(error) if error.kind() != ErrorKind::AlreadyExists => return Err(error.into()),
-        _ => {}
-    }
-    match fs::remove_dir_all(DEPS_FOLDER) {
-        // Err(error) if error.kind() != ErrorKind::AlreadyExists => return Err(error.into()),
-        _ => {}
-    }
-    match fs::create_dir_all(STORE_FOLDER) {
-        // Err(error) if error.kind() != ErrorKind::AlreadyExists => return Err(error.into()),
-        _ => {}
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    if let Some(dir) = &cli.global.dir {
+        env::set_current_dir(dir)
+            .with_context(|| format!("failed to change directory to {}", dir.display()))?;
     }
-    match fs::create_dir_all(DEPS_FOLDER) {
-        // Err(error) if error.kind() != ErrorKind::AlreadyExists => return Err(error.into()),
-        _ => {}
-    }
-
-    // let ip = lookup_host("registry.npmjs.org:443")
-    //     .await?
-    //     .next()
-    //     .expect("failed to resolve registry.npmjs.org:443");
-    // println!("registry ip: {:?}", ip);
-    let client = reqwest::Client::builder()
-        // .resolve("registry.npmjs.org", ip)
-        // .danger_accept_invalid_certs(true)
-        .build()
-        .expect("failed to build reqwest client");
 
-    let config = Config {
-        client,
-        // npm_registry_ip: ip,
-    };
+    let layers = ConfigLayers::load(cli.global.settings(), &env::current_dir()?)?;
 
-    // let package = &String::from("uuid");
-    // let pac = get_npm_package(package, &config).await?;
+    if let Command::Config { command } = cli.command {
+        let mut stdout = io::stdout().lock();
+        return match command {
+            ConfigCommand::Get { key } => config_get(&layers, &key, &mut stdout),
+            ConfigCommand::Set {
+                key,
+                value,
+                location,
+            } => {
+                let path = config_set(&layers, &key, &value, location)?;
+                writeln!(stdout, "set {key} in {}", path.display())?;
+                Ok(())
+            }
+            ConfigCommand::List => config_list(&layers, &mut stdout),
+        };
+    }
 
-    // println!("{}", serde_json::to_string_pretty(&pac).unwrap());
-    if packages.len() == 0 {
-        install_manifest(&config).await?;
-    } else {
-        install_package(packages, &config).await?;
+    let mut config = Config::from_layers(&layers)?;
+    config.bail = !cli.global.no_bail;
+    config.optional = !cli.global.no_optional;
+    if let Command::Install { scope } = &cli.command {
+        config.dependency_scope = scope.dependency_scope();
     }
+    config.platform = cli.global.platform();
+    config.reporter = cli.global.reporter.create();
 
-    Ok(())
+    Ok(match cli.command {
+        Command::Install { .. } => install_manifest(&config).await,
+        Command::Add { packages } => {
+            install_package(packages.into_iter().map(Into::into).collect(), &config).await
+        }
+        Command::Remove { packages } => remove_package(packages, &config).await,
+        Command::Update { packages, latest } => update_package(packages, latest, &config).await,
+        Command::Dedupe { check } => dedupe(check, &config).await,
+        Command::Config { .. } => unreachable!("handled before loading the config"),
+    }?)
 }
@@ -1,5 +1,7 @@
-use std::collections::HashMap;
+use rustc_hash::FxHashMap as HashMap;
+use std::{fmt, path::PathBuf};
 
+use chrono::{DateTime, Utc};
 use derive_more::{Deref, Display, Into};
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
@@ -19,6 +21,20 @@ pub struct NpmResolvedPackage {
 
     pub versions: IndexMap<Version, NpmPackageVersion>,
     pub modified: String,
+
+    /// The publish dates of the versions, along with `created` and `modified`.
+    #[serde(default)]
+    pub time: HashMap<String, String>,
+}
+
+impl NpmResolvedPackage {
+    /// When `version` was published, if the registry tells.
+    pub fn published(&self, version: &Version) -> Option<DateTime<Utc>> {
+        let time = self.time.get(version.as_str())?;
+        DateTime::parse_from_rfc3339(time)
+            .ok()
+            .map(|time| time.with_timezone(&Utc))
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
@@ -26,10 +42,109 @@ pub struct NpmPackageVersion {
     pub name: String,
     pub version: Version,
 
-    #[serde(default = "HashMap::new")]
+    #[serde(default)]
     pub dependencies: HashMap<String, VersionRangeSpecifier>,
+    /// Dependencies provided by the dependent of this package rather than installed for it.
+    #[serde(default, rename(deserialize = "peerDependencies"))]
+    pub peer_dependencies: HashMap<String, VersionRangeSpecifier>,
+    #[serde(default, rename(deserialize = "peerDependenciesMeta"))]
+    pub peer_dependencies_meta: HashMap<String, PeerDependencyMeta>,
+    /// Dependencies that are skipped when they fail to install or do not support the platform.
+    #[serde(default, rename(deserialize = "optionalDependencies"))]
+    pub optional_dependencies: HashMap<String, VersionRangeSpecifier>,
+    /// The platforms the package supports, as in `process.platform`.
+    /// Entries starting with `!` are the unsupported ones.
+    #[serde(default)]
+    pub os: Vec<String>,
+    #[serde(default)]
+    pub cpu: Vec<String>,
+    #[serde(default)]
+    pub libc: Vec<String>,
     pub dist: NpmVersionDist,
     pub engines: Option<Engines>,
+    /// Where the package comes from when it is not downloaded from a registry.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub source: Option<PackageSource>,
+}
+
+/// Where a package that is not downloaded from a registry comes from.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Hash)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PackageSource {
+    /// A commit of a git repository, packed into the store when it is resolved.
+    Git { url: String, commit: String },
+    /// A folder on disk, linked into the virtual store file by file.
+    Directory { path: PathBuf },
+    /// A tarball on disk, extracted into the store like the tarballs of registries.
+    Tarball { path: PathBuf },
+    /// A folder on disk the package is symlinked to, its dependencies are not installed.
+    Link { path: PathBuf },
+    /// A tarball downloaded from `url`, extracted into the store when it is resolved.
+    Remote { url: String },
+}
+
+impl NpmPackageVersion {
+    /// The version of a package from `source`, read from its package.json `manifest`.
+    /// `build` is added to the version as build metadata, so that packages from different
+    /// sources get their own folder in the virtual store while they still satisfy the ranges
+    /// of their version. `name` is the name of the package when the manifest has none.
+    pub fn from_manifest(
+        manifest: serde_json::Value,
+        name: &str,
+        build: &str,
+        dist: serde_json::Value,
+        source: PackageSource,
+    ) -> Result<Self, String> {
+        let serde_json::Value::Object(mut manifest) = manifest else {
+            return Err(String::from("package.json must contain a JSON object"));
+        };
+
+        let version = manifest
+            .get("version")
+            .and_then(serde_json::Value::as_str)
+            .and_then(|version| version.split('+').next())
+            .unwrap_or("0.0.0");
+        let version = format!("{version}+{build}");
+        manifest
+            .entry("name")
+            .or_insert_with(|| serde_json::Value::String(name.to_string()));
+        manifest.insert(String::from("version"), serde_json::Value::String(version));
+        manifest.insert(String::from("dist"), dist);
+
+        let mut version: Self = serde_json::from_value(serde_json::Value::Object(manifest))
+            .map_err(|error| format!("invalid package.json, {error}"))?;
+        version.source = Some(source);
+
+        Ok(version)
+    }
+
+    /// The dependencies and optional dependencies, with whether each is optional.
+    pub fn all_dependencies(
+        &self,
+    ) -> impl Iterator<Item = (&String, &VersionRangeSpecifier, bool)> {
+        self.dependencies
+            .iter()
+            .filter(|(name, _)| !self.optional_dependencies.contains_key(*name))
+            .map(|(name, range)| (name, range, false))
+            .chain(
+                self.optional_dependencies
+                    .iter()
+                    .map(|(name, range)| (name, range, true)),
+            )
+    }
+
+    /// Whether the peer dependency `name` may be left out.
+    pub fn is_optional_peer(&self, name: &str) -> bool {
+        self.peer_dependencies_meta
+            .get(name)
+            .map_or(false, |meta| meta.optional)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Default)]
+pub struct PeerDependencyMeta {
+    #[serde(default)]
+    pub optional: bool,
 }
 
 #[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
@@ -87,7 +202,8 @@ pub enum VersionParseError {
 impl std::error::Error for VersionParseError {}
 
 /// A semver-compatible version range.
-/// Can be either a range - ">3.0.0", "1.2.4" or tag - "latest".
+/// Can be either a range - ">3.0.0", "1.2.4", a tag - "latest"
+/// or an alias of another package - "npm:string-width@^4".
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Hash, Eq, Deref, Into)]
 #[serde(try_from = "String", into = "String")]
 pub struct VersionRangeSpecifier(String);
@@ -96,6 +212,24 @@ impl VersionRangeSpecifier {
     pub fn new(value: String) -> Self {
         Self(value)
     }
+
+    /// The package and range an `npm:` alias stands for, `latest` when it has no range.
+    pub fn alias(&self) -> Option<(&str, VersionRangeSpecifier)> {
+        let spec = self.0.strip_prefix("npm:")?;
+        // the first `@` of a scoped package belongs to the scope.
+        let separator = match spec.strip_prefix('@') {
+            Some(scoped) => scoped.find('@').map(|index| index + 1),
+            None => spec.find('@'),
+        };
+
+        let (name, range) = match separator {
+            Some(index) => (&spec[..index], spec[index + 1..].trim()),
+            None => (spec, ""),
+        };
+        let range = if range.is_empty() { "latest" } else { range };
+
+        Some((name, Self::new(range.to_string())))
+    }
 }
 
 impl TryFrom<String> for VersionRangeSpecifier {
@@ -136,6 +270,9 @@ pub struct ResolvedDependencyTree {
     pub name: String,
     pub version: NpmPackageVersion,
     pub dependencies: Vec<ResolvedDependencyTree>,
+    /// The package is one of its ancestors, a dependency cycle.
+    /// Its dependencies are left out.
+    pub circular: bool,
 }
 
 impl ResolvedDependencyTree {
@@ -148,7 +285,78 @@ impl ResolvedDependencyTree {
             name,
             version,
             dependencies,
+            circular: false,
+        }
+    }
+
+    /// The back edge of a dependency cycle to `version`.
+    pub fn circular(name: String, version: NpmPackageVersion) -> Self {
+        Self {
+            name,
+            version,
+            dependencies: vec![],
+            circular: true,
+        }
+    }
+
+    fn fmt_dependencies(&self, f: &mut fmt::Formatter<'_>, prefix: &str) -> fmt::Result {
+        for (index, dependency) in self.dependencies.iter().enumerate() {
+            let last = index == self.dependencies.len() - 1;
+            let (branch, indent) = if last {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+
+            write!(
+                f,
+                "\n{prefix}{branch}{}@{}",
+                dependency.name, dependency.version.version
+            )?;
+            if dependency.circular {
+                write!(f, " (circular)")?;
+            }
+            dependency.fmt_dependencies(f, &format!("{prefix}{indent}"))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Prints the tree like `npm ls`, one package per line.
+impl fmt::Display for ResolvedDependencyTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.name, self.version.version)?;
+        if self.circular {
+            write!(f, " (circular)")?;
         }
+        self.fmt_dependencies(f, "")
+    }
+}
+
+/// A package in the virtual store: a version of a package, along with the versions
+/// its peer dependencies resolved to. The same version gets one entry per set of peers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct PackageId {
+    pub name: String,
+    pub version: Version,
+    /// Sorted by name.
+    pub peers: Vec<(String, Version)>,
+}
+
+impl PackageId {
+    pub fn new(name: String, version: Version, peers: Vec<(String, Version)>) -> Self {
+        Self {
+            name,
+            version,
+            peers,
+        }
+    }
+}
+
+impl From<&NpmPackageVersion> for PackageId {
+    fn from(version: &NpmPackageVersion) -> Self {
+        Self::new(version.name.clone(), version.version.clone(), vec![])
     }
 }
 
@@ -156,23 +364,42 @@ impl ResolvedDependencyTree {
 /// and that should be downloaded.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct ResolvedDependencies {
+    pub id: PackageId,
     pub version: NpmPackageVersion,
-    pub dependencies: Vec<NpmPackageVersion>,
-    pub is_root: bool,
+    /// The packages linked next to this one, its dependencies and its peers,
+    /// with the name they are linked under, which differs from theirs for aliases.
+    pub dependencies: Vec<(String, PackageId)>,
+    /// The names of the direct dependencies it was picked for, empty for the others.
+    pub direct_names: Vec<String>,
+    /// Only needed by optional dependencies, so it may fail to install.
+    pub optional: bool,
+    /// Only needed by `devDependencies`, so production installs leave it out.
+    pub dev: bool,
 }
 
 impl ResolvedDependencies {
     pub fn new(
+        id: PackageId,
         version: NpmPackageVersion,
-        dependencies: Vec<NpmPackageVersion>,
-        is_root: bool,
+        dependencies: Vec<(String, PackageId)>,
+        direct_names: Vec<String>,
+        optional: bool,
+        dev: bool,
     ) -> Self {
         Self {
+            id,
             version,
             dependencies,
-            is_root,
+            direct_names,
+            optional,
+            dev,
         }
     }
+
+    /// Whether it is linked into the `node_modules` of the project.
+    pub fn is_root(&self) -> bool {
+        !self.direct_names.is_empty()
+    }
 }
 
 #[derive(Debug, PartialEq, Display)]
@@ -211,4 +438,28 @@ mod tests {
             VersionRangeSpecifier::new(String::from(">1.0.0"))
         )
     }
+
+    #[test]
+    fn parses_aliases() {
+        let alias = |spec: &str| {
+            VersionRangeSpecifier::new(String::from(spec))
+                .alias()
+                .map(|(name, range)| format!("{name} {}", *range))
+        };
+
+        assert_eq!(
+            alias("npm:string-width@^4").as_deref(),
+            Some("string-width ^4")
+        );
+        assert_eq!(
+            alias("npm:@types/node@18").as_deref(),
+            Some("@types/node 18")
+        );
+        assert_eq!(
+            alias("npm:@types/node").as_deref(),
+            Some("@types/node latest")
+        );
+        assert_eq!(alias("npm:react@").as_deref(), Some("react latest"));
+        assert_eq!(alias("^4.0.0"), None);
+    }
 }
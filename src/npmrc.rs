@@ -0,0 +1,371 @@
+use std::{
+    env, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use indexmap::IndexMap;
+use reqwest::Url;
+
+pub const NPMRC_FILE: &str = ".npmrc";
+
+/// The `key=value` settings of an `.npmrc` file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Npmrc {
+    entries: IndexMap<String, String>,
+}
+
+/// Credentials sent in the `Authorization` header of registry requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credentials {
+    /// `_authToken`, sent as `Bearer <token>`.
+    Token(String),
+    /// `_auth` or `username` and `_password`, sent as `Basic <base64>`.
+    Basic(String),
+}
+
+impl Credentials {
+    pub fn header_value(&self) -> String {
+        match self {
+            Self::Token(token) => format!("Bearer {token}"),
+            Self::Basic(auth) => format!("Basic {auth}"),
+        }
+    }
+}
+
+impl Npmrc {
+    /// Parse `content`, expanding `${VAR}` references with `get_env`.
+    pub fn parse_with_env(content: &str, get_env: impl Fn(&str) -> Option<String>) -> Self {
+        let mut entries = IndexMap::new();
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some((key, value)) = line.split_once('=') {
+                entries.insert(
+                    expand_env(key.trim(), &get_env),
+                    expand_env(unquote(value.trim()), &get_env),
+                );
+            }
+        }
+
+        Self { entries }
+    }
+
+    pub fn parse(content: &str) -> Self {
+        Self::parse_with_env(content, |key| env::var(key).ok())
+    }
+
+    /// Read the `.npmrc` at `path`. A missing file is an empty config.
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Set `key` to `value` in the `.npmrc` at `path`, keeping the other lines
+    /// and comments as they are.
+    pub fn set_in_file(path: &Path, key: &str, value: &str) -> std::io::Result<()> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error),
+        };
+
+        let entry = format!("{key}={value}");
+        let mut replaced = false;
+        let mut lines = vec![];
+        for line in content.lines() {
+            let is_key = line
+                .split_once('=')
+                .filter(|_| !line.trim_start().starts_with(['#', ';']))
+                .map_or(false, |(line_key, _)| line_key.trim() == key);
+
+            match (is_key, replaced) {
+                (false, _) => lines.push(line),
+                (true, false) => {
+                    lines.push(&entry);
+                    replaced = true;
+                }
+                // duplicates would be shadowed by the new value anyway.
+                (true, true) => {}
+            }
+        }
+        if !replaced {
+            lines.push(&entry);
+        }
+
+        fs::write(path, lines.join("\n") + "\n")
+    }
+
+    /// Add the entries of `other` that are not set in `self`.
+    pub fn extend_missing(&mut self, other: Self) {
+        for (key, value) in other.entries {
+            self.entries.entry(key).or_insert(value);
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(String::as_str)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// The `@scope:registry=<url>` entries, keyed by scope including the `@`.
+    pub fn scoped_registries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().filter_map(|(key, value)| {
+            key.strip_suffix(":registry")
+                .filter(|scope| scope.starts_with('@'))
+                .map(|scope| (scope, value.as_str()))
+        })
+    }
+
+    /// Find the credentials for a request to `url`.
+    /// Settings scoped to the closest `//host/path/:` prefix of `url` win,
+    /// unscoped `_authToken` and `_auth` only apply to the default `registry`.
+    /// A `_password` that is not base64 is skipped, like a missing one.
+    pub fn get_credentials(&self, url: &Url, registry: &Url) -> Option<Credentials> {
+        for nerf_dart in get_nerf_darts(url) {
+            let get = |key: &str| self.get(&format!("{nerf_dart}:{key}"));
+
+            if let Some(token) = get("_authToken") {
+                return Some(Credentials::Token(token.to_string()));
+            }
+            if let Some(auth) = get("_auth") {
+                return Some(Credentials::Basic(auth.to_string()));
+            }
+            if let (Some(username), Some(password)) = (get("username"), get("_password")) {
+                if let Ok(password) = BASE64.decode(password) {
+                    let password = String::from_utf8_lossy(&password);
+                    return Some(Credentials::Basic(
+                        BASE64.encode(format!("{username}:{password}")),
+                    ));
+                }
+            }
+        }
+
+        if url.host_str() != registry.host_str() || url.port() != registry.port() {
+            return None;
+        }
+
+        self.get("_authToken")
+            .map(|token| Credentials::Token(token.to_string()))
+            .or_else(|| {
+                self.get("_auth")
+                    .map(|auth| Credentials::Basic(auth.to_string()))
+            })
+    }
+}
+
+/// The "nerf darts" of `url`, the schemeless `//host/path/` prefixes npm
+/// uses to scope credentials, from the most to the least specific.
+fn get_nerf_darts(url: &Url) -> Vec<String> {
+    let Some(host) = url.host_str() else {
+        return vec![];
+    };
+    let host = match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    };
+
+    let mut path = url.path();
+    let mut nerf_darts = vec![];
+    while let Some(index) = path.rfind('/') {
+        path = &path[..index];
+        nerf_darts.push(format!("//{host}{path}/"));
+    }
+
+    nerf_darts
+}
+
+/// The user `.npmrc`, `$NPM_CONFIG_USERCONFIG` or `~/.npmrc`.
+pub fn get_user_npmrc_path() -> Option<PathBuf> {
+    match env::var_os("NPM_CONFIG_USERCONFIG") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => env::var_os("HOME").map(|home| PathBuf::from(home).join(NPMRC_FILE)),
+    }
+}
+
+/// Replace `${VAR}` with the value of the environment variable `VAR`.
+/// Unset variables are left untouched, like the npm cli does.
+fn expand_env(value: &str, get_env: impl Fn(&str) -> Option<String>) -> String {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + end];
+
+        expanded.push_str(&rest[..start]);
+        match get_env(name) {
+            Some(env_value) => expanded.push_str(&env_value),
+            None => expanded.push_str(&rest[start..=start + end]),
+        }
+        rest = &rest[start + end + 1..];
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_entries() {
+        let npmrc = Npmrc::parse(
+            r#"
+            # comment
+            ; another comment
+            registry = http://localhost:4873/
+            save-exact="true"
+            invalid line
+            "#,
+        );
+
+        assert_eq!(npmrc.get("registry"), Some("http://localhost:4873/"));
+        assert_eq!(npmrc.get("save-exact"), Some("true"));
+        assert_eq!(npmrc.get("invalid line"), None);
+        assert_eq!(npmrc.get("# comment"), None);
+    }
+
+    #[test]
+    fn missing_file_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert_eq!(
+            Npmrc::load(&dir.path().join(NPMRC_FILE)).unwrap(),
+            Npmrc::default()
+        );
+    }
+
+    #[test]
+    fn expands_env() {
+        let npmrc = Npmrc::parse_with_env(
+            "//npm.corp.dev/:_authToken=${NPM_TOKEN}\ncache=${HOME}/${UNSET}/cache",
+            |key| match key {
+                "NPM_TOKEN" => Some(String::from("secret")),
+                "HOME" => Some(String::from("/home/me")),
+                _ => None,
+            },
+        );
+
+        assert_eq!(npmrc.get("//npm.corp.dev/:_authToken"), Some("secret"));
+        assert_eq!(npmrc.get("cache"), Some("/home/me/${UNSET}/cache"));
+    }
+
+    #[test]
+    fn project_overrides_user() {
+        let mut npmrc = Npmrc::parse("registry=http://localhost:4873/");
+        npmrc.extend_missing(Npmrc::parse(
+            "registry=https://registry.npmjs.org/\n@corp:registry=https://npm.corp.dev/",
+        ));
+
+        assert_eq!(npmrc.get("registry"), Some("http://localhost:4873/"));
+        assert_eq!(
+            npmrc.scoped_registries().collect::<Vec<_>>(),
+            [("@corp", "https://npm.corp.dev/")]
+        );
+    }
+
+    #[test]
+    fn nerf_darts() {
+        let url = Url::parse("https://npm.corp.dev:8443/npm/@corp%2fui").unwrap();
+
+        assert_eq!(
+            get_nerf_darts(&url),
+            ["//npm.corp.dev:8443/npm/", "//npm.corp.dev:8443/"]
+        );
+    }
+
+    #[test]
+    fn credentials() {
+        let registry = Url::parse("https://registry.npmjs.org/").unwrap();
+        let npmrc = Npmrc::parse_with_env(
+            r#"
+            _authToken=public
+            //npm.corp.dev/npm/:_authToken=corp
+            //basic.corp.dev/:_auth=dXNlcjpwYXNz
+            //legacy.corp.dev/:username=user
+            //legacy.corp.dev/:_password=cGFzcw==
+            //legacy.corp.dev/typo/:username=user
+            //legacy.corp.dev/typo/:_password=not base64
+            //registry.npmjs.org/:username=user
+            //registry.npmjs.org/:_password=not base64
+            "#,
+            |_| None,
+        );
+
+        let get = |url: &str| npmrc.get_credentials(&Url::parse(url).unwrap(), &registry);
+
+        assert_eq!(
+            get("https://npm.corp.dev/npm/@corp%2fui"),
+            Some(Credentials::Token(String::from("corp")))
+        );
+        assert_eq!(
+            get("https://npm.corp.dev/npm/@corp/ui/-/ui-1.0.0.tgz"),
+            Some(Credentials::Token(String::from("corp")))
+        );
+        assert_eq!(get("https://npm.corp.dev/other/ui"), None);
+        assert_eq!(
+            get("https://basic.corp.dev/ui"),
+            Some(Credentials::Basic(String::from("dXNlcjpwYXNz")))
+        );
+        assert_eq!(
+            get("https://legacy.corp.dev/ui"),
+            Some(Credentials::Basic(String::from("dXNlcjpwYXNz")))
+        );
+        // the password is not base64, the less specific credentials apply.
+        assert_eq!(
+            get("https://legacy.corp.dev/typo/ui"),
+            Some(Credentials::Basic(String::from("dXNlcjpwYXNz")))
+        );
+        assert_eq!(
+            get("https://registry.npmjs.org/react"),
+            Some(Credentials::Token(String::from("public")))
+        );
+        assert_eq!(get("https://example.com/react"), None);
+    }
+
+    #[test]
+    fn sets_values_in_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(NPMRC_FILE);
+
+        Npmrc::set_in_file(&path, "registry", "http://localhost:4873/").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "registry=http://localhost:4873/\n"
+        );
+
+        fs::write(
+            &path,
+            "# registry=commented\nregistry = old\nsave-exact=true\nregistry=older",
+        )
+        .unwrap();
+        Npmrc::set_in_file(&path, "registry", "http://localhost:4873/").unwrap();
+        Npmrc::set_in_file(&path, "fetch-timeout", "1000").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# registry=commented\nregistry=http://localhost:4873/\nsave-exact=true\nfetch-timeout=1000\n"
+        );
+    }
+}
@@ -0,0 +1,367 @@
+use rustc_hash::FxHashMap as HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::npm::VersionRangeSpecifier;
+
+/// The rules under which a package is resolved, the root of the project
+/// or the override rule that matched one of its dependents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct OverrideScope(Option<usize>);
+
+/// A replacement of the range of a dependency.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Rule {
+    /// The rule the dependent must match, `None` for any dependent.
+    parent: Option<usize>,
+    name: String,
+    /// Only requests of a range within this one are replaced, as in `bar@<2`.
+    condition: Option<node_semver::Range>,
+    /// `None` for rules that only select the dependents of nested rules.
+    range: Option<VersionRangeSpecifier>,
+}
+
+/// The `overrides` of package.json, along with yarn `resolutions` and `mnpm.overrides`.
+/// Rules are nested, a nested rule only applies below the packages matching its parent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Overrides {
+    rules: Vec<Rule>,
+}
+
+impl Overrides {
+    /// Read the overrides of `package_json`. `resolutions` are applied first, then
+    /// `overrides` and `mnpm.overrides`, so the latter win when they replace the same range.
+    /// `$name` values refer to the range of the direct dependency `name`.
+    pub fn from_manifest(
+        package_json: &Value,
+        root_deps: &HashMap<String, VersionRangeSpecifier>,
+    ) -> Result<Self, String> {
+        let mut overrides = Self::default();
+        let read_range = |value: &Value| match value {
+            Value::String(range) => resolve_reference(range, root_deps),
+            _ => Err(format!("expected a version range, found {value}")),
+        };
+
+        if let Some(resolutions) = package_json.get("resolutions") {
+            for (selector, range) in as_object(resolutions, "resolutions")? {
+                let path: Vec<_> = split_yarn_path(selector)
+                    .into_iter()
+                    .filter(|segment| *segment != "**")
+                    .collect();
+                overrides.add_path(&path, read_range(range)?)?;
+            }
+        }
+
+        if let Some(npm_overrides) = package_json.get("overrides") {
+            overrides.add_nested(None, as_object(npm_overrides, "overrides")?, root_deps)?;
+        }
+
+        if let Some(mnpm_overrides) = package_json.pointer("/mnpm/overrides") {
+            for (selector, range) in as_object(mnpm_overrides, "mnpm.overrides")? {
+                let path: Vec<_> = selector.split('>').map(str::trim).collect();
+                overrides.add_path(&path, read_range(range)?)?;
+            }
+        }
+
+        Ok(overrides)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The range to resolve for the dependency `name@range` of a package in `scope`,
+    /// and the scope of the dependency itself.
+    /// The rules nested in the scope apply first, then the rules around them.
+    pub fn apply(
+        &self,
+        scope: OverrideScope,
+        name: &str,
+        range: &VersionRangeSpecifier,
+    ) -> (VersionRangeSpecifier, OverrideScope) {
+        let mut parent = scope.0;
+        loop {
+            let matching = self.rules.iter().enumerate().find(|(_, rule)| {
+                rule.parent == parent && rule.name == name && rule.matches(range)
+            });
+
+            if let Some((index, rule)) = matching {
+                let has_nested = self.rules.iter().any(|rule| rule.parent == Some(index));
+                let nested_scope = if has_nested {
+                    OverrideScope(Some(index))
+                } else {
+                    scope
+                };
+                let range = rule.range.clone().unwrap_or_else(|| range.clone());
+
+                return (range, nested_scope);
+            }
+
+            match parent {
+                Some(index) => parent = self.rules[index].parent,
+                None => return (range.clone(), scope),
+            }
+        }
+    }
+
+    /// Add the npm `overrides` in `map` below `parent`.
+    fn add_nested(
+        &mut self,
+        parent: Option<usize>,
+        map: &Map<String, Value>,
+        root_deps: &HashMap<String, VersionRangeSpecifier>,
+    ) -> Result<(), String> {
+        for (selector, value) in map {
+            match value {
+                Value::String(range) => {
+                    if selector != "." {
+                        let range = resolve_reference(range, root_deps)?;
+                        self.add(parent, selector, Some(range))?;
+                    }
+                }
+                Value::Object(nested) => {
+                    let range = match nested.get(".") {
+                        Some(Value::String(range)) => Some(resolve_reference(range, root_deps)?),
+                        Some(value) => {
+                            return Err(format!("expected a version range, found {value}"))
+                        }
+                        None => None,
+                    };
+                    let index = self.add(parent, selector, range)?;
+                    self.add_nested(Some(index), nested, root_deps)?;
+                }
+                _ => return Err(format!("invalid override of {selector}: {value}")),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add a rule for the last package of `path`, below the packages before it.
+    fn add_path(&mut self, path: &[&str], range: VersionRangeSpecifier) -> Result<(), String> {
+        let Some((name, parents)) = path.split_last() else {
+            return Ok(());
+        };
+
+        let mut parent = None;
+        for selector in parents {
+            parent = Some(self.add(parent, selector, None)?);
+        }
+        self.add(parent, name, Some(range))?;
+
+        Ok(())
+    }
+
+    /// Add the rule for `selector`, `name` or `name@range`, or update the existing one.
+    fn add(
+        &mut self,
+        parent: Option<usize>,
+        selector: &str,
+        range: Option<VersionRangeSpecifier>,
+    ) -> Result<usize, String> {
+        let (name, condition) = match selector.rfind('@') {
+            Some(at) if at > 0 => {
+                let condition = &selector[at + 1..];
+                let condition = node_semver::Range::parse(condition)
+                    .map_err(|error| format!("invalid override {selector}: {error}"))?;
+                (&selector[..at], Some(condition))
+            }
+            _ => (selector, None),
+        };
+
+        let existing = self.rules.iter().position(|rule| {
+            rule.parent == parent && rule.name == name && rule.condition == condition
+        });
+        if let Some(index) = existing {
+            if range.is_some() {
+                self.rules[index].range = range;
+            }
+            return Ok(index);
+        }
+
+        self.rules.push(Rule {
+            parent,
+            name: name.to_string(),
+            condition,
+            range,
+        });
+        Ok(self.rules.len() - 1)
+    }
+}
+
+impl Rule {
+    fn matches(&self, range: &VersionRangeSpecifier) -> bool {
+        match &self.condition {
+            None => true,
+            Some(condition) => node_semver::Range::parse(&**range)
+                .map_or(false, |range| condition.allows_all(&range)),
+        }
+    }
+}
+
+fn as_object<'a>(value: &'a Value, field: &str) -> Result<&'a Map<String, Value>, String> {
+    value
+        .as_object()
+        .ok_or_else(|| format!("{field} must be an object"))
+}
+
+/// Replace a `$name` reference with the range of the direct dependency `name`.
+fn resolve_reference(
+    range: &str,
+    root_deps: &HashMap<String, VersionRangeSpecifier>,
+) -> Result<VersionRangeSpecifier, String> {
+    match range.strip_prefix('$') {
+        Some(name) => root_deps.get(name).cloned().ok_or_else(|| {
+            format!("unable to resolve reference {range}, {name} is not a direct dependency")
+        }),
+        None => Ok(VersionRangeSpecifier::new(range.to_string())),
+    }
+}
+
+/// The packages of a yarn resolution path, such as `@scope/foo/**/bar`.
+fn split_yarn_path(path: &str) -> Vec<&str> {
+    let mut segments = vec![];
+    let mut rest = path;
+    while !rest.is_empty() {
+        // scoped names contain a `/` themselves.
+        let end = if rest.starts_with('@') {
+            rest.match_indices('/').nth(1).map(|(index, _)| index)
+        } else {
+            rest.find('/')
+        };
+
+        match end {
+            Some(end) => {
+                segments.push(&rest[..end]);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                segments.push(rest);
+                rest = "";
+            }
+        }
+    }
+
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn range(range: &str) -> VersionRangeSpecifier {
+        VersionRangeSpecifier::new(String::from(range))
+    }
+
+    /// Resolve the ranges of `path`, a chain of dependencies from the root.
+    fn apply(overrides: &Overrides, path: &[(&str, &str)]) -> Vec<String> {
+        let mut scope = OverrideScope::default();
+        path.iter()
+            .map(|(name, requested)| {
+                let (resolved, nested_scope) = overrides.apply(scope, name, &range(requested));
+                scope = nested_scope;
+                resolved.to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn applies_npm_overrides() {
+        let root_deps = HashMap::from_iter([(String::from("react"), range("^18.2.0"))]);
+        let overrides = Overrides::from_manifest(
+            &json!({
+                "overrides": {
+                    "minimist": "1.2.8",
+                    "foo": { ".": "2.0.0", "bar": "1.0.0", "baz": { "qux": "3.0.0" } },
+                    "semver@<6": "5.7.2",
+                    "react": "$react",
+                }
+            }),
+            &root_deps,
+        )
+        .unwrap();
+
+        assert_eq!(
+            apply(&overrides, &[("a", "^1.0.0"), ("minimist", "^1.0.0")]),
+            ["^1.0.0", "1.2.8"]
+        );
+        assert_eq!(
+            apply(
+                &overrides,
+                &[("foo", "^1.0.0"), ("a", "^1.0.0"), ("bar", "^2.0.0")]
+            ),
+            ["2.0.0", "^1.0.0", "1.0.0"]
+        );
+        assert_eq!(apply(&overrides, &[("bar", "^2.0.0")]), ["^2.0.0"]);
+        assert_eq!(
+            apply(
+                &overrides,
+                &[("foo", "^1.0.0"), ("baz", "^1.0.0"), ("qux", "^1.0.0")]
+            ),
+            ["2.0.0", "^1.0.0", "3.0.0"]
+        );
+        assert_eq!(
+            apply(&overrides, &[("baz", "^1.0.0"), ("qux", "^1.0.0")]),
+            ["^1.0.0", "^1.0.0"]
+        );
+        assert_eq!(apply(&overrides, &[("semver", "^5.1.0")]), ["5.7.2"]);
+        assert_eq!(apply(&overrides, &[("semver", "^7.0.0")]), ["^7.0.0"]);
+        assert_eq!(
+            apply(&overrides, &[("a", "1.0.0"), ("react", "^16.0.0")]),
+            ["1.0.0", "^18.2.0"]
+        );
+    }
+
+    #[test]
+    fn applies_resolutions_and_mnpm_overrides() {
+        let overrides = Overrides::from_manifest(
+            &json!({
+                "resolutions": {
+                    "**/bar": "1.0.0",
+                    "@scope/foo/**/baz": "2.0.0",
+                    "qux": "1.0.0",
+                },
+                "overrides": { "qux": "2.0.0" },
+                "mnpm": { "overrides": { "qux": "3.0.0", "a@1>b": "4.0.0" } },
+            }),
+            &HashMap::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            apply(&overrides, &[("a", "^2.0.0"), ("bar", "^2.0.0")]),
+            ["^2.0.0", "1.0.0"]
+        );
+        assert_eq!(
+            apply(&overrides, &[("@scope/foo", "^1.0.0"), ("baz", "^1.0.0")]),
+            ["^1.0.0", "2.0.0"]
+        );
+        assert_eq!(apply(&overrides, &[("baz", "^1.0.0")]), ["^1.0.0"]);
+        assert_eq!(apply(&overrides, &[("qux", "^1.0.0")]), ["3.0.0"]);
+        assert_eq!(
+            apply(&overrides, &[("a", "^1.2.0"), ("b", "^1.0.0")]),
+            ["^1.2.0", "4.0.0"]
+        );
+        assert_eq!(
+            apply(&overrides, &[("a", "^2.0.0"), ("b", "^1.0.0")]),
+            ["^2.0.0", "^1.0.0"]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_overrides() {
+        let parse = |package_json| Overrides::from_manifest(&package_json, &HashMap::default());
+
+        assert!(parse(json!({})).unwrap().is_empty());
+        assert!(parse(json!({ "overrides": ["foo"] })).is_err());
+        assert!(parse(json!({ "overrides": { "foo": 1 } })).is_err());
+        assert!(parse(json!({ "overrides": { "foo@not a range!": "1.0.0" } })).is_err());
+        assert_eq!(
+            parse(json!({ "overrides": { "foo": "$foo" } })).unwrap_err(),
+            "unable to resolve reference $foo, foo is not a direct dependency"
+        );
+    }
+}
@@ -1,85 +1,176 @@
+use rustc_hash::FxHashMap as HashMap;
 use std::{
-    collections::HashMap,
     env,
     fs::{self, File},
     io::BufReader,
+    path::{Path, PathBuf},
 };
 
-use serde_json::Value;
-use thiserror::Error;
+use serde_json::{Map, Value};
 use tokio::task;
 
-use crate::npm::VersionRangeSpecifier;
+use crate::{
+    dependency_resolver::RootDependencies,
+    error::{Error, Result},
+    npm::VersionRangeSpecifier,
+    overrides::Overrides,
+};
 
-#[derive(Error, Debug)]
-pub enum ManifestError {
-    #[error("Failed to locate manifest file")]
-    ManifestNotFound,
-}
+const MANIFEST_FILE: &str = "package.json";
+const DEPENDENCIES: &str = "dependencies";
+const DEV_DEPENDENCIES: &str = "devDependencies";
 
 pub async fn update_package_manifest(
     packages_to_add: HashMap<String, VersionRangeSpecifier>,
-) -> anyhow::Result<()> {
-    task::spawn_blocking(|| update_manifest(packages_to_add)).await??;
+) -> Result<()> {
+    task::spawn_blocking(|| update_manifest(packages_to_add)).await?
+}
 
-    Ok(())
+pub async fn remove_from_package_manifest(packages_to_remove: Vec<String>) -> Result<()> {
+    task::spawn_blocking(move || remove_from_manifest(&packages_to_remove)).await?
 }
 
-fn update_manifest(packages_to_add: HashMap<String, VersionRangeSpecifier>) -> anyhow::Result<()> {
+fn update_manifest(packages_to_add: HashMap<String, VersionRangeSpecifier>) -> Result<()> {
     let mut package_json = get_manifest_file()?;
 
-    match &mut package_json {
-        Value::Object(package_json) => match package_json.get_mut("dependencies") {
-            Some(deps) => match deps {
-                Value::Object(deps_obj) => {
-                    for (package, range) in packages_to_add {
-                        deps_obj.insert(
-                            package.to_owned(),
-                            Value::String(range.to_owned().to_string()),
-                        );
-                    }
-                }
-                _ => (),
-            },
-            None => match serde_json::to_string(&packages_to_add) {
-                Ok(deps) => {
-                    package_json.insert(String::from("dependencies"), Value::String(deps));
-                }
-                Err(error) => panic!("{}", error),
-            },
-        },
-        _ => panic!("failed to read package.json"),
+    add_to_manifest(&mut package_json, packages_to_add)?;
+
+    write_manifest_file(&package_json)
+}
+
+/// Set the range of `packages_to_add` in `devDependencies` for the ones listed there,
+/// in `dependencies` otherwise.
+fn add_to_manifest(
+    package_json: &mut Value,
+    packages_to_add: HashMap<String, VersionRangeSpecifier>,
+) -> Result<()> {
+    let Value::Object(manifest) = package_json else {
+        return Err(invalid_manifest("it must contain a JSON object"));
     };
 
-    fs::write(
-        "./package.json",
-        serde_json::to_string_pretty(&package_json).unwrap(),
-    )?;
+    for (package, range) in packages_to_add {
+        let is_dev = manifest
+            .get(DEV_DEPENDENCIES)
+            .and_then(|dev_deps| dev_deps.get(&package))
+            .is_some();
+        let field = if is_dev {
+            DEV_DEPENDENCIES
+        } else {
+            DEPENDENCIES
+        };
+
+        let deps = manifest
+            .entry(field)
+            .or_insert_with(|| Value::Object(Map::new()));
+        if let Value::Object(deps_obj) = deps {
+            deps_obj.insert(package, Value::String(range.to_string()));
+        }
+    }
 
     Ok(())
 }
 
-pub fn get_manifest_file() -> anyhow::Result<Value> {
-    let mut manifest_path = env::current_dir()?.join("package.json");
+fn remove_from_manifest(packages_to_remove: &[String]) -> Result<()> {
+    let mut package_json = get_manifest_file()?;
 
-    while !manifest_path.exists() {
-        if let Some(parent) = manifest_path.parent() {
-            if let Some(parent) = parent.parent() {
-                manifest_path = parent.join("package.json");
-            } else {
-                return Err(ManifestError::ManifestNotFound.into());
+    for package in packages_to_remove {
+        let mut removed = false;
+        for field in [DEPENDENCIES, DEV_DEPENDENCIES] {
+            if let Some(Value::Object(deps_obj)) = package_json.get_mut(field) {
+                removed |= deps_obj.remove(package).is_some();
             }
-        } else {
-            return Err(ManifestError::ManifestNotFound.into());
+        }
+
+        if !removed {
+            return Err(Error::DependencyNotFound(package.clone()));
+        }
+    }
+
+    write_manifest_file(&package_json)
+}
+
+fn write_manifest_file(package_json: &Value) -> Result<()> {
+    let content = serde_json::to_string_pretty(package_json)
+        .map_err(|error| invalid_manifest(&error.to_string()))?;
+    fs::write(Path::new(".").join(MANIFEST_FILE), content + "\n")?;
+
+    Ok(())
+}
+
+/// Read the `dependencies`, `devDependencies` and overrides of the closest package.json.
+pub fn get_manifest_dependencies() -> Result<RootDependencies> {
+    let manifest_path = find_manifest_file()?;
+    let mut root = read_root_dependencies(&read_manifest_file(&manifest_path)?)?;
+    root.dir = manifest_path
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+
+    Ok(root)
+}
+
+pub(crate) fn read_root_dependencies(package_json: &Value) -> Result<RootDependencies> {
+    let dependencies = read_dependencies(package_json, DEPENDENCIES)?;
+    let dev_dependencies = read_dependencies(package_json, DEV_DEPENDENCIES)?;
+
+    // `$name` references resolve to the range of either kind of direct dependency.
+    let mut root_deps = dev_dependencies.clone();
+    root_deps.extend(dependencies.clone());
+    let overrides = Overrides::from_manifest(package_json, &root_deps)
+        .map_err(|reason| invalid_manifest(&format!("invalid overrides, {reason}")))?;
+
+    Ok(RootDependencies {
+        dependencies,
+        dev_dependencies,
+        overrides,
+        ..RootDependencies::default()
+    })
+}
+
+fn read_dependencies(
+    package_json: &Value,
+    field: &str,
+) -> Result<HashMap<String, VersionRangeSpecifier>> {
+    match package_json.get(field) {
+        Some(deps) => serde_json::from_value(deps.to_owned())
+            .map_err(|error| invalid_manifest(&format!("invalid {field}, {error}"))),
+        None => Ok(HashMap::default()),
+    }
+}
+
+pub fn get_manifest_file() -> Result<Value> {
+    read_manifest_file(&find_manifest_file()?)
+}
+
+/// The path of the closest package.json, in the current directory or above.
+fn find_manifest_file() -> Result<PathBuf> {
+    let mut manifest_path = env::current_dir()?.join(MANIFEST_FILE);
+
+    while !manifest_path.exists() {
+        match manifest_path.parent().and_then(Path::parent) {
+            Some(parent) => manifest_path = parent.join(MANIFEST_FILE),
+            None => return Err(Error::ManifestNotFound),
         }
     }
 
+    Ok(manifest_path)
+}
+
+fn read_manifest_file(manifest_path: &Path) -> Result<Value> {
     let file = File::open(manifest_path)?;
     let reader = BufReader::new(file);
 
-    let package_json: Value = serde_json::from_reader(reader)?;
+    serde_json::from_reader(reader).map_err(|error| Error::InvalidManifest {
+        path: manifest_path.to_path_buf(),
+        reason: error.to_string(),
+    })
+}
 
-    Ok(package_json)
+fn invalid_manifest(reason: &str) -> Error {
+    Error::InvalidManifest {
+        path: PathBuf::from(MANIFEST_FILE),
+        reason: reason.to_string(),
+    }
 }
 
 #[cfg(test)]
@@ -90,9 +181,84 @@ mod tests {
     use tempfile::{tempdir, tempdir_in};
 
     use super::*;
+    use crate::overrides::OverrideScope;
+
+    #[test]
+    fn reads_dependencies_and_dev_dependencies() {
+        let package_json = json!({
+            "dependencies": { "react": "^18.2.0" },
+            "devDependencies": { "jest": "^29.0.0" },
+        });
+
+        let deps = read_dependencies(&package_json, DEPENDENCIES).unwrap();
+        assert_eq!(deps.len(), 1);
+        assert_eq!(*deps["react"], "^18.2.0");
+        let dev_deps = read_dependencies(&package_json, DEV_DEPENDENCIES).unwrap();
+        assert_eq!(*dev_deps["jest"], "^29.0.0");
+        assert!(read_dependencies(&json!({}), DEV_DEPENDENCIES)
+            .unwrap()
+            .is_empty());
+        assert!(read_dependencies(&json!({ "devDependencies": [] }), DEV_DEPENDENCIES).is_err());
+    }
+
+    #[test]
+    fn reads_overrides() {
+        let root = read_root_dependencies(&json!({
+            "devDependencies": { "typescript": "^5.0.0" },
+            "overrides": { "typescript": "$typescript" },
+        }))
+        .unwrap();
+        let (range, _) = root.overrides.apply(
+            OverrideScope::default(),
+            "typescript",
+            &VersionRangeSpecifier::new(String::from("^4.0.0")),
+        );
+        assert_eq!(*range, "^5.0.0");
+
+        let Err(Error::InvalidManifest { reason, .. }) =
+            read_root_dependencies(&json!({ "overrides": { "react": "$react" } }))
+        else {
+            panic!("expected an invalid manifest");
+        };
+        assert_eq!(
+            reason,
+            "invalid overrides, unable to resolve reference $react, react is not a direct dependency"
+        );
+    }
+
+    #[test]
+    fn updates_dev_dependencies_in_place() {
+        let mut package_json = json!({ "devDependencies": { "jest": "^28.0.0" } });
+
+        add_to_manifest(
+            &mut package_json,
+            HashMap::from_iter([
+                (
+                    String::from("jest"),
+                    VersionRangeSpecifier::new(String::from("^29.0.0")),
+                ),
+                (
+                    String::from("react"),
+                    VersionRangeSpecifier::new(String::from("^18.2.0")),
+                ),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            package_json,
+            json!({
+                "dependencies": { "react": "^18.2.0" },
+                "devDependencies": { "jest": "^29.0.0" },
+            })
+        );
+    }
 
     #[test]
     fn get_manifest_from_pwd() {
+        let _current_dir = crate::CURRENT_DIR
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
         let pwd = std::env::current_dir().unwrap();
 
         let file_content = json!({ "a": 1 });
@@ -115,6 +281,9 @@ mod tests {
 
     #[test]
     fn get_manifest_from_nested() {
+        let _current_dir = crate::CURRENT_DIR
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
         let pwd = std::env::current_dir().unwrap();
 
         let file_content = json!({ "a": 1 });
@@ -138,6 +307,9 @@ mod tests {
 
     #[test]
     fn get_manifest_from_deep_nested() {
+        let _current_dir = crate::CURRENT_DIR
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
         let pwd = std::env::current_dir().unwrap();
 
         let file_content = json!({ "a": 1 });
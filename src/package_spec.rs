@@ -0,0 +1,194 @@
+use std::{fmt, str::FromStr};
+
+use thiserror::Error;
+
+use crate::npm::VersionRangeSpecifier;
+
+const MAX_PACKAGE_NAME_LENGTH: usize = 214;
+
+/// A package requested on the command line.
+/// Can be a bare name - "react", a name with a range - "react@^18",
+/// a scoped name with a tag - "@types/node@latest"
+/// or an alias of another package - "string-width-cjs@npm:string-width@^4".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageSpec {
+    pub name: String,
+    pub range: VersionRangeSpecifier,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum PackageSpecError {
+    #[error("package name cannot be empty")]
+    EmptyName,
+    #[error("invalid package name \"{name}\": {reason}")]
+    InvalidName { name: String, reason: &'static str },
+}
+
+impl FromStr for PackageSpec {
+    type Err = PackageSpecError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        // the first `@` of a scoped package belongs to the scope.
+        let separator = match spec.strip_prefix('@') {
+            Some(scoped) => scoped.find('@').map(|index| index + 1),
+            None => spec.find('@'),
+        };
+
+        let (name, range) = match separator {
+            Some(index) => (&spec[..index], &spec[index + 1..]),
+            None => (spec, ""),
+        };
+
+        validate_package_name(name)?;
+
+        let range = if range.trim().is_empty() {
+            "latest"
+        } else {
+            range.trim()
+        };
+        let range = VersionRangeSpecifier::new(range.to_string());
+        if let Some((aliased, _)) = range.alias() {
+            validate_package_name(aliased)?;
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            range,
+        })
+    }
+}
+
+impl fmt::Display for PackageSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.name, *self.range)
+    }
+}
+
+impl From<PackageSpec> for (String, VersionRangeSpecifier) {
+    fn from(spec: PackageSpec) -> Self {
+        (spec.name, spec.range)
+    }
+}
+
+/// Check `name` against the rules the npm registry applies to new package names.
+pub fn validate_package_name(name: &str) -> Result<(), PackageSpecError> {
+    let invalid = |reason| {
+        Err(PackageSpecError::InvalidName {
+            name: name.to_string(),
+            reason,
+        })
+    };
+
+    if name.is_empty() {
+        return Err(PackageSpecError::EmptyName);
+    }
+    if name.len() > MAX_PACKAGE_NAME_LENGTH {
+        return invalid("name can no longer contain more than 214 characters");
+    }
+    if name.trim() != name {
+        return invalid("name cannot contain leading or trailing spaces");
+    }
+    if name.to_lowercase() != name {
+        return invalid("name can no longer contain capital letters");
+    }
+
+    let package_name = match name.strip_prefix('@') {
+        Some(scoped) => match scoped.split_once('/') {
+            Some((scope, package_name)) if !scope.is_empty() => {
+                validate_name_part(scope).or_else(invalid)?;
+                package_name
+            }
+            _ => return invalid("scoped name must look like @scope/name"),
+        },
+        None => name,
+    };
+
+    if package_name.is_empty() {
+        return invalid("name cannot be empty");
+    }
+    if package_name.starts_with('.') || package_name.starts_with('_') {
+        return invalid("name cannot start with a period or an underscore");
+    }
+    if package_name == "node_modules" || package_name == "favicon.ico" {
+        return invalid("name is not allowed");
+    }
+
+    validate_name_part(package_name).or_else(invalid)
+}
+
+fn validate_name_part(part: &str) -> Result<(), &'static str> {
+    let is_url_safe = |char: char| {
+        char.is_ascii_alphanumeric()
+            || matches!(char, '-' | '.' | '_' | '~' | '!' | '*' | '\'' | '(' | ')')
+    };
+
+    if part.chars().all(is_url_safe) {
+        Ok(())
+    } else {
+        Err("name can only contain URL-friendly characters")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_package_specs() {
+        let cases = [
+            ("react", "react", "latest"),
+            ("react@", "react", "latest"),
+            ("react@^18.2.0", "react", "^18.2.0"),
+            ("react@canary", "react", "canary"),
+            ("is-even@>=1.0.0 <2", "is-even", ">=1.0.0 <2"),
+            ("@types/node", "@types/node", "latest"),
+            ("@types/node@18", "@types/node", "18"),
+            ("@next/env@latest", "@next/env", "latest"),
+            ("foo@npm:bar", "foo", "npm:bar"),
+            ("@a/b@npm:@c/d@^1", "@a/b", "npm:@c/d@^1"),
+        ];
+
+        for (spec, name, range) in cases {
+            assert_eq!(
+                spec.parse::<PackageSpec>(),
+                Ok(PackageSpec {
+                    name: name.to_string(),
+                    range: VersionRangeSpecifier::new(range.to_string()),
+                }),
+                "{spec}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        let cases = [
+            "",
+            "@latest",
+            "@types",
+            "@/node",
+            "@types/",
+            "React",
+            ".bin",
+            "_private",
+            "node_modules",
+            "foo bar",
+            "foo/bar",
+            " foo",
+            "foo@npm:",
+            "foo@npm:Bar@1",
+        ];
+
+        for spec in cases {
+            assert!(spec.parse::<PackageSpec>().is_err(), "{spec}");
+        }
+    }
+
+    #[test]
+    fn rejects_long_names() {
+        let name = "a".repeat(MAX_PACKAGE_NAME_LENGTH + 1);
+
+        assert!(validate_package_name(&name).is_err());
+        assert!(validate_package_name(&name[1..]).is_ok());
+    }
+}
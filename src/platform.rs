@@ -0,0 +1,122 @@
+use std::env::consts;
+
+use crate::npm::NpmPackageVersion;
+
+/// The platform packages are installed for, named like `process.platform` and
+/// `process.arch` in node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Platform {
+    pub os: String,
+    pub cpu: String,
+    /// `glibc` or `musl`, only known on linux.
+    pub libc: Option<String>,
+}
+
+impl Platform {
+    /// The platform mnpm runs on.
+    pub fn current() -> Self {
+        let os = match consts::OS {
+            "macos" => "darwin",
+            "windows" => "win32",
+            "solaris" => "sunos",
+            os => os,
+        };
+        let cpu = match consts::ARCH {
+            "x86_64" => "x64",
+            "x86" => "ia32",
+            "aarch64" => "arm64",
+            "powerpc64" => "ppc64",
+            "loongarch64" => "loong64",
+            arch => arch,
+        };
+        let libc = (os == "linux").then(|| {
+            if cfg!(target_env = "musl") {
+                String::from("musl")
+            } else {
+                String::from("glibc")
+            }
+        });
+
+        Self {
+            os: os.to_string(),
+            cpu: cpu.to_string(),
+            libc,
+        }
+    }
+
+    /// Whether the `os`, `cpu` and `libc` fields of `package` allow this platform.
+    pub fn supports(&self, package: &NpmPackageVersion) -> bool {
+        matches(&package.os, Some(&self.os))
+            && matches(&package.cpu, Some(&self.cpu))
+            && matches(&package.libc, self.libc.as_deref())
+    }
+}
+
+impl Default for Platform {
+    fn default() -> Self {
+        Self::current()
+    }
+}
+
+/// Whether `value` is allowed by `list`: listed, or not excluded with `!` when
+/// nothing is listed without it. An empty list allows everything.
+fn matches(list: &[String], value: Option<&str>) -> bool {
+    let mut allowed = None;
+    for entry in list {
+        match entry.strip_prefix('!') {
+            Some(excluded) if Some(excluded) == value => return false,
+            Some(_) => {}
+            None => {
+                allowed = Some(allowed.unwrap_or(false) || Some(entry.as_str()) == value);
+            }
+        }
+    }
+
+    allowed.unwrap_or(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(os: &[&str], cpu: &[&str], libc: &[&str]) -> NpmPackageVersion {
+        let list = |values: &[&str]| values.iter().map(ToString::to_string).collect::<Vec<_>>();
+        serde_json::from_value(serde_json::json!({
+            "name": "@esbuild/linux-x64",
+            "version": "0.18.0",
+            "dist": { "shasum": "", "tarball": "" },
+            "os": list(os),
+            "cpu": list(cpu),
+            "libc": list(libc),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn matches_platforms() {
+        let linux = Platform {
+            os: String::from("linux"),
+            cpu: String::from("x64"),
+            libc: Some(String::from("glibc")),
+        };
+        let darwin = Platform {
+            os: String::from("darwin"),
+            cpu: String::from("arm64"),
+            libc: None,
+        };
+
+        for (package, on_linux, on_darwin) in [
+            (package(&[], &[], &[]), true, true),
+            (package(&["linux"], &["x64"], &[]), true, false),
+            (package(&["linux"], &["x64"], &["musl"]), false, false),
+            (package(&["linux"], &["x64"], &["glibc"]), true, false),
+            (package(&["!win32"], &[], &[]), true, true),
+            (package(&["!linux"], &["arm64", "x64"], &[]), false, true),
+            (package(&["darwin", "linux"], &["!x64"], &[]), false, true),
+            (package(&[], &[], &["!musl"]), true, true),
+        ] {
+            assert_eq!(linux.supports(&package), on_linux, "{package:?}");
+            assert_eq!(darwin.supports(&package), on_darwin, "{package:?}");
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::{io::Cursor, path::PathBuf};
+
+use reqwest::Url;
+use serde_json::json;
+use tokio::{fs, task};
+
+use crate::{
+    config::Config,
+    downloader::{
+        get_remote_index_path, get_store_package_path, hash_key, read_tarball_manifest,
+        sha512_integrity, store_tarball, TarballDigest,
+    },
+    error::{Error, Result},
+    http::get_package_tar,
+    npm::{NpmPackageVersion, NpmVersionDist, PackageSource, UrlString},
+    reporter::Event,
+};
+
+/// The url of a dependency on a tarball served over http, such as
+/// `https://host/pkg-1.2.3.tgz`. returns None for any other range.
+pub fn parse_tarball_url(spec: &str) -> Option<Url> {
+    let url = Url::parse(spec).ok()?;
+    matches!(url.scheme(), "http" | "https").then_some(url)
+}
+
+/// Download the tarball at `url` into the store and read its package.json.
+/// The integrity of the tarball is recorded by url in the store, so later
+/// resolutions read the stored package rather than downloading it again.
+/// `name` is the name of the package when its package.json has none.
+pub async fn resolve_remote(name: &str, url: &Url, config: &Config) -> Result<NpmPackageVersion> {
+    let remote_error = |reason| Error::RemoteTarball {
+        url: url.to_string(),
+        reason,
+    };
+    let index_path = get_remote_index_path(&config.store_dir, url.as_str());
+
+    let stored = match fs::read_to_string(&index_path).await {
+        Ok(integrity) => match store_path(url, &integrity, config).map(|(path, _)| path) {
+            Some(store_path) => fs::read(store_path.join("package.json"))
+                .await
+                .ok()
+                .map(|manifest| (integrity, manifest)),
+            None => None,
+        },
+        Err(_) => None,
+    };
+    let cached = stored.is_some();
+
+    let (integrity, manifest) = match stored {
+        Some(stored) => stored,
+        None => {
+            let content = {
+                let fetch_error = |source| Error::Fetch {
+                    name: name.to_string(),
+                    url: url.to_string(),
+                    source,
+                };
+                let (response, _permit) = get_package_tar(&UrlString::new(url.to_string()), config)
+                    .await
+                    .map_err(fetch_error)?;
+                response.bytes().await.map_err(fetch_error)?
+            };
+
+            let (integrity, manifest) = task::spawn_blocking({
+                let content = content.clone();
+                move || {
+                    (
+                        sha512_integrity(&content),
+                        read_tarball_manifest(&content[..]),
+                    )
+                }
+            })
+            .await?;
+            let manifest = match manifest {
+                Ok(Some(manifest)) => manifest,
+                Ok(None) => {
+                    return Err(remote_error(String::from(
+                        "the tarball has no package.json",
+                    )))
+                }
+                Err(error) => return Err(remote_error(error.to_string())),
+            };
+
+            let Some((store_path, digest)) = store_path(url, &integrity, config) else {
+                unreachable!("the integrity is computed from the tarball");
+            };
+            if fs::metadata(&store_path).await.is_err() {
+                store_tarball(
+                    Cursor::new(content),
+                    &store_path,
+                    &digest,
+                    config,
+                    |error| remote_error(error.to_string()),
+                    |actual| remote_error(format!("the tarball changed, it has {actual}")),
+                )
+                .await?;
+            }
+            if let Some(parent) = index_path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::write(&index_path, &integrity).await?;
+
+            (integrity, manifest)
+        }
+    };
+
+    let manifest = serde_json::from_slice(&manifest)
+        .map_err(|error| remote_error(format!("invalid package.json, {error}")))?;
+    // a url gets a folder in the virtual store per content it served.
+    let version = NpmPackageVersion::from_manifest(
+        manifest,
+        name,
+        &format!("url.{}", hash_key(&format!("{url}#{integrity}"))),
+        dist(url, &integrity),
+        PackageSource::Remote {
+            url: url.to_string(),
+        },
+    )
+    .map_err(remote_error)?;
+
+    config.reporter.report(Event::Fetched {
+        name: version.name.clone(),
+        version: version.version.clone(),
+        cached,
+    });
+    if !cached {
+        config.reporter.report(Event::Extracted {
+            name: version.name.clone(),
+            version: version.version.clone(),
+        });
+    }
+
+    Ok(version)
+}
+
+fn dist(url: &Url, integrity: &str) -> serde_json::Value {
+    json!({ "shasum": "", "tarball": url.as_str(), "integrity": integrity })
+}
+
+/// The folder of the package downloaded from `url` in the store, and the digest it has.
+fn store_path(url: &Url, integrity: &str, config: &Config) -> Option<(PathBuf, TarballDigest)> {
+    let dist: NpmVersionDist = serde_json::from_value(dist(url, integrity)).ok()?;
+    Some((
+        get_store_package_path(&config.store_dir, &dist)?,
+        TarballDigest::from_dist(&dist)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hash::FxHashMap as HashMap;
+
+    use super::*;
+    use crate::{
+        npmrc::Npmrc,
+        test_registry::{TestRegistry, TestResponse},
+    };
+
+    #[test]
+    fn parses_tarball_urls() {
+        assert!(parse_tarball_url("https://host/pkg-1.2.3.tgz").is_some());
+        assert!(parse_tarball_url("http://localhost:8080/pkg.tgz").is_some());
+        assert!(parse_tarball_url("git+https://host/repo.git").is_none());
+        assert!(parse_tarball_url("file:pkg.tgz").is_none());
+        assert!(parse_tarball_url("^1.2.3").is_none());
+    }
+
+    #[tokio::test]
+    async fn reuses_downloaded_tarballs() {
+        let server = TestRegistry::with_routes(HashMap::from_iter([(
+            String::from("/pkg-1.2.3.tgz"),
+            TestResponse::tarball(&[
+                (
+                    "package.json",
+                    r#"{"name":"pkg","version":"1.2.3","dependencies":{"is-odd":"^1.0.0"}}"#,
+                ),
+                ("index.js", "module.exports = 1;"),
+            ]),
+        )]))
+        .await;
+        let store_dir = tempfile::tempdir().unwrap();
+        let config = Config::from_npmrc(
+            Some(server.url.clone()),
+            Some(store_dir.path().to_path_buf()),
+            Npmrc::default(),
+        )
+        .unwrap();
+        let url = server.url.join("pkg-1.2.3.tgz").unwrap();
+
+        let version = resolve_remote("alias", &url, &config).await.unwrap();
+        assert_eq!(version.name, "pkg");
+        assert!(version.version.starts_with("1.2.3+url."));
+        assert_eq!(version.dependencies.len(), 1);
+        let store_path = get_store_package_path(store_dir.path(), &version.dist).unwrap();
+        assert!(store_path.join("index.js").exists());
+
+        let reused = resolve_remote("alias", &url, &config).await.unwrap();
+        assert_eq!(reused, version);
+        assert_eq!(server.requests().len(), 1);
+    }
+}
@@ -0,0 +1,64 @@
+use crate::{
+    config::Config, error::Result, install_manifest::install_manifest,
+    package_manifest::remove_from_package_manifest,
+};
+
+/// Remove `packages` from the dependencies of package.json and reinstall the rest.
+pub async fn remove_package(packages: Vec<String>, config: &Config) -> Result<()> {
+    remove_from_package_manifest(packages).await?;
+
+    install_manifest(config).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::PoisonError};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{npmrc::Npmrc, test_registry::TestRegistry, CURRENT_DIR, DEPS_FOLDER};
+
+    #[test]
+    fn removes_the_last_dependency() {
+        let _current_dir = CURRENT_DIR.lock().unwrap_or_else(PoisonError::into_inner);
+        let pwd = std::env::current_dir().unwrap();
+        let project = tempfile::tempdir().unwrap();
+        let store_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            project.path().join("package.json"),
+            json!({ "dependencies": { "is-odd": "^1.0.0" } }).to_string(),
+        )
+        .unwrap();
+        std::env::set_current_dir(project.path()).unwrap();
+
+        let result = tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let server = TestRegistry::with_packages(json!({ "is-odd": { "1.0.0": {} } })).await;
+            let config = Config::from_npmrc(
+                Some(server.url.clone()),
+                Some(store_dir.path().to_path_buf()),
+                Npmrc::default(),
+            )
+            .unwrap();
+
+            install_manifest(&config).await?;
+            let installed = Path::new(DEPS_FOLDER).join("is-odd").exists();
+            remove_package(vec![String::from("is-odd")], &config).await?;
+
+            Ok::<_, crate::error::Error>(installed)
+        });
+        std::env::set_current_dir(pwd).unwrap();
+
+        assert!(result.unwrap());
+        let deps_folder = project.path().join(DEPS_FOLDER);
+        assert!(!deps_folder.join("is-odd").exists());
+        let packages = std::fs::read_dir(deps_folder.join(crate::STORE_FOLDER))
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_type().unwrap().is_dir());
+        assert_eq!(packages.count(), 0);
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(project.path().join("package.json")).unwrap())
+                .unwrap();
+        assert!(manifest["dependencies"].get("is-odd").is_none());
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    sync::Mutex,
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{error::UnresolvableRange, npm::Version};
+
+/// Something that happened during an install, reported as it happens.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A version was picked for a dependency.
+    Resolved {
+        name: String,
+        version: Version,
+    },
+    /// A package is available in the store, `cached` if it was already there.
+    Fetched {
+        name: String,
+        version: Version,
+        cached: bool,
+    },
+    /// A downloaded package was unpacked into the store.
+    Extracted {
+        name: String,
+        version: Version,
+    },
+    /// A package was linked into the virtual store.
+    Linked {
+        name: String,
+        version: Version,
+    },
+    Warning {
+        message: String,
+    },
+    /// No version matches a requested range, the install fails.
+    Unresolvable(UnresolvableRange),
+    /// The install completed.
+    Summary {
+        packages: usize,
+        direct: Vec<(String, Version)>,
+        #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+        duration: Duration,
+    },
+}
+
+/// Receives the events of every install phase.
+pub trait Reporter: Send + Sync {
+    fn report(&self, event: Event);
+}
+
+/// The `--reporter` output formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ReporterKind {
+    /// Progress on terminals, warnings and a summary otherwise.
+    #[default]
+    Default,
+    /// One JSON event per line.
+    Ndjson,
+    /// Nothing but errors.
+    Silent,
+}
+
+impl ReporterKind {
+    pub fn create(self) -> Box<dyn Reporter> {
+        match self {
+            Self::Default => Box::new(DefaultReporter::new(io::stderr().is_terminal())),
+            Self::Ndjson => Box::new(NdjsonReporter),
+            Self::Silent => Box::new(SilentReporter),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Progress {
+    resolved: usize,
+    fetched: usize,
+    extracted: usize,
+    linked: usize,
+}
+
+/// Draws a single progress line on stderr when `interactive`,
+/// and prints warnings and the summary.
+#[derive(Debug, Default)]
+pub struct DefaultReporter {
+    interactive: bool,
+    progress: Mutex<Progress>,
+}
+
+impl DefaultReporter {
+    pub fn new(interactive: bool) -> Self {
+        Self {
+            interactive,
+            progress: Mutex::default(),
+        }
+    }
+}
+
+impl Reporter for DefaultReporter {
+    fn report(&self, event: Event) {
+        let mut progress = self
+            .progress
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        let mut stderr = io::stderr().lock();
+        if self.interactive {
+            // clear the progress line before anything else is printed.
+            let _ = write!(stderr, "\r\x1b[2K");
+        }
+
+        match event {
+            Event::Resolved { .. } => progress.resolved += 1,
+            Event::Fetched { .. } => progress.fetched += 1,
+            Event::Extracted { .. } => progress.extracted += 1,
+            Event::Linked { .. } => progress.linked += 1,
+            Event::Warning { message } => {
+                let _ = writeln!(stderr, "warning: {message}");
+            }
+            // printed along with the error the install fails with.
+            Event::Unresolvable(_) => {}
+            Event::Summary {
+                packages,
+                direct,
+                duration,
+            } => {
+                let mut stdout = io::stdout().lock();
+                for (name, version) in direct {
+                    let _ = writeln!(stdout, "+ {name} {version}");
+                }
+                let _ = writeln!(
+                    stdout,
+                    "{packages} packages installed in {:.1}s",
+                    duration.as_secs_f64()
+                );
+                return;
+            }
+        }
+
+        if self.interactive {
+            let _ = write!(
+                stderr,
+                "resolved {}, fetched {}, extracted {}, linked {}",
+                progress.resolved, progress.fetched, progress.extracted, progress.linked
+            );
+            let _ = stderr.flush();
+        }
+    }
+}
+
+/// Prints every event as a JSON line on stdout, for CI and other tools.
+#[derive(Debug, Default)]
+pub struct NdjsonReporter;
+
+impl Reporter for NdjsonReporter {
+    fn report(&self, event: Event) {
+        if let Ok(line) = serde_json::to_string(&event) {
+            let _ = writeln!(io::stdout().lock(), "{line}");
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SilentReporter;
+
+impl Reporter for SilentReporter {
+    fn report(&self, _event: Event) {}
+}
+
+/// Keeps every event, for tests.
+#[cfg(test)]
+#[derive(Debug, Default, Clone)]
+pub struct RecordingReporter(pub std::sync::Arc<Mutex<Vec<Event>>>);
+
+#[cfg(test)]
+impl Reporter for RecordingReporter {
+    fn report(&self, event: Event) {
+        self.0.lock().unwrap().push(event);
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn serialize_millis<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::npm::VersionRangeSpecifier;
+
+    #[test]
+    fn serializes_events() {
+        let event = Event::Fetched {
+            name: String::from("react"),
+            version: Version::new(String::from("18.2.0")),
+            cached: true,
+        };
+        assert_eq!(
+            serde_json::to_value(event).unwrap(),
+            json!({ "event": "fetched", "name": "react", "version": "18.2.0", "cached": true })
+        );
+
+        let event = Event::Summary {
+            packages: 3,
+            direct: vec![(String::from("react"), Version::new(String::from("18.2.0")))],
+            duration: Duration::from_millis(1500),
+        };
+        assert_eq!(
+            serde_json::to_value(event).unwrap(),
+            json!({
+                "event": "summary",
+                "packages": 3,
+                "direct": [["react", "18.2.0"]],
+                "duration_ms": 1500
+            })
+        );
+
+        let event = Event::Unresolvable(UnresolvableRange {
+            name: String::from("b"),
+            range: VersionRangeSpecifier::new(String::from("^3")),
+            available: vec![Version::new(String::from("2.1.0"))],
+            closest: vec![Version::new(String::from("2.1.0"))],
+            tags: vec![],
+            path: vec![String::from("root"), String::from("b@^3")],
+        });
+        assert_eq!(
+            serde_json::to_value(event).unwrap(),
+            json!({
+                "event": "unresolvable",
+                "name": "b",
+                "range": "^3",
+                "available": ["2.1.0"],
+                "closest": ["2.1.0"],
+                "path": ["root", "b@^3"]
+            })
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use std::error;
 
+use chrono::{DateTime, Utc};
 use derive_more::Display;
 
 use crate::npm::{NpmPackageVersion, NpmResolvedPackage, Version, VersionRangeSpecifier};
@@ -7,53 +8,164 @@ use crate::npm::{NpmPackageVersion, NpmResolvedPackage, Version, VersionRangeSpe
 #[derive(Debug, Display, PartialEq)]
 pub enum Error {
     VersionRangeResolveError,
+    /// The range is neither semver nor one of the dist-tags of the package.
+    UnknownTag,
 }
 
 impl error::Error for Error {}
 
+/// Which of the versions matching a range is picked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VersionPreference {
+    /// Pick the lowest matching version instead of the highest.
+    pub lowest: bool,
+    /// Leave out the versions published after this date.
+    pub published_before: Option<DateTime<Utc>>,
+}
+
 /// Get a package and a version range,
-/// and return the matching version. It will return None if the version is not found.
-pub fn resolve_version_from_version_range<'a>(
-    package: &'a NpmResolvedPackage,
+/// and return the matching version.
+/// Ranges that are not semver are read as dist-tags, such as `latest` or `next`.
+/// Fails with `UnknownTag` when the range is neither semver nor a dist-tag of the
+/// package, and with `VersionRangeResolveError` when no version matches it, or the
+/// tag points to a missing version.
+/// Like npm, the version `latest` points to is picked when it is in the range, before
+/// the highest matching one. Prereleases only match ranges that mention a prerelease
+/// of the same version, such as `^1.1.0-beta.0`.
+pub fn resolve_version_from_version_range(
+    package: &NpmResolvedPackage,
     version_range: &VersionRangeSpecifier,
+    preference: VersionPreference,
 ) -> Result<NpmPackageVersion, Error> {
-    if *version_range == VersionRangeSpecifier::new(String::from("latest")) {
-        let latest = package.dist_tags.get("latest");
+    // versions without a publish date are kept.
+    let is_published = |version: &Version| match preference.published_before {
+        Some(before) => package
+            .published(version)
+            .map_or(true, |time| time <= before),
+        None => true,
+    };
 
-        return match latest {
-            Some(latest) => package
-                .versions
-                .get(latest)
-                .map(|version| version.to_owned())
-                .ok_or(Error::VersionRangeResolveError),
-            None => Err(Error::VersionRangeResolveError),
-        };
-    }
+    let (version_req, lowest) = match parse_range(version_range) {
+        Some(range) => (range, preference.lowest),
+        None => {
+            let tagged = package
+                .dist_tags
+                .get(version_range.as_str())
+                .ok_or(Error::UnknownTag)?;
 
-    let version_req: node_semver::Range = match version_range.parse() {
-        Ok(req) => req,
-        Err(_) => return Err(Error::VersionRangeResolveError),
-    };
+            if is_published(tagged) {
+                return package
+                    .versions
+                    .get(tagged)
+                    .map(|version| version.to_owned())
+                    .ok_or(Error::VersionRangeResolveError);
+            }
 
-    let mut matched_version: Result<NpmPackageVersion, Error> =
-        Err(Error::VersionRangeResolveError);
+            // the newest version published before the date, that the tag was at the time.
+            let range = format!("<={tagged}").parse::<node_semver::Range>();
+            (range.map_err(|_| Error::VersionRangeResolveError)?, false)
+        }
+    };
 
-    for vrs in package.versions.iter().rev() {
-        matched_version = match vrs.0.parse::<node_semver::Version>() {
-            Ok(parsed_version) if parsed_version.satisfies(&version_req) => package
-                .versions
-                .get(&Version::new(vrs.0.to_string()))
-                .map(|version| version.to_owned())
-                .ok_or(Error::VersionRangeResolveError),
-            _ => Err(Error::VersionRangeResolveError),
-        };
+    let matches = |version: &Version| {
+        is_published(version)
+            && version
+                .parse::<node_semver::Version>()
+                .map_or(false, |version| version.satisfies(&version_req))
+    };
 
-        if matched_version.is_ok() {
-            break;
+    if !lowest {
+        let latest = package
+            .dist_tags
+            .get("latest")
+            .filter(|latest| matches(latest));
+        if let Some(version) = latest.and_then(|latest| package.versions.get(latest)) {
+            return Ok(version.to_owned());
         }
     }
 
-    matched_version
+    // registries do not guarantee the order of `versions`.
+    let matching = package
+        .versions
+        .iter()
+        .filter(|(version, _)| matches(version))
+        .filter_map(|(version, manifest)| {
+            Some((version.parse::<node_semver::Version>().ok()?, manifest))
+        });
+    let matched = if lowest {
+        matching.min_by(|(a, _), (b, _)| a.cmp(b))
+    } else {
+        matching.max_by(|(a, _), (b, _)| a.cmp(b))
+    };
+
+    matched
+        .map(|(_, version)| version.to_owned())
+        .ok_or(Error::VersionRangeResolveError)
+}
+
+/// Parse `version_range` as semver, an empty range matches every version as `*` does.
+/// returns None for ranges that are not semver, such as tags.
+fn parse_range(version_range: &VersionRangeSpecifier) -> Option<node_semver::Range> {
+    let version_range = match version_range.trim() {
+        "" => "*",
+        version_range => version_range,
+    };
+
+    version_range.parse().ok()
+}
+
+/// The versions of `package`, lowest first.
+pub fn sorted_versions(package: &NpmResolvedPackage) -> Vec<Version> {
+    let mut versions: Vec<_> = package.versions.keys().cloned().collect();
+    versions.sort_by_cached_key(|version| version.parse::<node_semver::Version>().ok());
+    versions
+}
+
+/// The versions of `package` nearest to `version_range`: the highest one below it
+/// and the lowest one above it.
+pub fn closest_versions(
+    package: &NpmResolvedPackage,
+    version_range: &VersionRangeSpecifier,
+) -> Vec<Version> {
+    let Some(range) = parse_range(version_range) else {
+        return vec![];
+    };
+    let Some(lowest) = range.min_version() else {
+        return vec![];
+    };
+
+    let versions: Vec<_> = package
+        .versions
+        .keys()
+        .filter_map(|version| Some((version.parse::<node_semver::Version>().ok()?, version)))
+        .filter(|(parsed, _)| !parsed.satisfies(&range))
+        .collect();
+    let below = versions
+        .iter()
+        .filter(|(parsed, _)| *parsed < lowest)
+        .max_by(|(a, _), (b, _)| a.cmp(b));
+    let above = versions
+        .iter()
+        .filter(|(parsed, _)| *parsed > lowest)
+        .min_by(|(a, _), (b, _)| a.cmp(b));
+
+    below
+        .into_iter()
+        .chain(above)
+        .map(|(_, version)| (*version).clone())
+        .collect()
+}
+
+/// Whether `version` is in `version_range`. Ranges that are not semver, such as tags,
+/// match no version.
+pub fn satisfies(version: &Version, version_range: &VersionRangeSpecifier) -> bool {
+    match (
+        version.parse::<node_semver::Version>(),
+        parse_range(version_range),
+    ) {
+        (Ok(version), Some(range)) => version.satisfies(&range),
+        _ => false,
+    }
 }
 
 #[cfg(test)]
@@ -126,6 +238,7 @@ mod tests {
         let resolved = resolve_version_from_version_range(
             &package,
             &VersionRangeSpecifier::new(String::from("latest")),
+            VersionPreference::default(),
         );
 
         assert_eq!(
@@ -199,11 +312,12 @@ mod tests {
             "modified": "2022-06-19T02:40:54.045Z"
           }"#;
 
-        let package: NpmResolvedPackage = serde_json::from_str(&package_json).unwrap();
+        let package: NpmResolvedPackage = serde_json::from_str(package_json).unwrap();
 
         let resolved = resolve_version_from_version_range(
             &package,
             &VersionRangeSpecifier::new(String::from("^0.1.2")),
+            VersionPreference::default(),
         );
 
         assert_eq!(
@@ -215,4 +329,164 @@ mod tests {
                 .ok_or(Error::VersionRangeResolveError)
         );
     }
+
+    #[test]
+    fn resolves_with_preference() {
+        let package: NpmResolvedPackage = serde_json::from_value(serde_json::json!({
+            "name": "is-odd",
+            "dist-tags": { "latest": "1.1.0", "next": "2.0.0-rc.1", "legacy": "1.0.1" },
+            "versions": {
+                "1.0.0": { "name": "is-odd", "version": "1.0.0", "dist": { "shasum": "", "tarball": "" } },
+                "1.0.1": { "name": "is-odd", "version": "1.0.1", "dist": { "shasum": "", "tarball": "" } },
+                "1.1.0": { "name": "is-odd", "version": "1.1.0", "dist": { "shasum": "", "tarball": "" } },
+                "2.0.0-rc.1": { "name": "is-odd", "version": "2.0.0-rc.1", "dist": { "shasum": "", "tarball": "" } },
+            },
+            "modified": "2021-01-01T00:00:00.000Z",
+            "time": {
+                "1.0.0": "2020-01-01T00:00:00.000Z",
+                "1.0.1": "2020-06-01T00:00:00.000Z",
+                "1.1.0": "2021-01-01T00:00:00.000Z",
+            },
+        }))
+        .unwrap();
+        let june = "2020-06-30T00:00:00Z".parse().ok();
+
+        for (range, lowest, published_before, expected) in [
+            ("^1.0.0", false, None, Some("1.1.0")),
+            ("^1.0.0", true, None, Some("1.0.0")),
+            ("^1.0.0", false, june, Some("1.0.1")),
+            ("~1.0.1", true, june, Some("1.0.1")),
+            ("^1.1.0", false, june, None),
+            ("latest", true, None, Some("1.1.0")),
+            ("latest", false, june, Some("1.0.1")),
+            ("next", false, None, Some("2.0.0-rc.1")),
+            ("legacy", true, None, Some("1.0.1")),
+            ("canary", false, None, None),
+        ] {
+            let resolved = resolve_version_from_version_range(
+                &package,
+                &VersionRangeSpecifier::new(String::from(range)),
+                VersionPreference {
+                    lowest,
+                    published_before,
+                },
+            );
+            assert_eq!(
+                resolved
+                    .ok()
+                    .map(|version| version.version.to_string())
+                    .as_deref(),
+                expected,
+                "{range}, lowest: {lowest}, before: {published_before:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn resolves_like_npm() {
+        let dist = serde_json::json!({ "shasum": "", "tarball": "" });
+        // in no particular order, as registries do not sort them.
+        let versions: serde_json::Map<_, _> = [
+            "1.2.0",
+            "2.0.0",
+            "1.10.0",
+            "1.9.0",
+            "2.1.0-beta.1",
+            "3.0.0-rc.1",
+            "0.9.0",
+        ]
+        .into_iter()
+        .map(|version| {
+            let manifest = serde_json::json!({ "name": "a", "version": version, "dist": dist });
+            (String::from(version), manifest)
+        })
+        .collect();
+        let package: NpmResolvedPackage = serde_json::from_value(serde_json::json!({
+            "name": "a",
+            "dist-tags": { "latest": "1.9.0" },
+            "versions": versions,
+            "modified": "2021-01-01T00:00:00.000Z",
+        }))
+        .unwrap();
+
+        for (range, lowest, expected) in [
+            // `latest` is picked when it is in the range, otherwise the highest version.
+            ("^1.0.0", false, Some("1.9.0")),
+            (">=1.10.0", false, Some("2.0.0")),
+            ("<1.10.0", false, Some("1.9.0")),
+            ("~1.10.0", false, Some("1.10.0")),
+            ("^1.0.0", true, Some("1.2.0")),
+            ("<=1.10.0", true, Some("0.9.0")),
+            // every version.
+            ("*", false, Some("1.9.0")),
+            ("", false, Some("1.9.0")),
+            ("x", true, Some("0.9.0")),
+            // x-ranges.
+            ("1.x", true, Some("1.2.0")),
+            ("2.x", false, Some("2.0.0")),
+            ("1.10.x", false, Some("1.10.0")),
+            ("1.X", false, Some("1.9.0")),
+            // prereleases only match ranges that opt in.
+            (">=2.0.0", false, Some("2.0.0")),
+            ("^2.1.0-beta.0", false, Some("2.1.0-beta.1")),
+            ("3.0.0-rc.1", false, Some("3.0.0-rc.1")),
+            ("^3.0.0", false, None),
+            ("^4.0.0", false, None),
+        ] {
+            let resolved = resolve_version_from_version_range(
+                &package,
+                &VersionRangeSpecifier::new(String::from(range)),
+                VersionPreference {
+                    lowest,
+                    published_before: None,
+                },
+            );
+            assert_eq!(
+                resolved
+                    .ok()
+                    .map(|version| version.version.to_string())
+                    .as_deref(),
+                expected,
+                "{range:?}, lowest: {lowest}"
+            );
+        }
+    }
+
+    #[test]
+    fn finds_closest_versions() {
+        let dist = serde_json::json!({ "shasum": "", "tarball": "" });
+        let versions: serde_json::Map<_, _> = ["2.0.0", "1.0.0", "3.1.0", "1.2.0"]
+            .into_iter()
+            .map(|version| {
+                let manifest = serde_json::json!({ "name": "b", "version": version, "dist": dist });
+                (String::from(version), manifest)
+            })
+            .collect();
+        let package: NpmResolvedPackage = serde_json::from_value(serde_json::json!({
+            "name": "b",
+            "dist-tags": { "latest": "3.1.0" },
+            "versions": versions,
+            "modified": "2021-01-01T00:00:00.000Z",
+        }))
+        .unwrap();
+
+        let sorted: Vec<_> = sorted_versions(&package)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(sorted, ["1.0.0", "1.2.0", "2.0.0", "3.1.0"]);
+
+        for (range, expected) in [
+            ("^1.5.0", vec!["1.2.0", "2.0.0"]),
+            ("^4.0.0", vec!["3.1.0"]),
+            ("<1.0.0", vec!["1.0.0"]),
+            ("~2.5.0", vec!["2.0.0", "3.1.0"]),
+            ("latest", vec![]),
+        ] {
+            let closest =
+                closest_versions(&package, &VersionRangeSpecifier::new(String::from(range)));
+            let closest: Vec<_> = closest.iter().map(ToString::to_string).collect();
+            assert_eq!(closest, expected, "{range}");
+        }
+    }
 }
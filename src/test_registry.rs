@@ -0,0 +1,433 @@
+//! A local stand-in for the npm registry, used by tests.
+
+use std::{
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use openssl::ssl::{Ssl, SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
+use reqwest::Url;
+use rustc_hash::FxHashMap as HashMap;
+use serde_json::{json, Map, Value};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tokio_openssl::SslStream;
+
+use crate::downloader::sha512_integrity;
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub fn json(body: &serde_json::Value) -> Self {
+        Self {
+            status: 200,
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    /// A gzipped tarball of `files`, inside a `package/` folder like npm packs them.
+    pub fn tarball(files: &[(&str, &str)]) -> Self {
+        let mut archive = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive
+                .append_data(&mut header, format!("package/{path}"), content.as_bytes())
+                .unwrap();
+        }
+
+        Self {
+            status: 200,
+            body: archive.into_inner().unwrap().finish().unwrap(),
+        }
+    }
+
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            body: vec![],
+        }
+    }
+}
+
+type Handler = dyn Fn(&RecordedRequest) -> TestResponse + Send + Sync;
+
+pub struct TestRegistry {
+    pub url: Url,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    task: JoinHandle<()>,
+}
+
+/// How the stand-in registry serves https.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tls {
+    /// With the `tests/fixtures/tls` certificate of `127.0.0.1`.
+    Server,
+    /// And require a client certificate signed by the test CA.
+    ClientAuth,
+}
+
+/// A file of `tests/fixtures`.
+pub fn fixture(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(path)
+}
+
+/// The packages of `tests/fixtures/registry.json`.
+pub fn registry_fixture() -> Value {
+    serde_json::from_str(&std::fs::read_to_string(fixture("registry.json")).unwrap()).unwrap()
+}
+
+/// The `dependencies` of the package.json of a fixture project.
+pub fn fixture_dependencies(project: &str) -> HashMap<String, crate::npm::VersionRangeSpecifier> {
+    let manifest: Value = serde_json::from_str(
+        &std::fs::read_to_string(fixture(project).join("package.json")).unwrap(),
+    )
+    .unwrap();
+
+    serde_json::from_value(manifest["dependencies"].clone()).unwrap()
+}
+
+/// A file of `tests/fixtures/tls`.
+pub fn tls_fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/tls")
+        .join(name)
+}
+
+impl TestRegistry {
+    /// Serve every request with `handler` on a random local port,
+    /// over https with `tls`.
+    pub async fn start(
+        handler: impl Fn(&RecordedRequest) -> TestResponse + Send + Sync + 'static,
+        tls: Option<Tls>,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        let url = Url::parse(&format!("{scheme}://{}/", listener.local_addr().unwrap())).unwrap();
+
+        let requests = Arc::new(Mutex::new(vec![]));
+        let handler: Arc<Handler> = Arc::new(handler);
+        let acceptor = tls.map(|tls| Arc::new(tls_acceptor(tls)));
+
+        let recorded = requests.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (handler, recorded) = (handler.clone(), recorded.clone());
+                let Some(acceptor) = &acceptor else {
+                    tokio::spawn(serve(stream, handler, recorded));
+                    continue;
+                };
+
+                let ssl = Ssl::new(acceptor.context()).unwrap();
+                tokio::spawn(async move {
+                    let mut stream = SslStream::new(ssl, stream).unwrap();
+                    // failed handshakes are how clients without the right certificates fail.
+                    if Pin::new(&mut stream).accept().await.is_ok() {
+                        serve(stream, handler, recorded).await;
+                    }
+                });
+            }
+        });
+
+        Self {
+            url,
+            requests,
+            task,
+        }
+    }
+
+    /// Serve the responses of `routes` by request path, and 404 otherwise.
+    pub async fn with_routes(routes: HashMap<String, TestResponse>) -> Self {
+        Self::with_routes_and_tls(routes, None).await
+    }
+
+    pub async fn with_routes_and_tls(
+        routes: HashMap<String, TestResponse>,
+        tls: Option<Tls>,
+    ) -> Self {
+        Self::start(
+            move |request| {
+                routes
+                    .get(&request.path)
+                    .cloned()
+                    .unwrap_or_else(|| TestResponse::status(404))
+            },
+            tls,
+        )
+        .await
+    }
+
+    /// Serve the packuments and tarballs of `packages`, `{ name: { version: manifest } }`.
+    /// The other keys of a package, like `dist-tags`, are copied to its packument,
+    /// and `latest` defaults to the highest stable version.
+    pub async fn with_packages(packages: Value) -> Self {
+        Self::start(
+            move |request| {
+                let host = request.header("host").unwrap_or_default();
+                match request.path.split_once("/-/") {
+                    Some((name, file)) => tarball(&packages, &name[1..], file),
+                    None => packument(&packages, &request.path[1..].replace("%2f", "/"), host),
+                }
+                .unwrap_or_else(|| TestResponse::status(404))
+            },
+            None,
+        )
+        .await
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn is_version(key: &str) -> bool {
+    key.parse::<node_semver::Version>().is_ok()
+}
+
+fn packument(packages: &Value, name: &str, host: &str) -> Option<TestResponse> {
+    let package = packages.get(name)?.as_object()?;
+
+    let mut versions = Map::new();
+    let mut packument = Map::new();
+    for (key, value) in package {
+        if !is_version(key) {
+            packument.insert(key.clone(), value.clone());
+            continue;
+        }
+
+        let mut manifest = value.as_object().cloned().unwrap_or_default();
+        manifest.insert(String::from("name"), json!(name));
+        manifest.insert(String::from("version"), json!(key));
+        let basename = name.rsplit('/').next().unwrap_or(name);
+        if !manifest.contains_key("dist") {
+            let tarball = package_tarball(&manifest);
+            manifest.insert(
+                String::from("dist"),
+                json!({
+                    "tarball": format!("http://{host}/{name}/-/{basename}-{key}.tgz"),
+                    "shasum": "",
+                    "integrity": sha512_integrity(&tarball.body),
+                }),
+            );
+        }
+        versions.insert(key.clone(), Value::Object(manifest));
+    }
+
+    let latest = versions
+        .keys()
+        .filter_map(|version| version.parse::<node_semver::Version>().ok())
+        .filter(|version| !version.is_prerelease())
+        .max()
+        .map(|version| version.to_string());
+    let dist_tags = packument
+        .entry("dist-tags")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()?;
+    if let Some(latest) = latest {
+        dist_tags.entry("latest").or_insert(json!(latest));
+    }
+
+    packument.insert(String::from("name"), json!(name));
+    packument.insert(String::from("versions"), Value::Object(versions));
+    packument
+        .entry("modified")
+        .or_insert(json!("2023-01-01T00:00:00.000Z"));
+
+    Some(TestResponse::json(&Value::Object(packument)))
+}
+
+fn tarball(packages: &Value, name: &str, file: &str) -> Option<TestResponse> {
+    let basename = name.rsplit('/').next().unwrap_or(name);
+    let version = file
+        .strip_prefix(basename)?
+        .strip_prefix('-')?
+        .strip_suffix(".tgz")?;
+    let mut manifest = packages.get(name)?.get(version)?.as_object()?.clone();
+    manifest.insert(String::from("name"), json!(name));
+    manifest.insert(String::from("version"), json!(version));
+
+    Some(package_tarball(&manifest))
+}
+
+/// The tarball of a package with only its package.json, `manifest`.
+/// Tarballs are reproducible, so the packument can publish their integrity.
+fn package_tarball(manifest: &Map<String, Value>) -> TestResponse {
+    let mut manifest = manifest.clone();
+    manifest.remove("dist");
+
+    TestResponse::tarball(&[("package.json", &Value::Object(manifest).to_string())])
+}
+
+impl Drop for TestRegistry {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn tls_acceptor(tls: Tls) -> SslAcceptor {
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+    acceptor
+        .set_private_key_file(tls_fixture("server.key"), SslFiletype::PEM)
+        .unwrap();
+    acceptor
+        .set_certificate_chain_file(tls_fixture("server.pem"))
+        .unwrap();
+
+    if tls == Tls::ClientAuth {
+        acceptor.set_ca_file(tls_fixture("ca.pem")).unwrap();
+        acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+
+    acceptor.build()
+}
+
+/// A local stand-in for a forward proxy. It tunnels `CONNECT` requests and
+/// forwards plain http requests, recording both.
+pub struct TestProxy {
+    pub url: Url,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    task: JoinHandle<()>,
+}
+
+impl TestProxy {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+
+        let requests = Arc::new(Mutex::new(vec![]));
+        let recorded = requests.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(proxy(stream, recorded.clone()));
+            }
+        });
+
+        Self {
+            url,
+            requests,
+            task,
+        }
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for TestProxy {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn proxy(mut client: TcpStream, requests: Arc<Mutex<Vec<RecordedRequest>>>) {
+    let Some(request) = read_request(&mut client).await else {
+        return;
+    };
+    requests.lock().unwrap().push(request.clone());
+
+    let mut upstream = if request.method == "CONNECT" {
+        let Ok(upstream) = TcpStream::connect(&request.path).await else {
+            return;
+        };
+        let _ = client
+            .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .await;
+        upstream
+    } else {
+        let url = Url::parse(&request.path).unwrap();
+        let Ok(mut upstream) = TcpStream::connect(url.authority()).await else {
+            return;
+        };
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\n",
+            request.method,
+            &url[url::Position::BeforePath..]
+        );
+        for (key, value) in &request.headers {
+            head.push_str(&format!("{key}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+        let _ = upstream.write_all(head.as_bytes()).await;
+        upstream
+    };
+
+    let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+}
+
+/// Read the head of an HTTP/1.1 request.
+async fn read_request<S: AsyncRead + Unpin>(stream: &mut S) -> Option<RecordedRequest> {
+    let mut head = vec![];
+    let mut buffer = [0; 1];
+    // byte by byte, so nothing after the head is consumed.
+    while !head.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return None,
+            Ok(read) => head.extend_from_slice(&buffer[..read]),
+        }
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    Some(RecordedRequest {
+        method: request_line.next().unwrap_or_default().to_string(),
+        path: request_line.next().unwrap_or_default().to_string(),
+        headers: lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+            .collect(),
+    })
+}
+
+/// Answer a single HTTP/1.1 request and close the connection.
+pub async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    handler: Arc<Handler>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+) {
+    let Some(request) = read_request(&mut stream).await else {
+        return;
+    };
+
+    let response = handler(&request);
+    requests.lock().unwrap().push(request);
+
+    let head = format!(
+        "HTTP/1.1 {} Test\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.body.len()
+    );
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.write_all(&response.body).await;
+    let _ = stream.shutdown().await;
+}
@@ -0,0 +1,69 @@
+use tokio::task;
+
+use crate::{
+    config::Config,
+    error::{Error, Result},
+    git::GitSpec,
+    install_package::{install, updated_range},
+    local::LocalSpec,
+    npm::VersionRangeSpecifier,
+    package_manifest::{get_manifest_dependencies, update_package_manifest},
+    remote::parse_tarball_url,
+};
+
+/// Reinstall the dependencies of package.json with `packages` updated to their newest
+/// matching version, or to `latest` when `latest` is set.
+/// Every dependency is updated if `packages` is empty.
+pub async fn update_package(packages: Vec<String>, latest: bool, config: &Config) -> Result<()> {
+    let mut root = task::spawn_blocking(get_manifest_dependencies).await??;
+
+    for package in &packages {
+        if !root.dependencies.contains_key(package) && !root.dev_dependencies.contains_key(package)
+        {
+            return Err(Error::DependencyNotFound(package.clone()));
+        }
+    }
+
+    let should_update = |name: &String| packages.is_empty() || packages.contains(name);
+    let mut previous = root.dev_dependencies.clone();
+    previous.extend(root.dependencies.clone());
+
+    if latest {
+        for (name, range) in root
+            .dependencies
+            .iter_mut()
+            .chain(&mut root.dev_dependencies)
+        {
+            // git dependencies are updated to the newest commit of their ref,
+            // local and tarball url ones are left as they are.
+            let is_registry = GitSpec::parse(range).is_none()
+                && LocalSpec::parse(range).is_none()
+                && parse_tarball_url(range).is_none();
+            if should_update(name) && is_registry {
+                // aliases keep pointing to the same package.
+                *range = match range.alias() {
+                    Some((aliased, _)) => {
+                        VersionRangeSpecifier::new(format!("npm:{aliased}@latest"))
+                    }
+                    None => VersionRangeSpecifier::new(String::from("latest")),
+                };
+            }
+        }
+    }
+
+    let top_level = install(root, config).await?;
+
+    update_package_manifest(
+        top_level
+            .into_iter()
+            .filter(|(name, version)| should_update(name) && version.source.is_none())
+            .filter_map(|(name, version)| {
+                let range = updated_range(&name, &version, previous.get(&name)?);
+                Some((name, range))
+            })
+            .collect(),
+    )
+    .await?;
+
+    Ok(())
+}